@@ -0,0 +1,101 @@
+//! Compares ray throughput of `BVHNode` and `FlatBVH` built over the objects of each bundled
+//! scene file, for camera rays and one diffuse bounce. Top-level `Bvh` groups are spliced into
+//! the scene so that both structures index the same primitives; nested ones are left as is.
+//!
+//! Run with `cargo bench --bench bvh`.
+
+use raytracer::{bvh::*, hittable_list::*, prelude::*, scene::*, scene_file::*};
+
+use rand::prelude::*;
+use std::{hint::black_box, path::Path, sync::Arc, time::Instant};
+
+const RAYS: usize = 200_000;
+const MIN_SECONDS: f64 = 1.0;
+
+fn rays(scene: &Scene) -> Vec<Ray> {
+    let mut rng = SmallRng::seed_from_u64(0);
+    let camera = scene.camera.build(scene.image.aspect_ratio);
+    let world = scene.world.world();
+
+    let mut rays = Vec::with_capacity(RAYS);
+    while rays.len() < RAYS {
+        let (s, t) = rng.gen();
+        let r = camera.get_ray(&mut rng, s, t);
+        if let Some(rec) = world.hit(&mut rng, &r, 0.001, f32::INFINITY) {
+            let direction = rec.normal + random_in_unit_sphere(&mut rng).unit_vector();
+            rays.push(Ray::new(rec.p, direction, r.time()));
+        }
+        rays.push(r);
+    }
+    rays.truncate(RAYS);
+
+    rays
+}
+
+/// Returns rays per second.
+fn measure(bvh: &dyn Hittable, rays: &[Ray]) -> f64 {
+    let mut rng = SmallRng::seed_from_u64(0);
+    let start = Instant::now();
+    let mut traced = 0;
+    while traced == 0 || start.elapsed().as_secs_f64() < MIN_SECONDS {
+        for r in rays {
+            black_box(bvh.hit(&mut rng, black_box(r), 0.001, f32::INFINITY));
+        }
+        traced += rays.len();
+    }
+
+    traced as f64 / start.elapsed().as_secs_f64()
+}
+
+fn load(path: &Path) -> Scene {
+    let source = std::fs::read_to_string(path).unwrap();
+    let mut file = SceneFile::parse(&source).unwrap();
+    file.objects = file
+        .objects
+        .into_iter()
+        .flat_map(|desc| match desc {
+            ObjectDesc::Bvh(objects) => objects,
+            desc => vec![desc],
+        })
+        .collect();
+
+    file.build(path.parent().unwrap()).unwrap()
+}
+
+fn main() {
+    let mut paths: Vec<_> = std::fs::read_dir("scenes")
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    paths.sort();
+
+    println!(
+        "{:<24} {:>8} {:>14} {:>14} {:>8}",
+        "scene", "objects", "BVHNode Mr/s", "FlatBVH Mr/s", "speedup"
+    );
+    for path in &paths {
+        let scene = load(path);
+        let rays = rays(&scene);
+        let objects = &scene.world.world().objects;
+        let (time0, time1) = (scene.camera.time0, scene.camera.time1);
+
+        let mut list = HittableList::new();
+        for object in objects {
+            list.add(object.clone());
+        }
+        let tree = BVHNode::new_with_list(list, time0, time1);
+        let flat = Arc::new(FlatBVH::from(&*tree));
+
+        let tree_rate = measure(&*tree, &rays);
+        let flat_rate = measure(&*flat, &rays);
+
+        println!(
+            "{:<24} {:>8} {:>14.2} {:>14.2} {:>7.2}x",
+            path.file_name().unwrap().to_string_lossy(),
+            objects.len(),
+            tree_rate / 1e6,
+            flat_rate / 1e6,
+            flat_rate / tree_rate
+        );
+    }
+}
@@ -0,0 +1,87 @@
+//! Renders a sphere resting on a user-defined infinite ground plane.
+
+use raytracer::{camera::*, hittable_list::*, prelude::*, render::*, sphere::*, worlds::*};
+
+use std::sync::Arc;
+
+/// The plane `y = height`, facing up.
+#[derive(Debug)]
+struct Ground {
+    height: f32,
+    material: Arc<dyn Material>,
+}
+
+impl Hittable for Ground {
+    fn hit(
+        &self,
+        _rng: &mut dyn rand::RngCore,
+        r: &Ray,
+        t_min: f32,
+        t_max: f32,
+    ) -> Option<HitRecord> {
+        let t = (self.height - r.origin().y()) / r.direction().y();
+        if !(t > t_min && t < t_max) {
+            return None;
+        }
+
+        let p = r.at(t);
+        Some(HitRecord::new(
+            r,
+            Vec3::new(0.0, 1.0, 0.0),
+            p,
+            t,
+            p.x().rem_euclid(1.0),
+            p.z().rem_euclid(1.0),
+            self.material.clone(),
+        ))
+    }
+
+    fn bounding_box(&self, _t0: f32, _t1: f32) -> Option<AABB> {
+        None
+    }
+
+    fn pdf_value(&self, _rng: &mut dyn rand::RngCore, _o: Point3, _v: Vec3) -> f32 {
+        0.0
+    }
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut objects = HittableList::new();
+    objects.add(Arc::new(Ground {
+        height: 0.0,
+        material: Lambertian::new(CheckerTexture::new(
+            Color::new(0.2, 0.3, 0.1),
+            Color::new(0.9, 0.9, 0.9),
+        )),
+    }));
+    objects.add(Sphere::new(
+        Point3::new(0.0, 1.0, 0.0),
+        1.0,
+        Metal::new_rgbf(0.8, 0.6, 0.2, 0.1),
+    ));
+    let world = World::new(objects, HittableList::new());
+
+    let settings = RenderSettings {
+        image_width: 320,
+        image_height: 180,
+        samples_per_pixel: 32,
+        ..RenderSettings::default()
+    };
+    let camera = Camera::new(
+        Point3::new(6.0, 2.0, 4.0),
+        Point3::new(0.0, 0.8, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        30.0,
+        settings.image_width as f32 / settings.image_height as f32,
+        0.0,
+        10.0,
+        0.0,
+        1.0,
+    );
+
+    Renderer::new(settings)
+        .render(&world, &camera)
+        .save("custom_primitive.png")?;
+
+    Ok(())
+}
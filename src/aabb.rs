@@ -17,6 +17,15 @@ impl AABB {
     pub fn max(&self) -> Point3 {
         self.max
     }
+
+    pub fn centroid(&self) -> Point3 {
+        0.5 * (self.min + self.max)
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        let [x, y, z] = (self.max - self.min).to_array();
+        2.0 * (x * y + y * z + z * x)
+    }
 }
 
 impl std::default::Default for AABB {
@@ -30,14 +39,23 @@ impl std::default::Default for AABB {
 
 impl AABB {
     pub fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> bool {
-        let inv_d = 1.0 / r.direction();
-        let t0 = (self.min - r.origin()) * inv_d;
-        let t1 = (self.max - r.origin()) * inv_d;
+        self.hit_inv(r.origin(), 1.0 / r.direction(), t_min, t_max)
+    }
+
+    /// Same as `hit`, with the reciprocal of the ray direction computed once by the caller.
+    #[inline]
+    pub fn hit_inv(&self, origin: Point3, inv_d: Vec3, t_min: f32, t_max: f32) -> bool {
+        let t0 = (self.min - origin) * inv_d;
+        let t1 = (self.max - origin) * inv_d;
         let tt0 = t0.select_lt_0(t1, inv_d);
         let tt1 = t1.select_lt_0(t0, inv_d);
 
-        let tmin = tt0.max(Vec3::from_scalar(t_min));
-        let tmax = tt1.min(Vec3::from_scalar(t_max));
+        let [x0, y0, z0] = tt0.to_array();
+        let [x1, y1, z1] = tt1.to_array();
+
+        // The ray is inside the box between the last slab entry and the first slab exit
+        let tmin = x0.max(y0).max(z0).max(t_min);
+        let tmax = x1.min(y1).min(z1).min(t_max);
 
         tmax > tmin
     }
@@ -49,3 +67,47 @@ pub fn surrounding_box(box0: AABB, box1: AABB) -> AABB {
 
     AABB::new(small, big)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit_box() -> AABB {
+        AABB::new(point!(0.0, 0.0, 0.0), point!(1.0, 1.0, 1.0))
+    }
+
+    #[test]
+    fn hit() {
+        let r = Ray::new(point!(-1.0, 0.5, 0.5), vec3!(1.0, 0.0, 0.0), 0.0);
+        assert!(unit_box().hit(&r, 0.001, f32::INFINITY));
+        // The interval must overlap the slab crossing at t in [1, 2]
+        assert!(!unit_box().hit(&r, 0.001, 0.9));
+        assert!(!unit_box().hit(&r, 2.1, f32::INFINITY));
+
+        let inside = Ray::new(point!(0.5, 0.5, 0.5), vec3!(-1.0, 2.0, 0.5), 0.0);
+        assert!(unit_box().hit(&inside, 0.001, f32::INFINITY));
+    }
+
+    #[test]
+    fn miss() {
+        let away = Ray::new(point!(-1.0, 0.5, 0.5), vec3!(-1.0, 0.0, 0.0), 0.0);
+        assert!(!unit_box().hit(&away, 0.001, f32::INFINITY));
+
+        // Each slab is crossed, but never all three at once
+        let diagonal = Ray::new(point!(-1.0, 0.0, 0.5), vec3!(1.0, 1.0, 0.0), 0.0);
+        assert!(!unit_box().hit(&diagonal, 0.001, f32::INFINITY));
+
+        let parallel = Ray::new(point!(-1.0, 1.5, 0.5), vec3!(1.0, 0.0, 0.0), 0.0);
+        assert!(!unit_box().hit(&parallel, 0.001, f32::INFINITY));
+    }
+
+    #[test]
+    fn surrounding() {
+        let b = AABB::new(point!(-1.0, 0.5, 2.0), point!(0.5, 3.0, 4.0));
+        let s = surrounding_box(unit_box(), b);
+        assert_eq!(s.min().to_array(), [-1.0, 0.0, 0.0]);
+        assert_eq!(s.max().to_array(), [1.0, 3.0, 4.0]);
+        assert_eq!(s.centroid().to_array(), [0.0, 1.5, 2.0]);
+        assert_eq!(unit_box().surface_area(), 6.0);
+    }
+}
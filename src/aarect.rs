@@ -60,8 +60,27 @@ impl AARect {
     }
 }
 
+impl AARect {
+    fn normal(&self) -> Vec3 {
+        match self.axis {
+            Plane::Xy => vec3!(0.0, 0.0, 1.0),
+            Plane::Xz => vec3!(0.0, 1.0, 0.0),
+            Plane::Yz => vec3!(1.0, 0.0, 0.0),
+        }
+    }
+
+    /// Maps in-plane coordinates `(a, b)` and the offset `k` back to world space.
+    fn point(&self, a: f32, b: f32, k: f32) -> Point3 {
+        match self.axis {
+            Plane::Xy => point!(a, b, k),
+            Plane::Xz => point!(a, k, b),
+            Plane::Yz => point!(k, a, b),
+        }
+    }
+}
+
 impl Hittable for AARect {
-    fn hit(&self, r: &Ray, t0: f32, t1: f32) -> Option<HitRecord> {
+    fn hit(&self, _rng: &mut dyn rand::RngCore, r: &Ray, t0: f32, t1: f32) -> Option<HitRecord> {
         let tt = (Vec3::from_scalar(self.k) - r.origin()) / r.direction();
         let t = match self.axis {
             Plane::Xy => tt.z(),
@@ -86,15 +105,10 @@ impl Hittable for AARect {
         let u = (a - self.a0) / (self.a1 - self.a0);
         let v = (b - self.b0) / (self.b1 - self.b0);
         let p = r.at(t);
-        let outward_normal = match self.axis {
-            Plane::Xy => vec3!(0.0, 0.0, 1.0),
-            Plane::Xz => vec3!(0.0, 1.0, 0.0),
-            Plane::Yz => vec3!(1.0, 0.0, 0.0),
-        };
 
         Some(HitRecord::new(
             r,
-            outward_normal,
+            self.normal(),
             p,
             t,
             u,
@@ -104,30 +118,126 @@ impl Hittable for AARect {
     }
 
     fn bounding_box(&self, _: f32, _: f32) -> Option<AABB> {
+        // Pad the thin dimension so the box has some volume
         Some(AABB::new(
-            point!(self.k - 0.0001, self.a0, self.b0),
-            point!(self.k + 0.0001, self.a1, self.b1),
+            self.point(self.a0, self.b0, self.k - 0.0001),
+            self.point(self.a1, self.b1, self.k + 0.0001),
         ))
     }
 
-    fn pdf_value(&self, o: Point3, v: Vec3) -> f32 {
-        if let Some(rec) = self.hit(&Ray::new(o, v, 0.0), 0.001, f32::INFINITY) {
-            let area = (self.a1 - self.a0) * (self.b1 - self.b0);
+    fn pdf_value(&self, rng: &mut dyn rand::RngCore, o: Point3, v: Vec3) -> f32 {
+        if let Some(rec) = self.hit(rng, &Ray::new(o, v, 0.0), 0.001, f32::INFINITY) {
             let distance_squared = rec.t * rec.t * v.length_squared();
             let cos = (v.dot(rec.normal) / v.length()).abs();
 
-            distance_squared / (cos * area)
+            distance_squared / (cos * self.area())
         } else {
             0.0
         }
     }
 
     fn random(&self, rng: &mut dyn rand::RngCore, o: Vec3) -> Vec3 {
-        let random_point = point!(
+        let random_point = self.point(
             rng.gen_range(self.a0..self.a1),
+            rng.gen_range(self.b0..self.b1),
             self.k,
-            rng.gen_range(self.b0..self.b1)
         );
         random_point - o
     }
+
+    fn area(&self) -> f32 {
+        (self.a1 - self.a0) * (self.b1 - self.b0)
+    }
+
+    fn sample_surface(&self, rng: &mut dyn rand::RngCore) -> Option<HitRecord> {
+        let (u, v) = (rng.gen::<f32>(), rng.gen::<f32>());
+        let a = self.a0 + u * (self.a1 - self.a0);
+        let b = self.b0 + v * (self.b1 - self.b0);
+        Some(HitRecord {
+            p: self.point(a, b, self.k),
+            normal: self.normal(),
+            geometric_normal: self.normal(),
+            t: 0.0,
+            u,
+            v,
+            front_face: true,
+            mat_ptr: self.mat_ptr.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    fn rect(plane: Plane) -> Arc<AARect> {
+        AARect::new(
+            point!(1.0, 2.0, 0.0),
+            point!(3.0, 6.0, 0.0),
+            plane,
+            -1.0,
+            Lambertian::new_rgb(0.5, 0.5, 0.5),
+        )
+    }
+
+    /// Point at in-plane coordinates `(a, b)` and offset `k`, and the plane normal.
+    fn frame(plane: Plane, a: f32, b: f32, k: f32) -> (Point3, Vec3) {
+        match plane {
+            Plane::Xy => (point!(a, b, k), vec3!(0.0, 0.0, 1.0)),
+            Plane::Xz => (point!(a, k, b), vec3!(0.0, 1.0, 0.0)),
+            Plane::Yz => (point!(k, a, b), vec3!(1.0, 0.0, 0.0)),
+        }
+    }
+
+    #[test]
+    fn hit() {
+        let rng = &mut SmallRng::seed_from_u64(0);
+        for &plane in &[Plane::Xy, Plane::Xz, Plane::Yz] {
+            let (target, normal) = frame(plane, 1.5, 5.0, -1.0);
+            let r = Ray::new(target + 2.0 * normal, -normal, 0.0);
+            let rec = rect(plane).hit(rng, &r, 0.001, f32::INFINITY).unwrap();
+
+            assert_eq!(rec.t, 2.0, "{:?}", plane);
+            assert!((rec.p - target).length() < 1e-6, "{:?}", plane);
+            assert!(rec.front_face, "{:?}", plane);
+            assert_eq!((rec.u, rec.v), (0.25, 0.75), "{:?}", plane);
+
+            let back = Ray::new(target - 2.0 * normal, normal, 0.0);
+            let rec = rect(plane).hit(rng, &back, 0.001, f32::INFINITY).unwrap();
+            assert!(!rec.front_face, "{:?}", plane);
+            assert_eq!(rec.normal.to_array(), (-normal).to_array(), "{:?}", plane);
+
+            let (outside, _) = frame(plane, 3.5, 5.0, -1.0);
+            let r = Ray::new(outside + 2.0 * normal, -normal, 0.0);
+            assert!(rect(plane).hit(rng, &r, 0.001, f32::INFINITY).is_none());
+            let r = Ray::new(target + 2.0 * normal, -normal, 0.0);
+            assert!(rect(plane).hit(rng, &r, 0.001, 1.9).is_none());
+        }
+    }
+
+    fn contains(bbox: &AABB, p: Point3) -> bool {
+        let (min, max, p) = (bbox.min().to_array(), bbox.max().to_array(), p.to_array());
+        (0..3).all(|i| min[i] <= p[i] && p[i] <= max[i])
+    }
+
+    #[test]
+    fn bounding_box_and_sampling() {
+        let rng = &mut SmallRng::seed_from_u64(0);
+        for &plane in &[Plane::Xy, Plane::Xz, Plane::Yz] {
+            let rect = rect(plane);
+            let bbox = rect.bounding_box(0.0, 1.0).unwrap();
+            let (min, _) = frame(plane, 1.0, 2.0, -1.0);
+            let (max, _) = frame(plane, 3.0, 6.0, -1.0);
+            assert!(contains(&bbox, min) && contains(&bbox, max), "{:?}", plane);
+
+            let (o, normal) = frame(plane, 2.0, 4.0, 3.0);
+            for _ in 0..100 {
+                let p = o + rect.random(rng, o);
+                assert!(contains(&bbox, p), "{:?}", plane);
+                assert!(((p - o).dot(normal) + 4.0).abs() < 1e-5, "{:?}", plane);
+            }
+        }
+    }
 }
@@ -0,0 +1,731 @@
+use crate::prelude::*;
+
+use crate::{framebuffer::*, integrator::*, spectrum::*, worlds::*};
+
+use rand::Rng;
+
+/// Bidirectional path tracing with MIS, splatting light subpaths that reach the camera.
+#[derive(Debug)]
+pub struct Bidirectional;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum VertexKind {
+    Camera,
+    Light,
+    Surface,
+}
+
+#[derive(Clone)]
+struct Vertex {
+    kind: VertexKind,
+    p: Point3,
+    /// Where the subpath met a surface or left a light; none at the camera
+    rec: Option<HitRecord>,
+    /// Throughput of the subpath up to here, divided by the density of sampling it
+    beta: Color,
+    /// Scatters in a single direction, so nothing can be joined to it
+    delta: bool,
+    /// Density of sampling this vertex from the previous one on its subpath, per unit area
+    pdf_fwd: f32,
+    /// Density of sampling it from the next one instead, per unit area
+    pdf_rev: f32,
+}
+
+impl Vertex {
+    fn camera(p: Point3) -> Self {
+        Self {
+            kind: VertexKind::Camera,
+            p,
+            rec: None,
+            beta: rgb!(1.0, 1.0, 1.0),
+            delta: false,
+            pdf_fwd: 1.0,
+            pdf_rev: 0.0,
+        }
+    }
+
+    fn light(rec: HitRecord, pdf_pos: f32) -> Self {
+        Self {
+            kind: VertexKind::Light,
+            p: rec.p,
+            rec: Some(rec),
+            beta: Color::from_scalar(1.0 / pdf_pos),
+            delta: false,
+            pdf_fwd: pdf_pos,
+            pdf_rev: 0.0,
+        }
+    }
+
+    fn rec(&self) -> &HitRecord {
+        self.rec.as_ref().unwrap()
+    }
+
+    fn connectible(&self) -> bool {
+        !self.delta
+    }
+
+    /// Converts a density per solid angle from `self` to `next` to one per area at `next`.
+    fn to_area(&self, pdf: f32, next: &Vertex) -> f32 {
+        let d = next.p - self.p;
+        let distance_squared = d.length_squared();
+        match &next.rec {
+            Some(rec) => {
+                pdf * (rec.geometric_normal.dot(d).abs() / distance_squared.sqrt())
+                    / distance_squared
+            }
+            None => pdf / distance_squared,
+        }
+    }
+
+    /// Shading normal correction for light subpaths scattering from `from` to `to` (Veach 5.3).
+    fn shading_correction(&self, from: Vec3, to: Vec3) -> f32 {
+        match &self.rec {
+            Some(rec) if self.kind == VertexKind::Surface => {
+                let (from, to) = (from.unit_vector(), to.unit_vector());
+                let denom = rec.geometric_normal.dot(from).abs() * rec.normal.dot(to).abs();
+                if denom > 0.0 {
+                    rec.normal.dot(from).abs() * rec.geometric_normal.dot(to).abs() / denom
+                } else {
+                    0.0
+                }
+            }
+            _ => 1.0,
+        }
+    }
+
+    /// `|cos|` between the geometric normal and `direction`.
+    fn cos(&self, direction: Vec3) -> f32 {
+        match &self.rec {
+            Some(rec) => rec.geometric_normal.dot(direction.unit_vector()).abs(),
+            None => 1.0,
+        }
+    }
+}
+
+/// The ray that reaches `rec` from direction `from`, and the record it would have gotten.
+fn arriving(rec: &HitRecord, from: Vec3, time: f32, wavelength: Option<f32>) -> (Ray, HitRecord) {
+    let from = from.unit_vector();
+    let r = Ray::new(rec.p + from, -from, time).with_wavelength(wavelength);
+    (r, rec.facing(from))
+}
+
+/// Things every part of a sample needs.
+struct Tracer<'a, 'b> {
+    ctx: &'a SampleContext<'b>,
+    time: f32,
+    wavelength: Option<f32>,
+    /// Area of the image at unit distance from the lens
+    image_area: f32,
+}
+
+impl Tracer<'_, '_> {
+    /// BSDF at `v` between `from` and `to`, or a light's emitted radiance towards `to`.
+    fn f(&self, rng: &mut dyn rand::RngCore, v: &Vertex, from: Vec3, to: Vec3) -> Color {
+        let rec = v.rec();
+        match v.kind {
+            VertexKind::Light => emitted_towards(rec, to),
+            VertexKind::Surface => {
+                let (r_in, rec) = arriving(rec, from, self.time, self.wavelength);
+                let scattered = Ray::new(rec.p, to, self.time);
+                match rec.mat_ptr.scatter(rng, &r_in, &rec) {
+                    // Divided by the cosine that `cos` multiplies back, so the connection
+                    // gets the cosine of the shading normal in `scattering`
+                    Some(srec) if srec.specular_ray.is_none() => {
+                        rec.mat_ptr.scattering(&r_in, &rec, &srec, &scattered) / v.cos(to).max(1e-8)
+                    }
+                    _ => Color::black(),
+                }
+            }
+            VertexKind::Camera => unreachable!(),
+        }
+    }
+
+    /// Density of sampling direction `to` at `v` after arriving from `from`, per solid angle.
+    fn pdf(&self, rng: &mut dyn rand::RngCore, v: &Vertex, from: Vec3, to: Vec3) -> f32 {
+        match v.kind {
+            VertexKind::Camera => self.camera_pdf(v.p, to),
+            VertexKind::Light => emission_pdf(v.rec(), to),
+            VertexKind::Surface => {
+                let (r_in, rec) = arriving(v.rec(), from, self.time, self.wavelength);
+                match rec.mat_ptr.scatter(rng, &r_in, &rec) {
+                    Some(srec) => match srec.pdf_ptr {
+                        Some(pdf) if srec.specular_ray.is_none() => pdf.value(rng, to),
+                        _ => 0.0,
+                    },
+                    None => 0.0,
+                }
+            }
+        }
+    }
+
+    /// Density of the camera shooting a ray from `lens` in `direction`, per solid angle.
+    fn camera_pdf(&self, lens: Point3, direction: Vec3) -> f32 {
+        let camera = self.ctx.camera;
+        let direction = direction.unit_vector();
+        let cos = direction.dot(camera.forward());
+        match camera.project(lens, lens + direction) {
+            Some(st) if self.raster(st).is_some() => 1.0 / (self.image_area * cos * cos * cos),
+            _ => 0.0,
+        }
+    }
+
+    /// The pixel, from the top left, that `get_ray` coordinates `(s, t)` fall in.
+    fn raster(&self, (s, t): (f32, f32)) -> Option<(u32, u32)> {
+        let settings = self.ctx.settings;
+        let (width, height) = (settings.image_width, settings.image_height);
+        let i = (s * (width - 1) as f32).floor();
+        let j = (t * (height - 1) as f32).floor();
+        if i >= 0.0 && i < width as f32 && j >= 0.0 && j < height as f32 {
+            Some((i as u32, height - 1 - j as u32))
+        } else {
+            None
+        }
+    }
+
+    /// Density of light subpaths starting at the light `v` reached from `prev`, per unit area.
+    fn light_origin_pdf(&self, rng: &mut dyn rand::RngCore, prev: &Vertex, v: &Vertex) -> f32 {
+        let lights = &self.ctx.world.lights().objects;
+        let d = v.p - prev.p;
+        let distance = d.length();
+        let r = Ray::new(prev.p, d / distance, self.time);
+        let eps = 1e-3 * distance.max(1.0);
+
+        let select = 1.0 / lights.len() as f32;
+        lights
+            .iter()
+            .filter(|light| light.area() > 0.0)
+            .filter(|light| light.hit(rng, &r, distance - eps, distance + eps).is_some())
+            .map(|light| select / light.area())
+            .sum()
+    }
+
+    /// Fraction of light left after going straight between `a` and `b`.
+    fn transmittance(&self, a: &Vertex, b: &Vertex) -> Color {
+        let towards = |v: &Vertex, other: &Vertex| match v.kind {
+            VertexKind::Surface => {
+                let d = other.p - v.p;
+                v.rec()
+                    .mat_ptr
+                    .transmittance(&v.rec().facing(d), d.length())
+            }
+            _ => rgb!(1.0, 1.0, 1.0),
+        };
+        // When both ends are on the medium, they agree
+        towards(a, b).min(towards(b, a))
+    }
+
+    fn visible(&self, rng: &mut dyn rand::RngCore, a: Point3, b: Point3) -> bool {
+        let d = b - a;
+        let distance = d.length();
+        let r = Ray::new(a, d / distance, self.time);
+        self.ctx
+            .world
+            .world()
+            .hit(rng, &r, 0.001, distance - 0.001)
+            .is_none()
+    }
+
+    /// Extends `path` along `r` up to `max_len` vertices, returning the background it escapes to.
+    #[allow(clippy::too_many_arguments)]
+    fn random_walk(
+        &self,
+        rng: &mut dyn rand::RngCore,
+        mut r: Ray,
+        mut beta: Color,
+        mut pdf_dir: f32,
+        max_len: usize,
+        background: Color,
+        path: &mut Vec<Vertex>,
+    ) -> Color {
+        let settings = self.ctx.settings;
+        let from_light = path[0].kind == VertexKind::Light;
+        while path.len() < max_len {
+            let rec = match self.ctx.world.world().hit(rng, &r, 0.001, f32::INFINITY) {
+                Some(rec) => rec,
+                None => return beta * background,
+            };
+            beta = beta
+                * rec
+                    .mat_ptr
+                    .transmittance(&rec, rec.t * r.direction().length());
+
+            let prev = path.len() - 1;
+            let mut vertex = Vertex {
+                kind: VertexKind::Surface,
+                p: rec.p,
+                rec: Some(rec),
+                beta,
+                delta: false,
+                pdf_fwd: 0.0,
+                pdf_rev: 0.0,
+            };
+            vertex.pdf_fwd = path[prev].to_area(pdf_dir, &vertex);
+
+            let rec = vertex.rec();
+            let srec = match rec.mat_ptr.scatter(rng, &r, rec) {
+                Some(srec) => srec,
+                None => {
+                    path.push(vertex);
+                    break;
+                }
+            };
+
+            if let Some(specular) = srec.specular_ray {
+                beta = beta * srec.attenuation;
+                pdf_dir = 0.0;
+                vertex.delta = true;
+                r = Ray::new(
+                    specular.origin(),
+                    specular.direction().unit_vector(),
+                    specular.time(),
+                )
+                .with_wavelength(specular.wavelength());
+            } else {
+                let pdf = srec.pdf_ptr.as_ref().unwrap();
+                let scattered = Ray::new(rec.p, pdf.generate(rng).unit_vector(), r.time())
+                    .with_wavelength(r.wavelength());
+                pdf_dir = pdf.value(rng, scattered.direction());
+                let fcos = rec.mat_ptr.scattering(&r, rec, &srec, &scattered);
+                if pdf_dir <= 0.0 || fcos == Color::black() {
+                    path.push(vertex);
+                    break;
+                }
+
+                beta = beta * fcos / pdf_dir;
+                if from_light {
+                    beta *= vertex.shading_correction(-r.direction(), scattered.direction());
+                }
+                let pdf_rev = self.pdf(rng, &vertex, scattered.direction(), -r.direction());
+                path[prev].pdf_rev = vertex.to_area(pdf_rev, &path[prev]);
+                r = scattered;
+            }
+            path.push(vertex);
+
+            if path.len() > settings.roulette_depth as usize {
+                let [r, g, b] = beta.to_array();
+                let survival = r.max(g).max(b).min(0.95);
+                if rng.gen::<f32>() >= survival {
+                    break;
+                }
+                beta /= survival;
+            }
+        }
+
+        Color::black()
+    }
+
+    /// Weighted radiance of joining `s` light subpath vertices to `t` camera subpath vertices.
+    fn connect(
+        &self,
+        rng: &mut dyn rand::RngCore,
+        light: &[Vertex],
+        camera: &[Vertex],
+        s: usize,
+        t: usize,
+        splats: &mut Vec<Splat>,
+    ) -> Color {
+        if t == 1 {
+            // Trace the light subpath into the camera
+            let qs = match light.get(s - 1) {
+                Some(qs) => qs,
+                None => return Color::black(),
+            };
+            if !qs.connectible() {
+                return Color::black();
+            }
+            let cam = self.ctx.camera;
+            let lens = cam.lens_point(random_in_unit_disk(rng));
+            let (x, y) = match cam.project(lens, qs.p).and_then(|st| self.raster(st)) {
+                Some(pixel) => pixel,
+                None => return Color::black(),
+            };
+
+            let d = qs.p - lens;
+            let distance_squared = d.length_squared();
+            let cos_lens = d.unit_vector().dot(cam.forward());
+            let from = if s > 1 { light[s - 2].p - qs.p } else { -d };
+            let sampled = Vertex::camera(lens);
+            let l =
+                qs.beta * self.f(rng, qs, from, -d) * qs.shading_correction(from, -d) * qs.cos(d)
+                    / (self.image_area * cos_lens.powi(3) * distance_squared)
+                    * self.transmittance(qs, &sampled);
+            if l == Color::black() || !self.visible(rng, lens, qs.p) {
+                return Color::black();
+            }
+
+            let weight = self.mis_weight(rng, light, camera, s, t, None, Some(&sampled));
+            splats.push(Splat {
+                x,
+                y,
+                color: l * weight,
+            });
+            return Color::black();
+        }
+
+        let pt = &camera[t - 1];
+        let pt_from = camera[t - 2].p - pt.p;
+        if s == 0 {
+            // The camera subpath found a light on its own
+            let rec = pt.rec();
+            let l = pt.beta * rec.mat_ptr.emitted(rec);
+            if l == Color::black() {
+                return l;
+            }
+            return l * self.mis_weight(rng, light, camera, s, t, None, None);
+        }
+
+        if !pt.connectible() {
+            return Color::black();
+        }
+        let sampled = if s == 1 {
+            match self.ctx.world.sample_light_point(rng) {
+                Some((rec, pdf)) => Some(Vertex::light(rec, pdf)),
+                None => return Color::black(),
+            }
+        } else {
+            None
+        };
+        let qs = sampled.as_ref().unwrap_or_else(|| &light[s - 1]);
+        if !qs.connectible() {
+            return Color::black();
+        }
+
+        let d = qs.p - pt.p;
+        let qs_from = if s > 1 { light[s - 2].p - qs.p } else { -d };
+        let g = pt.cos(d) * qs.cos(d) / d.length_squared();
+        let l = qs.beta
+            * self.f(rng, qs, qs_from, -d)
+            * qs.shading_correction(qs_from, -d)
+            * self.f(rng, pt, pt_from, d)
+            * pt.beta
+            * g
+            * self.transmittance(pt, qs);
+        if l == Color::black() || !self.visible(rng, pt.p, qs.p) {
+            return Color::black();
+        }
+
+        l * self.mis_weight(rng, light, camera, s, t, sampled.as_ref(), None)
+    }
+
+    /// MIS weight of the path with `s` light and `t` camera vertices.
+    #[allow(clippy::too_many_arguments)]
+    fn mis_weight(
+        &self,
+        rng: &mut dyn rand::RngCore,
+        light: &[Vertex],
+        camera: &[Vertex],
+        s: usize,
+        t: usize,
+        light_sample: Option<&Vertex>,
+        camera_sample: Option<&Vertex>,
+    ) -> f32 {
+        let pt = camera_sample.unwrap_or_else(|| &camera[t - 1]);
+        let qs = if s > 0 {
+            Some(light_sample.unwrap_or_else(|| &light[s - 1]))
+        } else {
+            None
+        };
+
+        // Densities of each vertex from either end, with those next to the join recomputed
+        let pdfs = |v: &Vertex| (v.pdf_fwd, v.pdf_rev, v.delta);
+        let mut cam: Vec<(f32, f32, bool)> = camera[..t - 1].iter().map(pdfs).collect();
+        cam.push(pdfs(pt));
+        let mut lig: Vec<(f32, f32, bool)> =
+            light[..s.saturating_sub(1)].iter().map(pdfs).collect();
+        lig.extend(qs.map(pdfs));
+
+        match qs {
+            Some(qs) => {
+                let pt_from = if t > 1 {
+                    camera[t - 2].p - pt.p
+                } else {
+                    pt.p - qs.p
+                };
+                let qs_from = if s > 1 {
+                    light[s - 2].p - qs.p
+                } else {
+                    qs.p - pt.p
+                };
+
+                if t > 1 {
+                    cam[t - 1].1 = qs.to_area(self.pdf(rng, qs, qs_from, pt.p - qs.p), pt);
+                    cam[t - 2].1 =
+                        pt.to_area(self.pdf(rng, pt, qs.p - pt.p, pt_from), &camera[t - 2]);
+                }
+                lig[s - 1].1 = pt.to_area(self.pdf(rng, pt, pt_from, qs.p - pt.p), qs);
+                if s > 1 {
+                    lig[s - 2].1 =
+                        qs.to_area(self.pdf(rng, qs, pt.p - qs.p, qs_from), &light[s - 2]);
+                }
+            }
+            None => {
+                let prev = &camera[t - 2];
+                let origin = self.light_origin_pdf(rng, prev, pt);
+                if origin <= 0.0 {
+                    // No light subpath can start here
+                    return 1.0;
+                }
+                cam[t - 1].1 = origin;
+                let emission = Vertex {
+                    kind: VertexKind::Light,
+                    ..pt.clone()
+                };
+                cam[t - 2].1 = pt.to_area(
+                    self.pdf(rng, &emission, Vec3::from_scalar(0.0), prev.p - pt.p),
+                    prev,
+                );
+            }
+        }
+
+        let remap = |pdf: f32| if pdf != 0.0 { pdf } else { 1.0 };
+        let mut sum = 0.0;
+
+        let mut ri = 1.0;
+        for i in (1..t).rev() {
+            ri *= (remap(cam[i].1) / remap(cam[i].0)).powi(2);
+            if !cam[i].2 && !cam[i - 1].2 {
+                sum += ri;
+            }
+        }
+
+        let mut ri = 1.0;
+        for i in (0..s).rev() {
+            ri *= (remap(lig[i].1) / remap(lig[i].0)).powi(2);
+            let prev_delta = i > 0 && lig[i - 1].2;
+            if !lig[i].2 && !prev_delta {
+                sum += ri;
+            }
+        }
+
+        1.0 / (1.0 + sum)
+    }
+}
+
+impl Integrator for Bidirectional {
+    fn radiance(&self, rng: &mut dyn rand::RngCore, r: &Ray, ctx: &mut SampleContext) -> Color {
+        let settings = ctx.settings;
+        let max_depth = settings.max_depth as usize;
+        let (width, height) = (settings.image_width as f32, settings.image_height as f32);
+        let tracer = Tracer {
+            ctx,
+            time: r.time(),
+            wavelength: r.wavelength(),
+            // `get_ray` covers one pixel more than `[0, 1]` in each direction
+            image_area: ctx.camera.image_area()
+                * (width / (width - 1.0))
+                * (height / (height - 1.0)),
+        };
+
+        let r = Ray::new(r.origin(), r.direction().unit_vector(), r.time())
+            .with_wavelength(r.wavelength());
+        let mut camera = vec![Vertex::camera(r.origin())];
+        let mut radiance = tracer.random_walk(
+            rng,
+            r,
+            rgb!(1.0, 1.0, 1.0),
+            tracer.camera_pdf(r.origin(), r.direction()),
+            max_depth + 1,
+            settings.background,
+            &mut camera,
+        );
+
+        let mut light = Vec::new();
+        if let Some(emission) = ctx.world.sample_emission(rng) {
+            let Emission {
+                rec,
+                pdf_pos,
+                direction,
+                pdf_dir,
+                radiance: le,
+            } = emission;
+            let beta = le * rec.geometric_normal.dot(direction).abs() / (pdf_pos * pdf_dir);
+            let origin = rec.p;
+            light.push(Vertex::light(rec, pdf_pos));
+            tracer.random_walk(
+                rng,
+                Ray::new(origin, direction, r.time()).with_wavelength(r.wavelength()),
+                beta,
+                pdf_dir,
+                max_depth,
+                Color::black(),
+                &mut light,
+            );
+        }
+
+        let mut splats = Vec::new();
+        for t in 1..=camera.len() {
+            // The light is sampled afresh for `s == 1`, so it needs no light subpath
+            for s in 0..=light.len().max(1) {
+                if s + t < 2 || s + t - 1 > max_depth {
+                    continue;
+                }
+                radiance += tracer.connect(rng, &light, &camera, s, t, &mut splats);
+            }
+        }
+        // Splats go straight to the image, so they are converted here
+        let weight = ray_weight(&r);
+        ctx.splats.extend(splats.into_iter().map(|splat| Splat {
+            color: splat.color * weight,
+            ..splat
+        }));
+
+        radiance
+    }
+
+    fn wavelength(&self, u: f32, _ctx: &SampleContext) -> Option<f32> {
+        Some(sample_wavelength(u))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{color::*, hittable_list::*, render::*, scene::*, sphere::*, triangle::*};
+    use std::sync::Arc;
+
+    #[test]
+    fn matches_path_tracer() {
+        // A diffuse floor and a glass ball under a sphere light, seen through a thin lens
+        let mut world = HittableList::new();
+        let mut lights = HittableList::new();
+        world.add(Sphere::new(
+            point!(0.0, -1000.0, 0.0),
+            1000.0,
+            Lambertian::new_rgb(0.5, 0.5, 0.5),
+        ));
+        world.add(Sphere::new(
+            point!(0.5, 0.5, 0.0),
+            0.5,
+            Dielectric::new(1.5),
+        ));
+        let light: Arc<dyn Hittable> =
+            Sphere::new(point!(0.0, 3.0, 0.0), 1.0, DiffuseLight::white(4.0));
+        world.add(light.clone());
+        lights.add(light);
+        let world = World::new(world, lights);
+
+        let camera = CameraSettings {
+            look_from: point!(0.0, 4.0, 6.0),
+            look_at: point!(0.0, 0.0, 0.0),
+            vfov: 40.0,
+            aperture: 0.2,
+            focus_dist: 7.0,
+            ..CameraSettings::default()
+        }
+        .build(1.0);
+
+        let mean = |integrator, spectral| {
+            let fb = Renderer::new(RenderSettings {
+                image_width: 16,
+                image_height: 16,
+                samples_per_pixel: 256,
+                background: Color::black(),
+                integrator,
+                seed: 5,
+                spectral,
+                ..RenderSettings::default()
+            })
+            .render(&world, &camera);
+            let pixels = fb.pixels();
+            pixels.iter().map(|&c| luminance(c)).sum::<f32>() / pixels.len() as f32
+        };
+
+        let path = mean(IntegratorDesc::Path, false);
+        // Spectral renders splat light subpaths too
+        for &spectral in &[false, true] {
+            let bidirectional = mean(IntegratorDesc::Bidirectional, spectral);
+            assert!(
+                (bidirectional - path).abs() < 0.03 * path,
+                "path {} bidirectional {} (spectral: {})",
+                path,
+                bidirectional,
+                spectral
+            );
+        }
+    }
+
+    #[test]
+    fn smooth_shaded_meshes() {
+        // Squares with normals leaning away from their faces: a large light low over a floor,
+        // where densities of area go by the faces, and a floor that light subpaths must shade
+        // like camera subpaths do
+        let square = |y: f32, size: f32, normals: Vec<Vec3>, mat_ptr| -> Arc<dyn Hittable> {
+            let h = size / 2.0;
+            let positions = vec![
+                point!(-h, y, -h),
+                point!(h, y, -h),
+                point!(h, y, h),
+                point!(-h, y, h),
+            ];
+            // Facing down for the light, up for the floor
+            let indices = if y > 0.0 {
+                vec![[0, 1, 2], [0, 2, 3]]
+            } else {
+                vec![[0, 2, 1], [0, 3, 2]]
+            };
+            TriangleMesh::new(MeshData::new(positions, normals, vec![], indices, mat_ptr))
+        };
+
+        for &(camera_height, light_height, light_size, floor_lean, light_lean) in
+            &[(2.0, 0.5, 4.0, 0.0, 3.0), (6.0, 1.0, 3.0, 0.75, 1.0)]
+        {
+            let mut world = HittableList::new();
+            let mut lights = HittableList::new();
+            let lean = |x: f32, z: f32| Vec3::new(x * floor_lean, 1.0, z * floor_lean);
+            world.add(square(
+                0.0,
+                4.0,
+                vec![
+                    lean(1.0, 0.0),
+                    lean(0.0, 1.0),
+                    lean(-1.0, 0.0),
+                    lean(0.0, -1.0),
+                ],
+                Lambertian::new_rgb(0.5, 0.5, 0.5),
+            ));
+            let lean = |x: f32| Vec3::new(x * light_lean, -1.0, 0.0);
+            let light = square(
+                light_height,
+                light_size,
+                vec![lean(1.0), lean(1.0), lean(-1.0), lean(-1.0)],
+                DiffuseLight::white(4.0),
+            );
+            world.add(light.clone());
+            lights.add(light);
+            let world = World::new(world, lights);
+
+            let camera = CameraSettings {
+                look_from: Point3::new(0.0, camera_height, 4.0),
+                look_at: point!(0.0, 0.0, 0.0),
+                vfov: 60.0,
+                ..CameraSettings::default()
+            }
+            .build(1.0);
+
+            let mean = |integrator| {
+                let fb = Renderer::new(RenderSettings {
+                    image_width: 16,
+                    image_height: 16,
+                    samples_per_pixel: 256,
+                    background: Color::black(),
+                    integrator,
+                    seed: 2,
+                    ..RenderSettings::default()
+                })
+                .render(&world, &camera);
+                let pixels = fb.pixels();
+                pixels.iter().map(|&c| luminance(c)).sum::<f32>() / pixels.len() as f32
+            };
+
+            let path = mean(IntegratorDesc::Path);
+            let bidirectional = mean(IntegratorDesc::Bidirectional);
+            assert!(
+                (bidirectional - path).abs() < 0.05 * path,
+                "path {} bidirectional {} (camera at {})",
+                path,
+                bidirectional,
+                camera_height
+            );
+        }
+    }
+}
@@ -1,94 +1,345 @@
-use rand::Rng;
-
 use crate::prelude::*;
 
 use crate::hittable_list::*;
 
-use std::sync::Arc;
+use std::{cell::Cell, fmt::Display, sync::Arc};
+
+/// Relative cost of visiting a node compared to intersecting one primitive, used by the
+/// surface area heuristic.
+const TRAVERSAL_COST: f32 = 1.0;
+const INTERSECTION_COST: f32 = 1.0;
+
+thread_local! {
+    static VISITS: Cell<u32> = const { Cell::new(0) };
+}
+
+/// Runs `f` and counts the BVH nodes it visits and the objects it tests in BVH leaves on this
+/// thread, as a measure of how expensive its rays were to trace.
+pub fn count_visits<T>(f: impl FnOnce() -> T) -> (T, u32) {
+    let before = VISITS.with(Cell::get);
+    let result = f();
+    (result, VISITS.with(Cell::get).wrapping_sub(before))
+}
+
+#[inline]
+fn visit(n: u32) {
+    VISITS.with(|visits| visits.set(visits.get().wrapping_add(n)));
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct BVHSettings {
+    /// Nodes with at most this many objects become leaves when splitting does not pay off.
+    pub max_leaf_size: usize,
+    /// Number of centroid buckets tried per axis when looking for a split.
+    pub bins: usize,
+}
+
+impl Default for BVHSettings {
+    fn default() -> Self {
+        Self {
+            max_leaf_size: 4,
+            bins: 16,
+        }
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct BVHStats {
+    /// Inner nodes and leaves.
+    pub nodes: usize,
+    pub leaves: usize,
+    /// Number of nodes on the longest path from the root to a leaf.
+    pub depth: usize,
+    /// Expected cost of tracing a ray that hits the root box, under the surface area heuristic.
+    pub sah_cost: f32,
+}
+
+impl Display for BVHStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} nodes ({} leaves), depth {}, SAH cost {:.2}",
+            self.nodes, self.leaves, self.depth, self.sah_cost
+        )
+    }
+}
+
+#[derive(Debug)]
+pub(crate) enum BVHContents {
+    Leaf(Vec<Arc<dyn Hittable>>),
+    Node(Box<BVHNode>, Box<BVHNode>),
+}
 
 #[derive(Debug)]
 pub struct BVHNode {
-    left: Arc<dyn Hittable>,
-    right: Arc<dyn Hittable>,
+    pub(crate) contents: BVHContents,
+    pub(crate) bbox: AABB,
+}
+
+/// An object with its bounds over the shutter interval, cached while building.
+struct Primitive {
+    object: Arc<dyn Hittable>,
     bbox: AABB,
+    centroid: [f32; 3],
+}
+
+/// A candidate split: primitives whose centroid falls in a bin below `bin` along `axis` go
+/// left.
+#[derive(Copy, Clone)]
+struct Split {
+    cost: f32,
+    axis: usize,
+    min: f32,
+    scale: f32,
+    bin: usize,
+}
+
+#[derive(Copy, Clone)]
+struct Bin {
+    count: usize,
+    bbox: Option<AABB>,
 }
 
 impl BVHNode {
-    pub fn new(
-        objects: &mut Vec<Arc<dyn Hittable>>,
-        start: usize,
-        end: usize,
+    pub fn new_with_list(list: HittableList, time0: f32, time1: f32) -> Arc<Self> {
+        Self::with_settings(list, time0, time1, BVHSettings::default())
+    }
+
+    /// Builds a tree with a binned surface area heuristic. The result only depends on the
+    /// objects and their order.
+    pub fn with_settings(
+        list: HittableList,
         time0: f32,
         time1: f32,
+        settings: BVHSettings,
     ) -> Arc<Self> {
-        let axis = rand::thread_rng().gen_range(0..3);
-        let object_span = end - start;
-        let left: Arc<dyn Hittable>;
-        let right: Arc<dyn Hittable>;
-        let bbox;
-
-        match object_span {
-            0 => panic!("No Hittable provided"),
-            1 => {
-                left = objects[start].clone();
-                right = objects[start].clone();
-            }
-            2 => match Self::compare(axis, &objects[start], &objects[start + 1]) {
-                std::cmp::Ordering::Less => {
-                    left = objects[start].clone();
-                    right = objects[start + 1].clone();
+        assert!(!list.objects.is_empty(), "No Hittable provided");
+        assert!(settings.max_leaf_size > 0 && settings.bins > 1);
+
+        let mut primitives: Vec<_> = list
+            .objects
+            .into_iter()
+            .map(|object| {
+                let bbox = object
+                    .bounding_box(time0, time1)
+                    .expect("No bounding box in BVHNode constructor");
+                Primitive {
+                    object,
+                    bbox,
+                    centroid: bbox.centroid().to_array(),
                 }
-                _ => {
-                    left = objects[start + 1].clone();
-                    right = objects[start].clone();
+            })
+            .collect();
+
+        Arc::new(Self::build(&mut primitives, &settings))
+    }
+
+    fn build(primitives: &mut [Primitive], settings: &BVHSettings) -> Self {
+        let bbox = primitives
+            .iter()
+            .map(|p| p.bbox)
+            .reduce(surrounding_box)
+            .unwrap();
+        let n = primitives.len();
+
+        if n == 1 {
+            return Self::leaf(primitives, bbox);
+        }
+
+        let leaf_cost = INTERSECTION_COST * n as f32;
+        let mid = match Self::find_split(primitives, bbox, settings) {
+            Some(split) => {
+                if n <= settings.max_leaf_size && leaf_cost <= split.cost {
+                    return Self::leaf(primitives, bbox);
                 }
-            },
-            _ => {
-                objects[start..end].sort_unstable_by(|x, y| Self::compare(axis, x, y));
-                let mid = start + object_span / 2;
-                left = BVHNode::new(objects, start, mid, time0, time1);
-                right = BVHNode::new(objects, mid, end, time0, time1);
+                partition(primitives, |p| {
+                    bin_index(
+                        p.centroid[split.axis],
+                        split.min,
+                        split.scale,
+                        settings.bins,
+                    ) < split.bin
+                })
             }
+            // All centroids coincide, so no split separates anything
+            None if n <= settings.max_leaf_size => return Self::leaf(primitives, bbox),
+            None => n / 2,
+        };
+
+        let (left, right) = primitives.split_at_mut(mid);
+        Self {
+            contents: BVHContents::Node(
+                Box::new(Self::build(left, settings)),
+                Box::new(Self::build(right, settings)),
+            ),
+            bbox,
         }
+    }
+
+    fn leaf(primitives: &[Primitive], bbox: AABB) -> Self {
+        Self {
+            contents: BVHContents::Leaf(primitives.iter().map(|p| p.object.clone()).collect()),
+            bbox,
+        }
+    }
+
+    fn find_split(primitives: &[Primitive], bbox: AABB, settings: &BVHSettings) -> Option<Split> {
+        let centroid_min = primitives
+            .iter()
+            .fold([f32::INFINITY; 3], |acc, p| min3(acc, p.centroid));
+        let centroid_max = primitives
+            .iter()
+            .fold([f32::NEG_INFINITY; 3], |acc, p| max3(acc, p.centroid));
+        let parent_area = bbox.surface_area().max(f32::MIN_POSITIVE);
+
+        let mut best: Option<Split> = None;
+        for axis in 0..3 {
+            let extent = centroid_max[axis] - centroid_min[axis];
+            if extent <= 0.0 {
+                continue;
+            }
+            let min = centroid_min[axis];
+            let scale = settings.bins as f32 / extent;
+
+            let mut bins = vec![
+                Bin {
+                    count: 0,
+                    bbox: None
+                };
+                settings.bins
+            ];
+            for p in primitives {
+                let bin = &mut bins[bin_index(p.centroid[axis], min, scale, settings.bins)];
+                bin.count += 1;
+                bin.bbox = Some(bin.bbox.map_or(p.bbox, |b| surrounding_box(b, p.bbox)));
+            }
+
+            // Cost of everything right of each split, swept from the far end
+            let mut right_costs = vec![0.0; settings.bins];
+            let mut count = 0;
+            let mut right_box: Option<AABB> = None;
+            for split in (1..settings.bins).rev() {
+                count += bins[split].count;
+                right_box = merge(right_box, bins[split].bbox);
+                right_costs[split] = right_box.map_or(0.0, |b| b.surface_area()) * count as f32;
+            }
 
-        let box_left = left.bounding_box(time0, time1);
-        let box_right = right.bounding_box(time0, time1);
-        box_left
-            .and(box_right)
-            .expect("No bounding box in BVHNode constructor");
+            let mut count = 0;
+            let mut left_box: Option<AABB> = None;
+            for split in 1..settings.bins {
+                count += bins[split - 1].count;
+                left_box = merge(left_box, bins[split - 1].bbox);
+                if count == 0 || count == primitives.len() {
+                    continue;
+                }
 
-        bbox = surrounding_box(box_left.unwrap(), box_right.unwrap());
+                let left_cost = left_box.map_or(0.0, |b| b.surface_area()) * count as f32;
+                let cost = TRAVERSAL_COST
+                    + INTERSECTION_COST * (left_cost + right_costs[split]) / parent_area;
+                if !matches!(best, Some(best) if best.cost <= cost) {
+                    best = Some(Split {
+                        cost,
+                        axis,
+                        min,
+                        scale,
+                        bin: split,
+                    });
+                }
+            }
+        }
 
-        Arc::new(Self { left, right, bbox })
+        best
     }
 
-    pub fn new_with_list(mut list: HittableList, time0: f32, time1: f32) -> Arc<Self> {
-        let length = list.objects.len();
-        Self::new(&mut list.objects, 0, length, time0, time1)
+    pub fn stats(&self) -> BVHStats {
+        let mut stats = BVHStats::default();
+        let root_area = self.bbox.surface_area().max(f32::MIN_POSITIVE);
+        self.accumulate_stats(&mut stats, 1, root_area);
+        stats
     }
 
-    fn compare(axis: usize, a: &Arc<dyn Hittable>, b: &Arc<dyn Hittable>) -> std::cmp::Ordering {
-        let box_a = a.bounding_box(0.0, 0.0);
-        let box_b = b.bounding_box(0.0, 0.0);
-        box_a
-            .and(box_b)
-            .expect("No bounding box in BVHNode constructor");
+    fn accumulate_stats(&self, stats: &mut BVHStats, depth: usize, root_area: f32) {
+        let area = self.bbox.surface_area() / root_area;
 
-        let a = box_a.unwrap().min().to_array()[axis];
-        let b = box_b.unwrap().min().to_array()[axis];
-        a.partial_cmp(&b).unwrap()
+        stats.nodes += 1;
+        stats.depth = stats.depth.max(depth);
+        match &self.contents {
+            BVHContents::Leaf(objects) => {
+                stats.leaves += 1;
+                stats.sah_cost += INTERSECTION_COST * area * objects.len() as f32;
+            }
+            BVHContents::Node(left, right) => {
+                stats.sah_cost += TRAVERSAL_COST * area;
+                left.accumulate_stats(stats, depth + 1, root_area);
+                right.accumulate_stats(stats, depth + 1, root_area);
+            }
+        }
     }
 }
 
+#[inline]
+fn bin_index(centroid: f32, min: f32, scale: f32, bins: usize) -> usize {
+    (((centroid - min) * scale) as usize).min(bins - 1)
+}
+
+fn min3(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0].min(b[0]), a[1].min(b[1]), a[2].min(b[2])]
+}
+
+fn max3(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0].max(b[0]), a[1].max(b[1]), a[2].max(b[2])]
+}
+
+fn merge(a: Option<AABB>, b: Option<AABB>) -> Option<AABB> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(surrounding_box(a, b)),
+        _ => a.or(b),
+    }
+}
+
+/// Moves the primitives matching `pred` to the front, keeping the order stable on that side,
+/// and returns how many there are.
+fn partition(primitives: &mut [Primitive], pred: impl Fn(&Primitive) -> bool) -> usize {
+    let mut mid = 0;
+    for i in 0..primitives.len() {
+        if pred(&primitives[i]) {
+            primitives.swap(i, mid);
+            mid += 1;
+        }
+    }
+    mid
+}
+
 impl Hittable for BVHNode {
-    fn hit(&self, r: &crate::ray::Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+    fn hit(
+        &self,
+        rng: &mut dyn rand::RngCore,
+        r: &Ray,
+        t_min: f32,
+        t_max: f32,
+    ) -> Option<HitRecord> {
+        visit(1);
         if !self.bbox.hit(r, t_min, t_max) {
-            None
-        } else {
-            self.left
-                .hit(r, t_min, t_max)
-                .and_then(|rec_l| self.right.hit(r, t_min, rec_l.t).or(Some(rec_l)))
-                .or_else(|| self.right.hit(r, t_min, t_max))
+            return None;
+        }
+
+        match &self.contents {
+            BVHContents::Leaf(objects) => {
+                visit(objects.len() as u32);
+                let mut closest = None;
+                let mut closest_so_far = t_max;
+                for object in objects {
+                    if let Some(rec) = object.hit(rng, r, t_min, closest_so_far) {
+                        closest_so_far = rec.t;
+                        closest = Some(rec);
+                    }
+                }
+                closest
+            }
+            BVHContents::Node(left, right) => match left.hit(rng, r, t_min, t_max) {
+                Some(rec_l) => right.hit(rng, r, t_min, rec_l.t).or(Some(rec_l)),
+                None => right.hit(rng, r, t_min, t_max),
+            },
         }
     }
 
@@ -96,7 +347,337 @@ impl Hittable for BVHNode {
         Some(self.bbox)
     }
 
-    fn pdf_value(&self, _o: Point3, _v: Vec3) -> f32 {
+    fn pdf_value(&self, _rng: &mut dyn rand::RngCore, _o: Point3, _v: Vec3) -> f32 {
         0.0
     }
 }
+
+// Flattened BVH
+/// Traversal stack entries kept inline; deeper trees fall back to the heap.
+const STACK_SIZE: usize = 32;
+
+#[derive(Debug, Copy, Clone)]
+struct FlatNode {
+    bbox: AABB,
+    /// First object of a leaf, or the second child of an inner node. The first child always
+    /// directly follows its parent.
+    offset: u32,
+    /// Number of objects in a leaf, zero for inner nodes.
+    count: u32,
+    /// Axis along which the children are ordered, for visiting the nearer one first.
+    axis: u8,
+}
+
+/// A BVH laid out as a depth-first array of nodes, traversed without recursion or virtual
+/// calls except on the objects themselves.
+#[derive(Debug)]
+pub struct FlatBVH {
+    nodes: Vec<FlatNode>,
+    objects: Vec<Arc<dyn Hittable>>,
+    depth: usize,
+}
+
+impl FlatBVH {
+    pub fn new_with_list(list: HittableList, time0: f32, time1: f32) -> Arc<Self> {
+        Self::with_settings(list, time0, time1, BVHSettings::default())
+    }
+
+    pub fn with_settings(
+        list: HittableList,
+        time0: f32,
+        time1: f32,
+        settings: BVHSettings,
+    ) -> Arc<Self> {
+        Arc::new(Self::from(&*BVHNode::with_settings(
+            list, time0, time1, settings,
+        )))
+    }
+
+    fn flatten(&mut self, node: &BVHNode) -> u32 {
+        let index = self.nodes.len();
+        self.nodes.push(FlatNode {
+            bbox: node.bbox,
+            offset: 0,
+            count: 0,
+            axis: 0,
+        });
+
+        match &node.contents {
+            BVHContents::Leaf(objects) => {
+                self.nodes[index].offset = self.objects.len() as u32;
+                self.nodes[index].count = objects.len() as u32;
+                self.objects.extend(objects.iter().cloned());
+            }
+            BVHContents::Node(left, right) => {
+                self.flatten(left);
+                self.nodes[index].offset = self.flatten(right);
+
+                // The children were split along the axis that separates them the most
+                let d = (right.bbox.centroid() - left.bbox.centroid()).to_array();
+                let d = [d[0].abs(), d[1].abs(), d[2].abs()];
+                self.nodes[index].axis = if d[0] >= d[1] && d[0] >= d[2] {
+                    0
+                } else if d[1] >= d[2] {
+                    1
+                } else {
+                    2
+                };
+            }
+        }
+
+        index as u32
+    }
+
+    pub fn stats(&self) -> BVHStats {
+        let root_area = self.nodes[0].bbox.surface_area().max(f32::MIN_POSITIVE);
+        let leaves = self.nodes.iter().filter(|node| node.count > 0).count();
+        let sah_cost = self
+            .nodes
+            .iter()
+            .map(|node| {
+                let area = node.bbox.surface_area() / root_area;
+                if node.count > 0 {
+                    INTERSECTION_COST * area * node.count as f32
+                } else {
+                    TRAVERSAL_COST * area
+                }
+            })
+            .sum();
+
+        BVHStats {
+            nodes: self.nodes.len(),
+            leaves,
+            depth: self.depth,
+            sah_cost,
+        }
+    }
+
+    fn traverse(
+        &self,
+        rng: &mut dyn rand::RngCore,
+        r: &Ray,
+        t_min: f32,
+        t_max: f32,
+        stack: &mut [u32],
+    ) -> Option<HitRecord> {
+        let origin = r.origin();
+        let inv_d = 1.0 / r.direction();
+        let [dx, dy, dz] = r.direction().to_array();
+        let dir_is_neg = [dx < 0.0, dy < 0.0, dz < 0.0];
+
+        let mut closest = None;
+        let mut closest_so_far = t_max;
+        let mut stack_len = 0;
+        let mut index = 0;
+        loop {
+            let node = &self.nodes[index];
+            visit(1);
+            if node.bbox.hit_inv(origin, inv_d, t_min, closest_so_far) {
+                if node.count > 0 {
+                    visit(node.count);
+                    let first = node.offset as usize;
+                    for object in &self.objects[first..first + node.count as usize] {
+                        if let Some(rec) = object.hit(rng, r, t_min, closest_so_far) {
+                            closest_so_far = rec.t;
+                            closest = Some(rec);
+                        }
+                    }
+                } else {
+                    let (near, far) = if dir_is_neg[node.axis as usize] {
+                        (node.offset, index as u32 + 1)
+                    } else {
+                        (index as u32 + 1, node.offset)
+                    };
+                    stack[stack_len] = far;
+                    stack_len += 1;
+                    index = near as usize;
+                    continue;
+                }
+            }
+
+            if stack_len == 0 {
+                break;
+            }
+            stack_len -= 1;
+            index = stack[stack_len] as usize;
+        }
+
+        closest
+    }
+}
+
+impl From<&BVHNode> for FlatBVH {
+    fn from(root: &BVHNode) -> Self {
+        let stats = root.stats();
+        let mut bvh = Self {
+            nodes: Vec::with_capacity(stats.nodes),
+            objects: Vec::new(),
+            depth: stats.depth,
+        };
+        bvh.flatten(root);
+
+        bvh
+    }
+}
+
+impl Hittable for FlatBVH {
+    fn hit(
+        &self,
+        rng: &mut dyn rand::RngCore,
+        r: &Ray,
+        t_min: f32,
+        t_max: f32,
+    ) -> Option<HitRecord> {
+        // A path from the root to a leaf leaves at most one sibling per level on the stack
+        if self.depth <= STACK_SIZE {
+            self.traverse(rng, r, t_min, t_max, &mut [0; STACK_SIZE])
+        } else {
+            self.traverse(rng, r, t_min, t_max, &mut vec![0; self.depth])
+        }
+    }
+
+    fn bounding_box(&self, _t0: f32, _t1: f32) -> Option<AABB> {
+        Some(self.nodes[0].bbox)
+    }
+
+    fn pdf_value(&self, _rng: &mut dyn rand::RngCore, _o: Point3, _v: Vec3) -> f32 {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    use crate::{aarect::*, sphere::*};
+
+    fn spheres() -> HittableList {
+        let material = Lambertian::new_rgb(0.5, 0.5, 0.5);
+        let mut list = HittableList::new();
+        for i in 0..100 {
+            let x = (i % 10) as f32;
+            let z = (i / 10) as f32;
+            list.add(Sphere::new(point!(x, 0.0, z), 0.4, material.clone()));
+        }
+        list
+    }
+
+    #[test]
+    fn hits_closest() {
+        let rng = &mut SmallRng::seed_from_u64(0);
+        let bvh = BVHNode::new_with_list(spheres(), 0.0, 1.0);
+
+        let r = Ray::new(point!(3.0, 0.0, -5.0), vec3!(0.0, 0.0, 1.0), 0.0);
+        let rec = bvh.hit(rng, &r, 0.001, f32::INFINITY).unwrap();
+        assert!((rec.t - 4.6).abs() < 1e-4);
+
+        let r = Ray::new(point!(3.5, 0.0, -5.0), vec3!(0.0, 0.0, 1.0), 0.0);
+        assert!(bvh.hit(rng, &r, 0.001, f32::INFINITY).is_none());
+    }
+
+    #[test]
+    fn deterministic_stats() {
+        let a = BVHNode::new_with_list(spheres(), 0.0, 1.0).stats();
+        let b = BVHNode::new_with_list(spheres(), 0.0, 1.0).stats();
+        assert_eq!(a, b);
+        assert_eq!(a.nodes, 2 * a.leaves - 1);
+
+        let settings = BVHSettings {
+            max_leaf_size: 1,
+            ..Default::default()
+        };
+        let single = BVHNode::with_settings(spheres(), 0.0, 1.0, settings).stats();
+        assert_eq!(single.leaves, 100);
+        // A balanced tree over 100 leaves has depth 8
+        assert!(single.depth >= 8 && single.depth <= 12);
+        assert!(single.leaves >= a.leaves);
+    }
+
+    #[test]
+    fn flat_matches_tree() {
+        let rng = &mut SmallRng::seed_from_u64(0);
+        let tree = BVHNode::new_with_list(spheres(), 0.0, 1.0);
+        let flat = FlatBVH::from(&*tree);
+        assert_eq!(flat.stats().nodes, tree.stats().nodes);
+        assert_eq!(flat.stats().leaves, tree.stats().leaves);
+        assert!((flat.stats().sah_cost - tree.stats().sah_cost).abs() < 1e-3);
+
+        let directions = [
+            vec3!(0.3, -0.2, 1.0),
+            vec3!(-0.3, -0.1, -1.0),
+            vec3!(1.0, 0.0, 0.05),
+            vec3!(-1.0, 0.01, -0.2),
+        ];
+        let mut hits = 0;
+        for (i, &direction) in directions.iter().enumerate() {
+            for k in 0..20 {
+                let origin = Point3::new(k as f32 * 0.5 - 2.0, 0.1 * i as f32, -3.0 + k as f32);
+                let r = Ray::new(origin, direction, 0.0);
+                let a = tree.hit(rng, &r, 0.001, f32::INFINITY).map(|rec| rec.t);
+                let b = flat.hit(rng, &r, 0.001, f32::INFINITY).map(|rec| rec.t);
+                assert_eq!(a, b);
+                hits += a.is_some() as usize;
+            }
+        }
+        assert!(hits > 20);
+    }
+
+    #[test]
+    fn hits_rects_in_every_plane() {
+        let rng = &mut SmallRng::seed_from_u64(0);
+        let material = Lambertian::new_rgb(0.5, 0.5, 0.5);
+        let mut list = HittableList::new();
+        let corners = (Point3::origin(), point!(1.0, 1.0, 1.0));
+        for &(plane, k) in &[(Plane::Xy, 2.0), (Plane::Xz, 4.0), (Plane::Yz, 6.0)] {
+            list.add(AARect::from_corner(
+                corners.0,
+                corners.1,
+                plane,
+                k,
+                material.clone(),
+            ));
+        }
+        let settings = BVHSettings {
+            max_leaf_size: 1,
+            ..Default::default()
+        };
+        let tree = BVHNode::with_settings(list, 0.0, 1.0, settings);
+        let flat = FlatBVH::from(&*tree);
+
+        let rays = [
+            Ray::new(point!(0.5, 0.5, -5.0), vec3!(0.0, 0.0, 1.0), 0.0),
+            Ray::new(point!(0.5, 10.0, 0.5), vec3!(0.0, -1.0, 0.0), 0.0),
+            Ray::new(point!(10.0, 0.5, 0.5), vec3!(-1.0, 0.0, 0.0), 0.0),
+        ];
+        for (r, &t) in rays.iter().zip(&[7.0, 6.0, 4.0]) {
+            let a = tree.hit(rng, r, 0.001, f32::INFINITY).map(|rec| rec.t);
+            let b = flat.hit(rng, r, 0.001, f32::INFINITY).map(|rec| rec.t);
+            assert_eq!((a, b), (Some(t), Some(t)));
+        }
+    }
+
+    #[test]
+    fn counts_visits() {
+        let rng = &mut SmallRng::seed_from_u64(0);
+        let flat = FlatBVH::new_with_list(spheres(), 0.0, 1.0);
+
+        // Missing the root box costs a single visit
+        let r = Ray::new(point!(3.0, 5.0, -5.0), vec3!(0.0, 0.0, 1.0), 0.0);
+        assert_eq!(
+            count_visits(|| flat.hit(rng, &r, 0.001, f32::INFINITY)).1,
+            1
+        );
+
+        // Down the middle of the grid costs more than a few nodes, but far less than every one
+        let r = Ray::new(point!(3.0, 0.0, -5.0), vec3!(0.0, 0.0, 1.0), 0.0);
+        let (rec, visits) = count_visits(|| flat.hit(rng, &r, 0.001, f32::INFINITY));
+        assert!(rec.is_some());
+        assert!(
+            visits > 4 && (visits as usize) < flat.stats().nodes + 100,
+            "{}",
+            visits
+        );
+    }
+}
@@ -8,13 +8,16 @@ pub struct Camera {
 
     u: Vec3,
     v: Vec3,
+    w: Vec3,
     lens_radius: f32,
+    focus_dist: f32,
 
     time0: f32,
     time1: f32,
 }
 
 impl Camera {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         look_from: Point3,
         look_at: Point3,
@@ -49,7 +52,9 @@ impl Camera {
             lower_left_corner,
             u,
             v,
+            w,
             lens_radius,
+            focus_dist,
             time0,
             time1,
         }
@@ -62,7 +67,54 @@ impl Camera {
         Ray::new(
             self.origin + offset,
             self.lower_left_corner + s * self.horizontal + t * self.vertical - self.origin - offset,
-            rng.gen_range(self.time0..self.time1),
+            self.shutter_time(rng),
         )
     }
+
+    /// A random time while the shutter is open.
+    pub fn shutter_time(&self, rng: &mut impl rand::Rng) -> f32 {
+        rng.gen_range(self.time0..self.time1)
+    }
+
+    /// Where `get_ray` would start a ray through `offset` from the center of the lens, taken
+    /// from a unit disk.
+    pub fn lens_point(&self, offset: Vec3) -> Point3 {
+        let rd = self.lens_radius * offset;
+        self.origin + self.u * rd.x() + self.v * rd.y()
+    }
+
+    /// Area of the lens, or 1 for a pinhole, whose single point is its own density.
+    pub fn lens_area(&self) -> f32 {
+        if self.lens_radius > 0.0 {
+            PI * self.lens_radius * self.lens_radius
+        } else {
+            1.0
+        }
+    }
+
+    /// Direction the camera looks in.
+    pub fn forward(&self) -> Vec3 {
+        -self.w
+    }
+
+    /// Area that `s` and `t` in `[0, 1]` cover at unit distance in front of the lens.
+    pub fn image_area(&self) -> f32 {
+        self.horizontal.length() * self.vertical.length() / (self.focus_dist * self.focus_dist)
+    }
+
+    /// The `(s, t)` for which `get_ray` shoots a ray from `lens` through `p`, if `p` is in
+    /// front of the camera. The result may lie outside the image.
+    pub fn project(&self, lens: Point3, p: Point3) -> Option<(f32, f32)> {
+        let d = p - lens;
+        let depth = d.dot(self.forward());
+        if depth <= 0.0 {
+            return None;
+        }
+
+        let focus = lens + d * (self.focus_dist / depth) - self.lower_left_corner;
+        Some((
+            focus.dot(self.horizontal) / self.horizontal.length_squared(),
+            focus.dot(self.vertical) / self.vertical.length_squared(),
+        ))
+    }
 }
@@ -0,0 +1,471 @@
+use crate::prelude::*;
+
+use crate::{framebuffer::*, integrator::*, render::*, scene::*, tile::*};
+
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::Display,
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+const MAGIC: &[u8; 8] = b"RTCKPT03";
+/// The previous format, without splats
+const MAGIC_V2: &[u8; 8] = b"RTCKPT02";
+
+#[derive(Debug)]
+pub enum CheckpointError {
+    Io(PathBuf, std::io::Error),
+    Header(ron::Error),
+    Format(String),
+    /// Checkpoints of different renders, differing in the named setting
+    Mismatch(&'static str),
+    /// Parts that took some of the same samples of the same pixels
+    Overlap,
+}
+
+impl Display for CheckpointError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CheckpointError::Io(path, e) => write!(f, "checkpoint {}: {}", path.display(), e),
+            CheckpointError::Header(e) => write!(f, "invalid checkpoint header: {}", e),
+            CheckpointError::Format(msg) => write!(f, "invalid checkpoint: {}", msg),
+            CheckpointError::Mismatch(setting) => {
+                write!(f, "cannot merge renders with a different {}", setting)
+            }
+            CheckpointError::Overlap => {
+                write!(f, "cannot merge renders that took some of the same samples")
+            }
+        }
+    }
+}
+
+impl std::error::Error for CheckpointError {}
+
+/// Everything needed to rebuild the scene and renderer of a checkpointed render.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CheckpointHeader {
+    /// Built-in scene name or path to a scene file
+    pub scene: String,
+    pub seed: u64,
+    /// Resolution and the requested number of samples per pixel
+    pub image: ImageSettings,
+    pub camera: CameraSettings,
+    pub max_depth: u32,
+    #[serde(default = "default_roulette_depth")]
+    pub roulette_depth: u32,
+    #[serde(default)]
+    pub integrator: IntegratorDesc,
+    #[serde(default)]
+    pub spectral: bool,
+    #[serde(default)]
+    pub adaptive: Option<AdaptiveSettings>,
+    /// How the image was split between processes, if it was
+    #[serde(default = "default_tile_size")]
+    pub tile_size: u32,
+    #[serde(default)]
+    pub share: Option<TileShare>,
+    #[serde(default)]
+    pub sample_offset: u32,
+    /// Sample indices the parts of a merged checkpoint took, which later parts must stay clear
+    /// of. Samples added by resuming it follow the last of them. Empty for a single part.
+    #[serde(default)]
+    pub merged: Vec<Range<u32>>,
+}
+
+fn default_roulette_depth() -> u32 {
+    RenderSettings::default().roulette_depth
+}
+
+fn default_tile_size() -> u32 {
+    RenderSettings::default().tile_size
+}
+
+/// A partially rendered image: the settings it was started with and the samples so far.
+///
+/// The file is `MAGIC`, the length of the header as a little-endian `u64`, the header in RON,
+/// then for each pixel, row by row from the top: its sum as three `f32`s, its sum of squared
+/// luminance as an `f32`, its sample count as a `u32` and its splats as three `f32`s, all
+/// little-endian.
+#[derive(Debug, Clone)]
+pub struct Checkpoint {
+    pub header: CheckpointHeader,
+    pub accumulator: Accumulator,
+}
+
+impl Checkpoint {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, CheckpointError> {
+        let path = path.as_ref();
+        let io_err = |e| CheckpointError::Io(path.to_path_buf(), e);
+        let mut r = BufReader::new(File::open(path).map_err(io_err)?);
+
+        let mut magic = [0; 8];
+        r.read_exact(&mut magic).map_err(io_err)?;
+        let has_splats = match &magic {
+            MAGIC => true,
+            MAGIC_V2 => false,
+            _ => return Err(CheckpointError::Format("not a checkpoint file".into())),
+        };
+
+        let mut len = [0; 8];
+        r.read_exact(&mut len).map_err(io_err)?;
+        let mut header = vec![0; u64::from_le_bytes(len) as usize];
+        r.read_exact(&mut header).map_err(io_err)?;
+        let header: CheckpointHeader =
+            ron::de::from_bytes(&header).map_err(CheckpointError::Header)?;
+
+        let width = header.image.image_width();
+        let height = header.image.image_height;
+        let len = (width * height) as usize;
+        let mut sums = Vec::with_capacity(len);
+        let mut sums_sq = Vec::with_capacity(len);
+        let mut samples = Vec::with_capacity(len);
+        let mut splats = Vec::with_capacity(len);
+        let mut pixel = [0; 32];
+        let pixel_len = if has_splats { 32 } else { 20 };
+        for _ in 0..len {
+            r.read_exact(&mut pixel[..pixel_len])
+                .map_err(|e| match e.kind() {
+                    std::io::ErrorKind::UnexpectedEof => CheckpointError::Format(format!(
+                        "expected {}x{} pixels, the file is truncated",
+                        width, height
+                    )),
+                    _ => io_err(e),
+                })?;
+
+            let word = |i: usize| [pixel[i], pixel[i + 1], pixel[i + 2], pixel[i + 3]];
+            sums.push(Color::new(
+                f32::from_le_bytes(word(0)),
+                f32::from_le_bytes(word(4)),
+                f32::from_le_bytes(word(8)),
+            ));
+            sums_sq.push(f32::from_le_bytes(word(12)));
+            samples.push(u32::from_le_bytes(word(16)));
+            splats.push(Color::new(
+                f32::from_le_bytes(word(20)),
+                f32::from_le_bytes(word(24)),
+                f32::from_le_bytes(word(28)),
+            ));
+        }
+
+        Ok(Self {
+            header,
+            accumulator: Accumulator::from_parts(width, height, sums, sums_sq, samples, splats),
+        })
+    }
+
+    /// Adds the samples of `other`, a part of the same render split off with a share, crop
+    /// window or sample offset. The merged checkpoint covers both parts and can be resumed
+    /// without repeating any of their samples.
+    pub fn merge(&mut self, other: &Checkpoint) -> Result<(), CheckpointError> {
+        let (a, b) = (&self.header, &other.header);
+        let mismatch = if a.scene != b.scene {
+            Some("scene")
+        } else if a.seed != b.seed {
+            Some("seed")
+        } else if (a.image.image_width(), a.image.image_height)
+            != (b.image.image_width(), b.image.image_height)
+        {
+            Some("resolution")
+        } else if a.camera != b.camera {
+            Some("camera")
+        } else if a.max_depth != b.max_depth {
+            Some("maximum depth")
+        } else if a.roulette_depth != b.roulette_depth {
+            Some("Russian roulette depth")
+        } else if a.integrator != b.integrator {
+            Some("integrator")
+        } else if a.spectral != b.spectral {
+            Some("color mode")
+        } else {
+            None
+        };
+        if let Some(setting) = mismatch {
+            return Err(CheckpointError::Mismatch(setting));
+        }
+
+        // Parts of one render with the same samples of a pixel took them the same way, so they
+        // would only count them twice
+        let (ours, theirs) = (self.sample_ranges(), other.sample_ranges());
+        let same_samples = ours
+            .iter()
+            .any(|a| theirs.iter().any(|b| a.start < b.end && b.start < a.end));
+        let same_pixels = self
+            .accumulator
+            .samples()
+            .iter()
+            .zip(other.accumulator.samples())
+            .any(|(&a, &b)| a > 0 && b > 0);
+        if same_samples && same_pixels {
+            return Err(CheckpointError::Overlap);
+        }
+
+        let end = self.sample_end().max(other.sample_end());
+        self.accumulator.merge(&other.accumulator);
+
+        // The next sample of a pixel is `sample_offset` plus its count, so the pixels with the
+        // most samples go on past every sample index either part has used
+        let header = &mut self.header;
+        header.sample_offset = end.saturating_sub(self.accumulator.max_samples());
+        header.merged = ours.into_iter().chain(theirs).collect();
+        header.share = None;
+        if header.image.crop != other.header.image.crop {
+            header.image.crop = None;
+        }
+        header.image.samples_per_pixel = header
+            .image
+            .samples_per_pixel
+            .max(other.header.image.samples_per_pixel);
+
+        Ok(())
+    }
+
+    /// One past the last sample index taken of any pixel.
+    fn sample_end(&self) -> u32 {
+        self.header.sample_offset + self.accumulator.max_samples()
+    }
+
+    /// Sample indices the samples were taken from, for each part merged into the checkpoint and
+    /// for the samples added by resuming it.
+    fn sample_ranges(&self) -> Vec<Range<u32>> {
+        let mut ranges = self.header.merged.clone();
+        let resumed = ranges.iter().map(|r| r.end).max().unwrap_or(0);
+        if ranges.is_empty() {
+            ranges.push(self.header.sample_offset..self.sample_end());
+        } else if self.sample_end() > resumed {
+            ranges.push(resumed..self.sample_end());
+        }
+        ranges
+    }
+
+    /// Writes to a temporary file next to `path` first, so an interrupted save leaves the
+    /// previous checkpoint intact.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), CheckpointError> {
+        let path = path.as_ref();
+        let io_err = |e| CheckpointError::Io(path.to_path_buf(), e);
+
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+
+        let mut w = BufWriter::new(File::create(&tmp).map_err(io_err)?);
+        self.write(&mut w).map_err(io_err)?;
+        w.into_inner()
+            .map_err(|e| io_err(e.into_error()))?
+            .sync_all()
+            .map_err(io_err)?;
+
+        std::fs::rename(&tmp, path).map_err(io_err)
+    }
+
+    fn write(&self, w: &mut impl Write) -> std::io::Result<()> {
+        let header = ron::to_string(&self.header)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+
+        w.write_all(MAGIC)?;
+        w.write_all(&(header.len() as u64).to_le_bytes())?;
+        w.write_all(header.as_bytes())?;
+        let accumulator = &self.accumulator;
+        for (((sum, &sum_sq), &n), splat) in accumulator
+            .sums()
+            .iter()
+            .zip(accumulator.sums_sq())
+            .zip(accumulator.samples())
+            .zip(accumulator.splats())
+        {
+            for e in &sum.to_array() {
+                w.write_all(&e.to_le_bytes())?;
+            }
+            w.write_all(&sum_sq.to_le_bytes())?;
+            w.write_all(&n.to_le_bytes())?;
+            for e in &splat.to_array() {
+                w.write_all(&e.to_le_bytes())?;
+            }
+        }
+        w.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let image = ImageSettings {
+            aspect_ratio: 1.5,
+            image_height: 2,
+            samples_per_pixel: 7,
+            crop: Some(CropWindow::Normalized {
+                min: (0.25, 0.0),
+                max: (0.75, 0.5),
+            }),
+        };
+        let mut accumulator = Accumulator::new(image.image_width(), image.image_height);
+        accumulator.add(
+            1,
+            0,
+            PixelSamples {
+                sum: Color::new(0.5, 1.0, 2.0),
+                sum_sq: 1.5,
+                count: 3,
+            },
+        );
+        accumulator.add(
+            2,
+            1,
+            PixelSamples {
+                sum: Color::new(4.0, 0.25, 0.0),
+                sum_sq: 1.25,
+                count: 1,
+            },
+        );
+        accumulator.splat(Splat {
+            x: 2,
+            y: 0,
+            color: Color::new(0.0, 3.0, 0.5),
+        });
+
+        let checkpoint = Checkpoint {
+            header: CheckpointHeader {
+                scene: "cornell_box".into(),
+                seed: 9,
+                image,
+                camera: CameraSettings::default(),
+                max_depth: 12,
+                roulette_depth: 3,
+                integrator: IntegratorDesc::Depth { far: Some(10.0) },
+                spectral: true,
+                adaptive: Some(AdaptiveSettings::default()),
+                tile_size: 16,
+                share: Some(TileShare { index: 1, count: 3 }),
+                sample_offset: 40,
+                merged: vec![0..20, 20..40],
+            },
+            accumulator,
+        };
+
+        let path = std::env::temp_dir().join(format!("checkpoint-{}.rtck", std::process::id()));
+        checkpoint.save(&path).unwrap();
+        let loaded = Checkpoint::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.header.scene, "cornell_box");
+        assert_eq!(loaded.header.seed, 9);
+        assert_eq!(loaded.header.image.samples_per_pixel, 7);
+        assert_eq!(loaded.header.image.crop, image.crop);
+        assert_eq!(loaded.header.max_depth, 12);
+        assert_eq!(loaded.header.integrator, checkpoint.header.integrator);
+        assert!(loaded.header.spectral);
+        assert!(loaded.header.adaptive.is_some());
+        assert_eq!(loaded.header.share, Some(TileShare { index: 1, count: 3 }));
+        assert_eq!(loaded.header.sample_offset, 40);
+        assert_eq!(loaded.header.merged, [0..20, 20..40]);
+        assert_eq!(
+            loaded.accumulator.samples(),
+            checkpoint.accumulator.samples()
+        );
+        assert_eq!(loaded.accumulator.sums(), checkpoint.accumulator.sums());
+        assert_eq!(
+            loaded.accumulator.sums_sq(),
+            checkpoint.accumulator.sums_sq()
+        );
+        assert_eq!(loaded.accumulator.splats(), checkpoint.accumulator.splats());
+    }
+
+    #[test]
+    fn merge() {
+        let part = |sample_offset, samples, y| {
+            let image = ImageSettings {
+                aspect_ratio: 1.0,
+                image_height: 2,
+                ..ImageSettings::default()
+            };
+            let mut accumulator = Accumulator::new(2, 2);
+            accumulator.add(
+                0,
+                y,
+                PixelSamples {
+                    sum: Color::new(1.0, 2.0, 3.0),
+                    sum_sq: 4.0,
+                    count: samples,
+                },
+            );
+            Checkpoint {
+                header: CheckpointHeader {
+                    scene: "cornell_box".into(),
+                    seed: 1,
+                    image,
+                    camera: CameraSettings::default(),
+                    max_depth: 50,
+                    roulette_depth: 3,
+                    integrator: IntegratorDesc::Path,
+                    spectral: false,
+                    adaptive: None,
+                    tile_size: 32,
+                    share: None,
+                    sample_offset,
+                    merged: Vec::new(),
+                },
+                accumulator,
+            }
+        };
+
+        let mut merged = part(0, 4, 1);
+        merged.merge(&part(4, 6, 1)).unwrap();
+        assert_eq!(merged.accumulator.samples(), [0, 0, 10, 0]);
+        assert_eq!(merged.accumulator.sums()[2], Color::new(2.0, 4.0, 6.0));
+        // The next sample of the pixel is its 10th
+        assert_eq!(merged.header.sample_offset, 0);
+
+        // Samples a part already has are rejected, even after merging...
+        let mut twice = part(0, 4, 1);
+        assert!(matches!(
+            twice.merge(&part(0, 4, 1)),
+            Err(CheckpointError::Overlap)
+        ));
+        assert!(matches!(
+            merged.merge(&part(2, 4, 1)),
+            Err(CheckpointError::Overlap)
+        ));
+        assert_eq!(merged.accumulator.samples(), [0, 0, 10, 0]);
+        // ...but other pixels can take the same samples, and later samples come after the
+        // ranges of all parts
+        merged.merge(&part(0, 4, 0)).unwrap();
+        merged.merge(&part(10, 2, 1)).unwrap();
+        assert_eq!(merged.accumulator.samples(), [4, 0, 12, 0]);
+        assert_eq!(merged.header.merged, [0..4, 4..10, 0..4, 10..12]);
+
+        // Resuming the merged render takes samples 12 to 16 of the pixel, which a part
+        // cannot bring again
+        merged.accumulator.add(
+            0,
+            1,
+            PixelSamples {
+                sum: Color::new(1.0, 2.0, 3.0),
+                sum_sq: 4.0,
+                count: 4,
+            },
+        );
+        assert!(matches!(
+            merged.merge(&part(12, 2, 1)),
+            Err(CheckpointError::Overlap)
+        ));
+        merged.merge(&part(16, 2, 1)).unwrap();
+        assert_eq!(merged.accumulator.samples(), [4, 0, 18, 0]);
+        assert_eq!(merged.header.sample_offset, 0);
+        assert_eq!(
+            merged.header.merged,
+            [0..4, 4..10, 0..4, 10..12, 12..16, 16..18]
+        );
+
+        let mut other = part(0, 4, 1);
+        other.header.seed = 2;
+        assert!(matches!(
+            merged.merge(&other),
+            Err(CheckpointError::Mismatch("seed"))
+        ));
+    }
+}
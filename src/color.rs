@@ -1,13 +1,8 @@
 use crate::prelude::*;
 
-pub fn write_color(
-    f: &mut dyn std::fmt::Write,
-    mut pixel_color: Color,
-    samples_per_pixel: u32,
-) -> Result<(), std::fmt::Error> {
-    let scale = 1.0 / samples_per_pixel as f32;
-    pixel_color *= scale;
-    let (mut r, mut g, mut b) = (pixel_color.x(), pixel_color.y(), pixel_color.z());
+/// Replaces NaN components, which a degenerate sample can produce, with zero.
+pub fn sanitize(color: Color) -> Color {
+    let [mut r, mut g, mut b] = color.to_array();
 
     if r.is_nan() {
         r = 0.0;
@@ -19,17 +14,27 @@ pub fn write_color(
         b = 0.0;
     }
 
+    Color::new(r, g, b)
+}
+
+pub fn color_to_rgb8(pixel_color: Color) -> [u8; 3] {
+    let [mut r, mut g, mut b] = sanitize(pixel_color).to_array();
+
     // Gamma-correct for gamma = 2.0
     r = r.sqrt();
     g = g.sqrt();
     b = b.sqrt();
 
     // Truncate at [0,255]
-    writeln!(
-        f,
-        "{} {} {}",
-        (256.0 * clamp(r, 0.0, 0.999)) as u32,
-        (256.0 * clamp(g, 0.0, 0.999)) as u32,
-        (256.0 * clamp(b, 0.0, 0.999)) as u32,
-    )
+    [
+        (256.0 * clamp(r, 0.0, 0.999)) as u8,
+        (256.0 * clamp(g, 0.0, 0.999)) as u8,
+        (256.0 * clamp(b, 0.0, 0.999)) as u8,
+    ]
+}
+
+/// Relative luminance of a linear Rec. 709 color.
+pub fn luminance(color: Color) -> f32 {
+    let [r, g, b] = color.to_array();
+    0.2126 * r + 0.7152 * g + 0.0722 * b
 }
@@ -1,5 +1,6 @@
 use crate::prelude::*;
 
+use rand::Rng;
 use std::sync::Arc;
 #[derive(Debug)]
 pub struct ConstantMedium {
@@ -19,9 +20,15 @@ impl ConstantMedium {
 }
 
 impl Hittable for ConstantMedium {
-    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
-        if let Some(mut rec1) = self.boundary.hit(r, f32::NEG_INFINITY, f32::INFINITY) {
-            if let Some(mut rec2) = self.boundary.hit(r, rec1.t + 0.0001, f32::INFINITY) {
+    fn hit(
+        &self,
+        rng: &mut dyn rand::RngCore,
+        r: &Ray,
+        t_min: f32,
+        t_max: f32,
+    ) -> Option<HitRecord> {
+        if let Some(mut rec1) = self.boundary.hit(rng, r, f32::NEG_INFINITY, f32::INFINITY) {
+            if let Some(mut rec2) = self.boundary.hit(rng, r, rec1.t + 0.0001, f32::INFINITY) {
                 rec1.t = rec1.t.max(t_min);
                 rec2.t = rec2.t.min(t_max);
 
@@ -33,7 +40,7 @@ impl Hittable for ConstantMedium {
 
                 let ray_length = r.direction().length();
                 let distance_inside_boundary = (rec2.t - rec1.t) * ray_length;
-                let hit_distance = self.neg_inv_density * rand::random::<f32>().ln();
+                let hit_distance = self.neg_inv_density * rng.gen::<f32>().ln();
 
                 if hit_distance > distance_inside_boundary {
                     return None;
@@ -61,7 +68,7 @@ impl Hittable for ConstantMedium {
         self.boundary.bounding_box(t0, t1)
     }
 
-    fn pdf_value(&self, _o: Point3, _v: Vec3) -> f32 {
+    fn pdf_value(&self, _rng: &mut dyn rand::RngCore, _o: Point3, _v: Vec3) -> f32 {
         0.0
     }
 }
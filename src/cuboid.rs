@@ -32,15 +32,21 @@ impl Cuboid {
 }
 
 impl Hittable for Cuboid {
-    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
-        self.sides.hit(r, t_min, t_max)
+    fn hit(
+        &self,
+        rng: &mut dyn rand::RngCore,
+        r: &Ray,
+        t_min: f32,
+        t_max: f32,
+    ) -> Option<HitRecord> {
+        self.sides.hit(rng, r, t_min, t_max)
     }
 
     fn bounding_box(&self, _: f32, _: f32) -> Option<AABB> {
         Some(AABB::new(self.box_min, self.box_max))
     }
 
-    fn pdf_value(&self, _o: Point3, _v: Vec3) -> f32 {
+    fn pdf_value(&self, _rng: &mut dyn rand::RngCore, _o: Point3, _v: Vec3) -> f32 {
         0.0
     }
 }
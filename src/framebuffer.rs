@@ -0,0 +1,345 @@
+use crate::prelude::*;
+
+use crate::{color::*, tile::*};
+
+use image::{GrayImage, ImageFormat, Luma, Rgb, RgbImage};
+use std::{
+    fmt::Display,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum OutputFormat {
+    /// 8-bit formats, gamma-corrected and clamped
+    Ldr(ImageFormat),
+    /// Radiance RGBE
+    Hdr,
+    /// Portable float map
+    Pfm,
+}
+
+#[derive(Debug)]
+pub struct UnsupportedFormat(String);
+
+impl Display for UnsupportedFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "unsupported output format `{}`: expected one of png, jpg, ppm, bmp, tga, hdr, pfm",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for UnsupportedFormat {}
+
+impl OutputFormat {
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, UnsupportedFormat> {
+        let ext = path
+            .as_ref()
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or("")
+            .to_ascii_lowercase();
+
+        match ext.as_str() {
+            "hdr" => Ok(OutputFormat::Hdr),
+            "pfm" => Ok(OutputFormat::Pfm),
+            _ => match ImageFormat::from_extension(&ext) {
+                Some(
+                    format @ ImageFormat::Png
+                    | format @ ImageFormat::Jpeg
+                    | format @ ImageFormat::Pnm
+                    | format @ ImageFormat::Bmp
+                    | format @ ImageFormat::Tga,
+                ) => Ok(OutputFormat::Ldr(format)),
+                _ => Err(UnsupportedFormat(ext)),
+            },
+        }
+    }
+}
+
+/// Linear radiance per pixel, stored row by row from the top of the image.
+#[derive(Debug, Clone)]
+pub struct Framebuffer {
+    width: u32,
+    height: u32,
+    pixels: Vec<Color>,
+}
+
+impl Framebuffer {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self::from_pixels(
+            width,
+            height,
+            vec![Color::black(); (width * height) as usize],
+        )
+    }
+
+    pub fn from_pixels(width: u32, height: u32, pixels: Vec<Color>) -> Self {
+        assert_eq!(pixels.len(), (width * height) as usize);
+
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+    pub fn pixels(&self) -> &[Color] {
+        &self.pixels
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> Color {
+        self.pixels[(y * self.width + x) as usize]
+    }
+
+    pub fn set(&mut self, x: u32, y: u32, color: Color) {
+        self.pixels[(y * self.width + x) as usize] = color;
+    }
+
+    /// Copies out the pixels of `rect`, which must lie within the image.
+    pub fn crop(&self, rect: PixelRect) -> Framebuffer {
+        assert!(rect.x + rect.width <= self.width && rect.y + rect.height <= self.height);
+
+        let pixels = rect.pixels().map(|(x, y)| self.get(x, y)).collect();
+        Framebuffer::from_pixels(rect.width, rect.height, pixels)
+    }
+
+    pub fn to_rgb8(&self) -> RgbImage {
+        RgbImage::from_fn(self.width, self.height, |x, y| {
+            Rgb(color_to_rgb8(self.get(x, y)))
+        })
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Box<dyn std::error::Error>> {
+        let path = path.as_ref();
+
+        match OutputFormat::from_path(path)? {
+            OutputFormat::Ldr(_) => self.to_rgb8().save(path)?,
+            OutputFormat::Hdr => {
+                let pixels: Vec<_> = self
+                    .pixels
+                    .iter()
+                    .map(|&c| Rgb(sanitize(c).to_array()))
+                    .collect();
+                image::codecs::hdr::HdrEncoder::new(BufWriter::new(File::create(path)?)).encode(
+                    &pixels,
+                    self.width as usize,
+                    self.height as usize,
+                )?
+            }
+            OutputFormat::Pfm => self.write_pfm(&mut BufWriter::new(File::create(path)?))?,
+        }
+
+        Ok(())
+    }
+
+    /// PFM stores little-endian floats with the bottom row first.
+    fn write_pfm(&self, w: &mut impl Write) -> std::io::Result<()> {
+        write!(w, "PF\n{} {}\n-1.0\n", self.width, self.height)?;
+        for row in self.pixels.chunks(self.width as usize).rev() {
+            for &c in row {
+                for e in &sanitize(c).to_array() {
+                    w.write_all(&e.to_le_bytes())?;
+                }
+            }
+        }
+        w.flush()
+    }
+}
+
+/// A batch of samples of one pixel.
+#[derive(Debug, Copy, Clone)]
+pub struct PixelSamples {
+    pub sum: Color,
+    /// Sum of the squared luminance of each sample, to estimate the variance
+    pub sum_sq: f32,
+    pub count: u32,
+}
+
+/// Radiance a sample found for pixel `(x, y)`, counted from the top left.
+#[derive(Debug, Copy, Clone)]
+pub struct Splat {
+    pub x: u32,
+    pub y: u32,
+    pub color: Color,
+}
+
+/// Running sums of radiance samples and their counts per pixel, stored like a `Framebuffer`.
+/// Passes of samples can be added over time and resolved to an image at any point. Splats
+/// are averaged over the samples of the whole image.
+#[derive(Debug, Clone)]
+pub struct Accumulator {
+    width: u32,
+    height: u32,
+    sums: Vec<Color>,
+    sums_sq: Vec<f32>,
+    samples: Vec<u32>,
+    splats: Vec<Color>,
+}
+
+impl Accumulator {
+    pub fn new(width: u32, height: u32) -> Self {
+        let len = (width * height) as usize;
+        Self::from_parts(
+            width,
+            height,
+            vec![Color::black(); len],
+            vec![0.0; len],
+            vec![0; len],
+            vec![Color::black(); len],
+        )
+    }
+
+    pub fn from_parts(
+        width: u32,
+        height: u32,
+        sums: Vec<Color>,
+        sums_sq: Vec<f32>,
+        samples: Vec<u32>,
+        splats: Vec<Color>,
+    ) -> Self {
+        assert_eq!(sums.len(), (width * height) as usize);
+        assert_eq!(sums_sq.len(), sums.len());
+        assert_eq!(samples.len(), sums.len());
+        assert_eq!(splats.len(), sums.len());
+
+        Self {
+            width,
+            height,
+            sums,
+            sums_sq,
+            samples,
+            splats,
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+    pub fn sums(&self) -> &[Color] {
+        &self.sums
+    }
+    pub fn sums_sq(&self) -> &[f32] {
+        &self.sums_sq
+    }
+    pub fn samples(&self) -> &[u32] {
+        &self.samples
+    }
+    pub fn splats(&self) -> &[Color] {
+        &self.splats
+    }
+
+    /// Number of samples taken so far at pixel `(x, y)`.
+    pub fn sample_count(&self, x: u32, y: u32) -> u32 {
+        self.samples[(y * self.width + x) as usize]
+    }
+
+    /// Fewest samples taken by any pixel.
+    pub fn min_samples(&self) -> u32 {
+        self.samples.iter().copied().min().unwrap_or(0)
+    }
+
+    /// Most samples taken by any pixel.
+    pub fn max_samples(&self) -> u32 {
+        self.samples.iter().copied().max().unwrap_or(0)
+    }
+
+    /// Fewest samples taken by any pixel in `region`.
+    pub fn min_samples_in(&self, region: PixelRect) -> u32 {
+        region
+            .pixels()
+            .map(|(x, y)| self.sample_count(x, y))
+            .min()
+            .unwrap_or(0)
+    }
+
+    pub fn add(&mut self, x: u32, y: u32, samples: PixelSamples) {
+        let index = (y * self.width + x) as usize;
+        self.sums[index] += samples.sum;
+        self.sums_sq[index] += samples.sum_sq;
+        self.samples[index] += samples.count;
+    }
+
+    pub fn splat(&mut self, splat: Splat) {
+        self.splats[(splat.y * self.width + splat.x) as usize] += splat.color;
+    }
+
+    /// Adds all samples of `other`, which must have the same size.
+    pub fn merge(&mut self, other: &Accumulator) {
+        assert_eq!((self.width, self.height), (other.width, other.height));
+
+        for (a, &b) in self.sums.iter_mut().zip(&other.sums) {
+            *a += b;
+        }
+        for (a, &b) in self.sums_sq.iter_mut().zip(&other.sums_sq) {
+            *a += b;
+        }
+        for (a, &b) in self.samples.iter_mut().zip(&other.samples) {
+            *a += b;
+        }
+        for (a, &b) in self.splats.iter_mut().zip(&other.splats) {
+            *a += b;
+        }
+    }
+
+    /// Standard error of the mean luminance of pixel `(x, y)`, relative to the square root of
+    /// that mean to match the gamma of 8-bit output. Infinite below two samples.
+    pub fn error(&self, x: u32, y: u32) -> f32 {
+        let index = (y * self.width + x) as usize;
+        let n = self.samples[index] as f32;
+        if n < 2.0 {
+            return f32::INFINITY;
+        }
+
+        let mean = luminance(self.sums[index]) / n;
+        let variance = ((self.sums_sq[index] - n * mean * mean) / (n - 1.0)).max(0.0);
+        (variance / n).sqrt() / mean.max(1e-4).sqrt()
+    }
+
+    /// Averages the samples of each pixel and adds its splats; pixels without samples are
+    /// black.
+    pub fn resolve(&self) -> Framebuffer {
+        let total: u64 = self.samples.iter().map(|&n| n as u64).sum();
+        let splat_scale = (self.width * self.height) as f32 / total.max(1) as f32;
+        let splatted = self.splats.iter().any(|&splat| splat != Color::black());
+
+        let pixels = self
+            .sums
+            .iter()
+            .zip(&self.samples)
+            .zip(&self.splats)
+            .map(|((&sum, &n), &splat)| {
+                if n == 0 {
+                    Color::black()
+                } else if splatted {
+                    sum / n as f32 + splat * splat_scale
+                } else {
+                    sum / n as f32
+                }
+            })
+            .collect();
+
+        Framebuffer::from_pixels(self.width, self.height, pixels)
+    }
+
+    /// Grayscale image of the samples taken per pixel, as a fraction of `max_samples`.
+    pub fn sample_map(&self, max_samples: u32) -> GrayImage {
+        GrayImage::from_fn(self.width, self.height, |x, y| {
+            let fraction = self.sample_count(x, y) as f32 / max_samples as f32;
+            Luma([(255.0 * clamp(fraction, 0.0, 1.0)).round() as u8])
+        })
+    }
+}
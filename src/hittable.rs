@@ -2,9 +2,13 @@ use crate::prelude::*;
 
 use std::{fmt::Debug, sync::Arc};
 
+#[derive(Clone)]
 pub struct HitRecord {
     pub p: Point3,
     pub normal: Vec3,
+    /// Normal of the surface itself on its front side, which interpolated shading normals
+    /// leave out of `normal`. Measures of area, like light sampling densities, go by this one.
+    pub geometric_normal: Vec3,
     pub t: f32,
     pub u: f32,
     pub v: f32,
@@ -32,6 +36,7 @@ impl HitRecord {
         Self {
             p,
             normal,
+            geometric_normal: outward_normal,
             t,
             u,
             v,
@@ -40,20 +45,53 @@ impl HitRecord {
         }
     }
 
-    pub fn set_face_normal(&mut self, r: &Ray, normal: Vec3) {
-        self.front_face = r.direction().dot(normal) < 0.0;
-        self.normal = if self.front_face { normal } else { -normal };
+    /// The normal on the front side of the surface, whichever side the ray came from.
+    pub fn outward_normal(&self) -> Vec3 {
+        if self.front_face {
+            self.normal
+        } else {
+            -self.normal
+        }
+    }
+
+    /// The record a ray arriving from direction `towards` (pointing away from the surface)
+    /// would have gotten.
+    pub fn facing(&self, towards: Vec3) -> Self {
+        let outward = self.outward_normal();
+        let front_face = towards.dot(self.geometric_normal) > 0.0;
+        Self {
+            normal: if front_face { outward } else { -outward },
+            front_face,
+            ..self.clone()
+        }
     }
 }
 
 pub trait Hittable: Sync + Send + Debug {
-    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord>;
+    fn hit(
+        &self,
+        rng: &mut dyn rand::RngCore,
+        r: &Ray,
+        t_min: f32,
+        t_max: f32,
+    ) -> Option<HitRecord>;
     fn bounding_box(&self, t0: f32, t1: f32) -> Option<AABB>;
-    fn pdf_value(&self, o: Point3, v: Vec3) -> f32;
+    fn pdf_value(&self, rng: &mut dyn rand::RngCore, o: Point3, v: Vec3) -> f32;
 
     fn random(&self, _rng: &mut dyn rand::RngCore, _o: Vec3) -> Vec3 {
         vec3!(1.0, 0.0, 0.0)
     }
+
+    /// Surface area, for objects that support `sample_surface`, and 0 otherwise.
+    fn area(&self) -> f32 {
+        0.0
+    }
+
+    /// Picks a point uniformly by area on the surface, so light can be emitted from it. The
+    /// record is the one a ray arriving from the front side would get there.
+    fn sample_surface(&self, _rng: &mut dyn rand::RngCore) -> Option<HitRecord> {
+        None
+    }
 }
 
 #[derive(Debug)]
@@ -69,11 +107,16 @@ impl Translate {
 }
 
 impl Hittable for Translate {
-    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+    fn hit(
+        &self,
+        rng: &mut dyn rand::RngCore,
+        r: &Ray,
+        t_min: f32,
+        t_max: f32,
+    ) -> Option<HitRecord> {
         let moved_r = Ray::new(r.origin() - self.offset, r.direction(), r.time());
-        if let Some(mut rec) = self.inner.hit(&moved_r, t_min, t_max) {
+        if let Some(mut rec) = self.inner.hit(rng, &moved_r, t_min, t_max) {
             rec.p += self.offset;
-            rec.set_face_normal(&moved_r, rec.normal);
 
             Some(rec)
         } else {
@@ -82,25 +125,31 @@ impl Hittable for Translate {
     }
 
     fn bounding_box(&self, t0: f32, t1: f32) -> Option<AABB> {
-        if let Some(output_box) = self.inner.bounding_box(t0, t1) {
-            Some(AABB::new(
+        self.inner.bounding_box(t0, t1).map(|output_box| {
+            AABB::new(
                 output_box.min() + self.offset,
                 output_box.max() + self.offset,
-            ))
-        } else {
-            None
-        }
+            )
+        })
     }
 
-    fn pdf_value(&self, o: Point3, v: Vec3) -> f32 {
-        if self
-            .hit(&Ray::new(o, v, 0.0), 0.001, f32::INFINITY)
-            .is_some()
-        {
-            self.inner.pdf_value(o, v)
-        } else {
-            0.0
-        }
+    fn pdf_value(&self, rng: &mut dyn rand::RngCore, o: Point3, v: Vec3) -> f32 {
+        self.inner.pdf_value(rng, o - self.offset, v)
+    }
+
+    fn random(&self, rng: &mut dyn rand::RngCore, o: Vec3) -> Vec3 {
+        self.inner.random(rng, o - self.offset)
+    }
+
+    fn area(&self) -> f32 {
+        self.inner.area()
+    }
+
+    fn sample_surface(&self, rng: &mut dyn rand::RngCore) -> Option<HitRecord> {
+        self.inner.sample_surface(rng).map(|rec| HitRecord {
+            p: rec.p + self.offset,
+            ..rec
+        })
     }
 }
 
@@ -153,36 +202,45 @@ impl RotateY {
     }
 }
 
-impl Hittable for RotateY {
-    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
-        let mut origin = r.origin().to_array();
-        let mut direction = r.direction().to_array();
-
-        // Ray inversely rotated
-        origin[0] = self.cos_theta * r.origin().x() - self.sin_theta * r.origin().z();
-        origin[2] = self.sin_theta * r.origin().x() + self.cos_theta * r.origin().z();
+impl RotateY {
+    /// Applies the inverse rotation, taking world space to the space of `inner`.
+    fn to_object(&self, v: Vec3) -> Vec3 {
+        Vec3::new(
+            self.cos_theta * v.x() - self.sin_theta * v.z(),
+            v.y(),
+            self.sin_theta * v.x() + self.cos_theta * v.z(),
+        )
+    }
 
-        direction[0] = self.cos_theta * r.direction().x() - self.sin_theta * r.direction().z();
-        direction[2] = self.sin_theta * r.direction().x() + self.cos_theta * r.direction().z();
+    /// Applies the rotation, taking the space of `inner` to world space.
+    fn to_world(&self, v: Vec3) -> Vec3 {
+        Vec3::new(
+            self.cos_theta * v.x() + self.sin_theta * v.z(),
+            v.y(),
+            -self.sin_theta * v.x() + self.cos_theta * v.z(),
+        )
+    }
+}
 
+impl Hittable for RotateY {
+    fn hit(
+        &self,
+        rng: &mut dyn rand::RngCore,
+        r: &Ray,
+        t_min: f32,
+        t_max: f32,
+    ) -> Option<HitRecord> {
         let rotated_r = Ray::new(
-            Vec3::from_array(origin),
-            Vec3::from_array(direction),
+            self.to_object(r.origin()),
+            self.to_object(r.direction()),
             r.time(),
         );
 
-        if let Some(mut rec) = self.inner.hit(&rotated_r, t_min, t_max) {
-            let mut p = rec.p.to_array();
-            let mut normal = rec.normal.to_array();
-
-            p[0] = self.cos_theta * rec.p.x() + self.sin_theta * rec.p.z();
-            p[2] = -self.sin_theta * rec.p.x() + self.cos_theta * rec.p.z();
-
-            normal[0] = self.cos_theta * rec.normal.x() + self.sin_theta * rec.normal.z();
-            normal[2] = -self.sin_theta * rec.normal.x() + self.cos_theta * rec.normal.z();
-
-            rec.p = Vec3::from_array(p);
-            rec.set_face_normal(&rotated_r, Vec3::from_array(normal));
+        if let Some(mut rec) = self.inner.hit(rng, &rotated_r, t_min, t_max) {
+            // Rotation preserves the side the ray came from, so `front_face` carries over
+            rec.p = self.to_world(rec.p);
+            rec.normal = self.to_world(rec.normal);
+            rec.geometric_normal = self.to_world(rec.geometric_normal);
 
             Some(rec)
         } else {
@@ -194,15 +252,26 @@ impl Hittable for RotateY {
         self.bbox
     }
 
-    fn pdf_value(&self, o: Point3, v: Vec3) -> f32 {
-        if self
-            .hit(&Ray::new(o, v, 0.0), 0.001, f32::INFINITY)
-            .is_some()
-        {
-            self.inner.pdf_value(o, v)
-        } else {
-            0.0
-        }
+    fn pdf_value(&self, rng: &mut dyn rand::RngCore, o: Point3, v: Vec3) -> f32 {
+        self.inner
+            .pdf_value(rng, self.to_object(o), self.to_object(v))
+    }
+
+    fn random(&self, rng: &mut dyn rand::RngCore, o: Vec3) -> Vec3 {
+        self.to_world(self.inner.random(rng, self.to_object(o)))
+    }
+
+    fn area(&self) -> f32 {
+        self.inner.area()
+    }
+
+    fn sample_surface(&self, rng: &mut dyn rand::RngCore) -> Option<HitRecord> {
+        self.inner.sample_surface(rng).map(|rec| HitRecord {
+            p: self.to_world(rec.p),
+            normal: self.to_world(rec.normal),
+            geometric_normal: self.to_world(rec.geometric_normal),
+            ..rec
+        })
     }
 }
 
@@ -219,8 +288,15 @@ impl FlipFace {
 }
 
 impl Hittable for FlipFace {
-    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
-        self.inner.hit(r, t_min, t_max).map(|rec| HitRecord {
+    fn hit(
+        &self,
+        rng: &mut dyn rand::RngCore,
+        r: &Ray,
+        t_min: f32,
+        t_max: f32,
+    ) -> Option<HitRecord> {
+        self.inner.hit(rng, r, t_min, t_max).map(|rec| HitRecord {
+            geometric_normal: -rec.geometric_normal,
             front_face: !rec.front_face,
             ..rec
         })
@@ -230,11 +306,58 @@ impl Hittable for FlipFace {
         self.inner.bounding_box(t0, t1)
     }
 
-    fn pdf_value(&self, o: Point3, v: Vec3) -> f32 {
-        self.inner.pdf_value(o, v)
+    fn pdf_value(&self, rng: &mut dyn rand::RngCore, o: Point3, v: Vec3) -> f32 {
+        self.inner.pdf_value(rng, o, v)
     }
 
     fn random(&self, rng: &mut dyn rand::RngCore, o: Vec3) -> Vec3 {
         self.inner.random(rng, o)
     }
+
+    fn area(&self) -> f32 {
+        self.inner.area()
+    }
+
+    fn sample_surface(&self, rng: &mut dyn rand::RngCore) -> Option<HitRecord> {
+        self.inner.sample_surface(rng).map(|rec| HitRecord {
+            geometric_normal: -rec.geometric_normal,
+            front_face: !rec.front_face,
+            ..rec
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::sphere::*;
+
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    #[test]
+    fn rotate_y() {
+        let rng = &mut SmallRng::seed_from_u64(0);
+        let sphere = Sphere::new(
+            point!(1.0, 0.0, 0.0),
+            0.5,
+            Lambertian::new_rgb(0.5, 0.5, 0.5),
+        );
+        // A quarter turn about y takes +x to -z
+        let rotated = RotateY::new(sphere, 90.0);
+
+        let r = Ray::new(point!(0.0, 0.0, 5.0), vec3!(0.0, 0.0, -1.0), 0.0);
+        let rec = rotated.hit(rng, &r, 0.001, f32::INFINITY).unwrap();
+        assert!((rec.t - 5.5).abs() < 1e-5);
+        assert!((rec.p - point!(0.0, 0.0, -0.5)).length() < 1e-5);
+        assert!((rec.normal - vec3!(0.0, 0.0, 1.0)).length() < 1e-5);
+        assert!(rec.front_face);
+
+        let old = Ray::new(point!(1.0, 0.0, 5.0), vec3!(0.0, 0.0, -1.0), 0.0);
+        assert!(rotated.hit(rng, &old, 0.001, f32::INFINITY).is_none());
+
+        let bbox = rotated.bounding_box(0.0, 1.0).unwrap();
+        assert!((bbox.min() - point!(-0.5, -0.5, -1.5)).length() < 1e-5);
+        assert!((bbox.max() - point!(0.5, 0.5, -0.5)).length() < 1e-5);
+    }
 }
@@ -2,7 +2,7 @@ use crate::prelude::*;
 
 use rand::Rng;
 use std::sync::Arc;
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct HittableList {
     pub objects: Vec<Arc<dyn Hittable>>,
 }
@@ -20,12 +20,18 @@ impl HittableList {
 }
 
 impl Hittable for HittableList {
-    fn hit(&self, r: &crate::ray::Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+    fn hit(
+        &self,
+        rng: &mut dyn rand::RngCore,
+        r: &crate::ray::Ray,
+        t_min: f32,
+        t_max: f32,
+    ) -> Option<HitRecord> {
         let mut final_rec: Option<HitRecord> = None;
         let mut closest_so_far = t_max;
 
         for object in &self.objects {
-            if let Some(rec) = object.hit(r, t_min, closest_so_far) {
+            if let Some(rec) = object.hit(rng, r, t_min, closest_so_far) {
                 closest_so_far = rec.t;
                 final_rec = Some(rec);
             }
@@ -37,21 +43,18 @@ impl Hittable for HittableList {
     fn bounding_box(&self, t0: f32, t1: f32) -> Option<AABB> {
         let first = self.objects.first().and_then(|x| x.bounding_box(t0, t1))?;
 
-        self.objects.iter().skip(1).fold(Some(first), |acc, x| {
-            if let Some(bounding_box) = x.bounding_box(t0, t1) {
-                Some(surrounding_box(acc.unwrap(), bounding_box))
-            } else {
-                None
-            }
+        self.objects.iter().skip(1).try_fold(first, |acc, x| {
+            x.bounding_box(t0, t1)
+                .map(|bounding_box| surrounding_box(acc, bounding_box))
         })
     }
 
-    fn pdf_value(&self, o: Point3, v: Vec3) -> f32 {
+    fn pdf_value(&self, rng: &mut dyn rand::RngCore, o: Point3, v: Vec3) -> f32 {
         let weight = 1.0 / self.objects.len() as f32;
         let mut sum = 0.0;
 
         for object in &self.objects {
-            sum += weight * object.pdf_value(o, v);
+            sum += weight * object.pdf_value(rng, o, v);
         }
 
         sum
@@ -64,7 +67,7 @@ impl Hittable for HittableList {
             return Point3::origin();
         }
 
-        let index = rand::thread_rng().gen_range(0..size);
+        let index = rng.gen_range(0..size);
         self.objects[index].random(rng, o)
     }
 }
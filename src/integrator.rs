@@ -0,0 +1,649 @@
+use crate::prelude::*;
+
+use crate::{
+    bdpt::*, bvh::*, camera::*, framebuffer::*, mlt::*, onb::*, photon::*, render::*, spectrum::*,
+    worlds::*,
+};
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::{
+    any::Any,
+    fmt::{Debug, Display},
+    str::FromStr,
+    sync::Arc,
+};
+
+/// Computes what a camera ray sees.
+pub trait Integrator: Sync + Send + Debug {
+    fn radiance(&self, rng: &mut dyn rand::RngCore, r: &Ray, ctx: &mut SampleContext) -> Color;
+
+    /// Most samples per pixel the renderer should take in one pass, if limited.
+    fn samples_per_pass(&self) -> Option<u32> {
+        None
+    }
+
+    /// Wavelength of a spectral render's camera ray from `u` in `[0, 1)`, or `None` for RGB.
+    fn wavelength(&self, _u: f32, _ctx: &SampleContext) -> Option<f32> {
+        None
+    }
+
+    /// Sets up what all samples of one pass over the image share, for integrators that do
+    /// work up front. The pass's samples find it in `SampleContext::pass`.
+    fn begin_pass(
+        &self,
+        _world: &World,
+        _camera: &Camera,
+        _settings: &RenderSettings,
+    ) -> Option<Box<dyn Any + Send + Sync>> {
+        None
+    }
+}
+
+/// Everything a sample may use besides its ray.
+pub struct SampleContext<'a> {
+    pub world: &'a World,
+    pub camera: &'a Camera,
+    pub settings: &'a RenderSettings,
+    /// Index of the sample among those of its pixel, `RenderSettings::sample_offset` included.
+    pub sample: u32,
+    /// Light found for other pixels; the renderer adds it to the image afterwards.
+    pub splats: Vec<Splat>,
+    /// What `Integrator::begin_pass` returned for the current pass.
+    pub pass: Option<&'a (dyn Any + Send + Sync)>,
+}
+
+/// Global illumination with light sampling, up to `RenderSettings::max_depth` bounces.
+#[derive(Debug)]
+pub struct PathTracer;
+
+impl Integrator for PathTracer {
+    fn radiance(&self, rng: &mut dyn rand::RngCore, r: &Ray, ctx: &mut SampleContext) -> Color {
+        trace_path(rng, r, ctx.world, ctx.settings, u32::MAX)
+    }
+
+    fn wavelength(&self, u: f32, _ctx: &SampleContext) -> Option<f32> {
+        Some(sample_wavelength(u))
+    }
+}
+
+/// Direct lighting at the first diffuse surface, seen through specular ones.
+#[derive(Debug)]
+pub struct DirectLighting;
+
+impl Integrator for DirectLighting {
+    fn radiance(&self, rng: &mut dyn rand::RngCore, r: &Ray, ctx: &mut SampleContext) -> Color {
+        trace_path(rng, r, ctx.world, ctx.settings, 1)
+    }
+
+    fn wavelength(&self, u: f32, _ctx: &SampleContext) -> Option<f32> {
+        Some(sample_wavelength(u))
+    }
+}
+
+/// Radiance along `r` from a path with light sampling and MIS, up to `max_diffuse` diffuse
+/// bounces.
+fn trace_path(
+    rng: &mut dyn rand::RngCore,
+    r: &Ray,
+    world: &World,
+    settings: &RenderSettings,
+    max_diffuse: u32,
+) -> Color {
+    let lights = world.lights();
+    let sample_lights = !lights.objects.is_empty();
+
+    let mut radiance = Color::black();
+    let mut throughput = rgb!(1.0, 1.0, 1.0);
+    let mut ray = *r;
+    // MIS weight of emission found by the last ray, which is 1 unless it was a material
+    // sample that light sampling could also have produced
+    let mut emission_weight = 1.0;
+    let mut diffuse_bounces = 0;
+
+    for depth in 0..settings.max_depth {
+        let rec = match world.world().hit(rng, &ray, 0.001, f32::INFINITY) {
+            Some(rec) => rec,
+            None => {
+                radiance += throughput * settings.background;
+                break;
+            }
+        };
+
+        throughput = throughput
+            * rec
+                .mat_ptr
+                .transmittance(&rec, rec.t * ray.direction().length());
+        radiance += throughput * rec.mat_ptr.emitted(&rec) * emission_weight;
+        if diffuse_bounces == max_diffuse {
+            break;
+        }
+        let mut srec = match rec.mat_ptr.scatter(rng, &ray, &rec) {
+            Some(srec) => srec,
+            None => break,
+        };
+
+        if let Some(specular) = srec.specular_ray {
+            throughput = throughput * srec.attenuation;
+            ray = specular;
+            emission_weight = 1.0;
+        } else {
+            let material_pdf = srec.pdf_ptr.take().unwrap();
+            diffuse_bounces += 1;
+
+            if sample_lights {
+                let shadow = Ray::new(rec.p, lights.random(rng, rec.p), ray.time());
+                let light_pdf = lights.pdf_value(rng, rec.p, shadow.direction());
+                let scattering = rec.mat_ptr.scattering(&ray, &rec, &srec, &shadow);
+
+                if light_pdf > 0.0 && scattering != Color::black() {
+                    if let Some(light) = world.world().hit(rng, &shadow, 0.001, f32::INFINITY) {
+                        let weight =
+                            power_heuristic(light_pdf, material_pdf.value(rng, shadow.direction()));
+                        radiance += throughput
+                            * scattering
+                            * light.mat_ptr.emitted(&light)
+                            * (weight / light_pdf);
+                    }
+                }
+            }
+
+            let scattered = Ray::new(rec.p, material_pdf.generate(rng), ray.time())
+                .with_wavelength(ray.wavelength());
+            let pdf_val = material_pdf.value(rng, scattered.direction());
+            if pdf_val <= 0.0 {
+                break;
+            }
+
+            emission_weight = if sample_lights {
+                power_heuristic(pdf_val, lights.pdf_value(rng, rec.p, scattered.direction()))
+            } else {
+                1.0
+            };
+            throughput =
+                throughput * rec.mat_ptr.scattering(&ray, &rec, &srec, &scattered) / pdf_val;
+            ray = scattered;
+        }
+
+        if depth + 1 >= settings.roulette_depth {
+            let [r, g, b] = throughput.to_array();
+            let survival = r.max(g).max(b).min(0.95);
+            if rng.gen::<f32>() >= survival {
+                break;
+            }
+            throughput /= survival;
+        }
+    }
+
+    radiance
+}
+
+/// Power heuristic weight of a sample with density `pdf` against another strategy's `other`.
+fn power_heuristic(pdf: f32, other: f32) -> f32 {
+    let (a, b) = (pdf * pdf, other * other);
+    a / (a + b)
+}
+
+/// Ambient occlusion at the first hit, counting occluders within `distance` if given.
+#[derive(Debug)]
+pub struct AmbientOcclusion {
+    pub distance: Option<f32>,
+}
+
+impl Integrator for AmbientOcclusion {
+    fn radiance(&self, rng: &mut dyn rand::RngCore, r: &Ray, ctx: &mut SampleContext) -> Color {
+        let rec = match ctx.world.world().hit(rng, r, 0.001, f32::INFINITY) {
+            Some(rec) => rec,
+            None => return Color::black(),
+        };
+
+        // Cosine-weighted, so the fraction of open rays is the cosine-weighted visibility
+        let direction = OrthonormalBasis::from_w(rec.normal).local(random_cosine_direction(rng));
+        let occluder = ctx.world.world().hit(
+            rng,
+            &Ray::new(rec.p, direction, r.time()),
+            0.001,
+            self.distance.unwrap_or(f32::INFINITY),
+        );
+        match occluder {
+            Some(_) => Color::black(),
+            None => rgb!(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+// The debug integrators show a value in [0, 1] as is in 8-bit images, which are encoded with
+// gamma 2, and black where the ray escapes.
+fn display(value: Color) -> Color {
+    value * value
+}
+
+/// The outward surface normal at the first hit, mapped from [-1, 1] to [0, 1].
+#[derive(Debug)]
+pub struct Normals;
+
+impl Integrator for Normals {
+    fn radiance(&self, rng: &mut dyn rand::RngCore, r: &Ray, ctx: &mut SampleContext) -> Color {
+        match ctx.world.world().hit(rng, r, 0.001, f32::INFINITY) {
+            Some(rec) => {
+                let outward = if rec.front_face {
+                    rec.normal
+                } else {
+                    -rec.normal
+                };
+                display(0.5 * (outward + rgb!(1.0, 1.0, 1.0)))
+            }
+            None => Color::black(),
+        }
+    }
+}
+
+/// The texture coordinates of the first hit, as red and green.
+#[derive(Debug)]
+pub struct TextureCoordinates;
+
+impl Integrator for TextureCoordinates {
+    fn radiance(&self, rng: &mut dyn rand::RngCore, r: &Ray, ctx: &mut SampleContext) -> Color {
+        match ctx.world.world().hit(rng, r, 0.001, f32::INFINITY) {
+            Some(rec) => display(Color::new(rec.u, rec.v, 0.0)),
+            None => Color::black(),
+        }
+    }
+}
+
+/// Distance to the first hit, as gray that is white at `far` if given, otherwise raw.
+#[derive(Debug)]
+pub struct Depth {
+    pub far: Option<f32>,
+}
+
+impl Integrator for Depth {
+    fn radiance(&self, rng: &mut dyn rand::RngCore, r: &Ray, ctx: &mut SampleContext) -> Color {
+        let t = match ctx.world.world().hit(rng, r, 0.001, f32::INFINITY) {
+            Some(rec) => rec.t * r.direction().length(),
+            None => return Color::black(),
+        };
+
+        match self.far {
+            Some(far) => {
+                let gray = (t / far).min(1.0);
+                display(Color::new(gray, gray, gray))
+            }
+            None => Color::new(t, t, t),
+        }
+    }
+}
+
+/// A color per material of the first hit, which may change from run to run.
+#[derive(Debug)]
+pub struct MaterialId;
+
+impl Integrator for MaterialId {
+    fn radiance(&self, rng: &mut dyn rand::RngCore, r: &Ray, ctx: &mut SampleContext) -> Color {
+        match ctx.world.world().hit(rng, r, 0.001, f32::INFINITY) {
+            Some(rec) => {
+                let id = Arc::as_ptr(&rec.mat_ptr) as *const () as usize as u64;
+                let [r, g, b, ..] = mix_seed(0, id).to_le_bytes();
+                display(Color::new(r as f32, g as f32, b as f32) / 255.0)
+            }
+            None => Color::black(),
+        }
+    }
+}
+
+/// BVH nodes and objects the camera ray visits, from blue for none to red for `max`.
+#[derive(Debug)]
+pub struct BVHCost {
+    pub max: u32,
+}
+
+impl Integrator for BVHCost {
+    fn radiance(&self, rng: &mut dyn rand::RngCore, r: &Ray, ctx: &mut SampleContext) -> Color {
+        let (_, visits) = count_visits(|| ctx.world.world().hit(rng, r, 0.001, f32::INFINITY));
+        display(heat(visits as f32 / self.max as f32))
+    }
+}
+
+/// The "jet" color map, from blue at 0 through green to red at 1.
+fn heat(t: f32) -> Color {
+    let t = clamp(t, 0.0, 1.0);
+    let channel = |center: f32| clamp(1.5 - (4.0 * t - center).abs(), 0.0, 1.0);
+    Color::new(channel(3.0), channel(2.0), channel(1.0))
+}
+
+/// Which integrator to render with, as stored in checkpoints and written on the command line.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub enum IntegratorDesc {
+    #[default]
+    Path,
+    Direct,
+    Bidirectional,
+    PhotonMapping {
+        photons: u32,
+        radius: Option<f32>,
+    },
+    Metropolis {
+        mutations: u32,
+    },
+    AmbientOcclusion {
+        distance: Option<f32>,
+    },
+    Normals,
+    TextureCoordinates,
+    Depth {
+        far: Option<f32>,
+    },
+    MaterialId,
+    BVHCost {
+        max: u32,
+    },
+}
+
+impl IntegratorDesc {
+    pub fn build(&self) -> Arc<dyn Integrator> {
+        match *self {
+            IntegratorDesc::Path => Arc::new(PathTracer),
+            IntegratorDesc::Direct => Arc::new(DirectLighting),
+            IntegratorDesc::Bidirectional => Arc::new(Bidirectional),
+            IntegratorDesc::PhotonMapping { photons, radius } => {
+                Arc::new(PhotonMapping::new(photons, radius))
+            }
+            IntegratorDesc::Metropolis { mutations } => Arc::new(Metropolis::new(mutations)),
+            IntegratorDesc::AmbientOcclusion { distance } => {
+                Arc::new(AmbientOcclusion { distance })
+            }
+            IntegratorDesc::Normals => Arc::new(Normals),
+            IntegratorDesc::TextureCoordinates => Arc::new(TextureCoordinates),
+            IntegratorDesc::Depth { far } => Arc::new(Depth { far }),
+            IntegratorDesc::MaterialId => Arc::new(MaterialId),
+            IntegratorDesc::BVHCost { max } => Arc::new(BVHCost { max }),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct UnknownIntegrator(String);
+
+impl Display for UnknownIntegrator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "unknown integrator `{}`: expected one of path, direct, bdpt, sppm[:radius], \
+             mlt[:mutations], ao[:distance], normals, uv, \
+             depth[:far], material, bvh[:max]",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for UnknownIntegrator {}
+
+impl FromStr for IntegratorDesc {
+    type Err = UnknownIntegrator;
+
+    /// A name, optionally followed by `:` and a positive parameter.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || UnknownIntegrator(s.to_owned());
+        let lower = s.to_ascii_lowercase();
+        let (name, param) = match lower.split_once(':') {
+            Some((name, param)) => {
+                let param: f32 = param.trim().parse().map_err(|_| err())?;
+                if !(param > 0.0 && param.is_finite()) {
+                    return Err(err());
+                }
+                (name.trim(), Some(param))
+            }
+            None => (lower.trim(), None),
+        };
+
+        match (name, param) {
+            ("path", None) => Ok(IntegratorDesc::Path),
+            ("direct", None) => Ok(IntegratorDesc::Direct),
+            ("bdpt", None) => Ok(IntegratorDesc::Bidirectional),
+            ("sppm", radius) => Ok(IntegratorDesc::PhotonMapping {
+                photons: PhotonMapping::DEFAULT_PHOTONS,
+                radius,
+            }),
+            ("mlt", mutations) => Ok(IntegratorDesc::Metropolis {
+                mutations: mutations.map_or(Metropolis::DEFAULT_MUTATIONS, |m| m.ceil() as u32),
+            }),
+            ("ao", distance) => Ok(IntegratorDesc::AmbientOcclusion { distance }),
+            ("normals", None) => Ok(IntegratorDesc::Normals),
+            ("uv", None) => Ok(IntegratorDesc::TextureCoordinates),
+            ("depth", far) => Ok(IntegratorDesc::Depth { far }),
+            ("material", None) => Ok(IntegratorDesc::MaterialId),
+            ("bvh", max) => Ok(IntegratorDesc::BVHCost {
+                max: max.map_or(64, |max| max.ceil() as u32),
+            }),
+            _ => Err(err()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{hittable_list::*, scene::*, sphere::*};
+
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    /// Mean luminance of a floor lit by a ball of light above it, sampled if `sample_lights`.
+    fn lit_floor(
+        floor: Arc<dyn Material>,
+        integrator: &dyn Integrator,
+        sample_lights: bool,
+    ) -> f32 {
+        let light = Sphere::new(point!(0.0, 3.0, 0.0), 1.0, DiffuseLight::white(4.0));
+        lit_floor_by(light, floor, integrator, sample_lights)
+    }
+
+    /// Like `lit_floor`, with `light` in place of the ball of light over the floor.
+    fn lit_floor_by(
+        light: Arc<dyn Hittable>,
+        floor: Arc<dyn Material>,
+        integrator: &dyn Integrator,
+        sample_lights: bool,
+    ) -> f32 {
+        use crate::color::luminance;
+
+        let mut world = HittableList::new();
+        let mut lights = HittableList::new();
+        world.add(Sphere::new(point!(0.0, -1000.0, 0.0), 1000.0, floor));
+        world.add(light.clone());
+        if sample_lights {
+            lights.add(light);
+        }
+        let world = World::new(world, lights);
+
+        let settings = RenderSettings {
+            background: Color::black(),
+            roulette_depth: 50,
+            ..RenderSettings::default()
+        };
+        let camera = CameraSettings::default().build(1.0);
+        let ctx = &mut SampleContext {
+            world: &world,
+            camera: &camera,
+            settings: &settings,
+            sample: 0,
+            splats: Vec::new(),
+            pass: None,
+        };
+        let rng = &mut SmallRng::seed_from_u64(3);
+        let r = Ray::new(point!(0.0, 1.0, -1.0), vec3!(0.0, -1.0, 1.0), 0.0);
+        let n = 200_000;
+        let sum: f32 = (0..n)
+            .map(|_| luminance(integrator.radiance(rng, &r, ctx)))
+            .sum();
+        sum / n as f32
+    }
+
+    #[test]
+    fn light_sampling_unbiased() {
+        // The sphere's irradiance on the floor is E = pi L (r / d)^2, so the reflected radiance
+        // is albedo L (r / d)^2
+        let floor = || -> Arc<dyn Material> { Lambertian::new_rgb(0.5, 0.5, 0.5) };
+        let expected = 0.5 * 4.0 / 9.0;
+
+        // Nothing reflects light back onto the floor, so direct lighting is everything
+        for &(name, estimate) in &[
+            ("material sampling", lit_floor(floor(), &PathTracer, false)),
+            ("MIS", lit_floor(floor(), &PathTracer, true)),
+            ("direct", lit_floor(floor(), &DirectLighting, true)),
+        ] {
+            assert!(
+                (estimate - expected).abs() < 0.02 * expected,
+                "{}: {} != {}",
+                name,
+                estimate,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn transformed_light_sampling_unbiased() {
+        // The same ball of light, moved out and rotated back into place
+        let light = || -> Arc<dyn Hittable> {
+            let ball = Sphere::new(point!(2.0, 3.0, 0.0), 1.0, DiffuseLight::white(4.0));
+            Translate::new(RotateY::new(ball, 90.0), vec3!(0.0, 0.0, 2.0))
+        };
+        let floor = || -> Arc<dyn Material> { Lambertian::new_rgb(0.5, 0.5, 0.5) };
+        let expected = 0.5 * 4.0 / 9.0;
+
+        for &(name, estimate) in &[
+            ("MIS", lit_floor_by(light(), floor(), &PathTracer, true)),
+            (
+                "direct",
+                lit_floor_by(light(), floor(), &DirectLighting, true),
+            ),
+        ] {
+            assert!(
+                (estimate - expected).abs() < 0.02 * expected,
+                "{}: {} != {}",
+                name,
+                estimate,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn glossy_light_sampling_unbiased() {
+        // Light sampling only agrees with sampling the BSDF if its value and pdf match
+        let floors: [fn() -> Arc<dyn Material>; 2] = [
+            || Metal::measured(Conductor::Gold, 0.8),
+            || Dielectric::rough(1.5, 0.8),
+        ];
+        for floor in &floors {
+            let expected = lit_floor(floor(), &PathTracer, false);
+            let estimate = lit_floor(floor(), &PathTracer, true);
+            assert!(
+                (estimate - expected).abs() < 0.02 * expected,
+                "{:?}: {} != {}",
+                floor(),
+                estimate,
+                expected
+            );
+        }
+
+        // Only the metal reflects everything right away
+        let expected = lit_floor(floors[0](), &PathTracer, false);
+        let direct = lit_floor(floors[0](), &DirectLighting, true);
+        assert!((direct - expected).abs() < 0.02 * expected);
+    }
+
+    #[test]
+    fn glass_absorbs_along_path() {
+        use crate::color::luminance;
+
+        // Straight through the middle of a ball that lets half the light through its diameter,
+        // against a white background. Light reflects off either side with r0 = 0.04 and goes
+        // through the ball once more with every reflection inside, so it comes to
+        // r0 + (1 - r0)² T / (1 - r0 T).
+        let mut world = HittableList::new();
+        world.add(Sphere::new(
+            Point3::origin(),
+            1.0,
+            Dielectric::tinted(Ior::Constant(1.5), 0.0, rgb!(0.5, 0.5, 0.5), 2.0),
+        ));
+        let world = World::new(world, HittableList::new());
+        let (r0, t) = (0.04, 0.5);
+        let expected = r0 + (1.0 - r0) * (1.0 - r0) * t / (1.0 - r0 * t);
+
+        let settings = RenderSettings {
+            background: rgb!(1.0, 1.0, 1.0),
+            roulette_depth: 50,
+            ..RenderSettings::default()
+        };
+        let camera = CameraSettings::default().build(1.0);
+        let ctx = &mut SampleContext {
+            world: &world,
+            camera: &camera,
+            settings: &settings,
+            sample: 0,
+            splats: Vec::new(),
+            pass: None,
+        };
+        let rng = &mut SmallRng::seed_from_u64(0);
+        let r = Ray::new(point!(0.0, 0.0, -5.0), vec3!(0.0, 0.0, 2.0), 0.0);
+        let n = 100_000;
+        let estimate = (0..n)
+            .map(|_| luminance(PathTracer.radiance(rng, &r, ctx)))
+            .sum::<f32>()
+            / n as f32;
+        assert!(
+            (estimate - expected).abs() < 0.01 * expected,
+            "{} != {}",
+            estimate,
+            expected
+        );
+    }
+
+    #[test]
+    fn debug_integrators() {
+        let rng = &mut SmallRng::seed_from_u64(0);
+        let mut objects = HittableList::new();
+        objects.add(Sphere::new(
+            point!(0.0, 0.0, -5.0),
+            1.0,
+            Lambertian::new_rgb(0.5, 0.5, 0.5),
+        ));
+        let world = World::new(objects, HittableList::new());
+        let camera = CameraSettings::default().build(1.0);
+        let settings = RenderSettings::default();
+        let ctx = &mut SampleContext {
+            world: &world,
+            camera: &camera,
+            settings: &settings,
+            sample: 0,
+            splats: Vec::new(),
+            pass: None,
+        };
+        let r = Ray::new(Point3::origin(), vec3!(0.0, 0.0, -1.0), 0.0);
+        let miss = Ray::new(Point3::origin(), vec3!(0.0, 1.0, 0.0), 0.0);
+
+        let mut radiance = |desc: &str, r: &Ray| {
+            let integrator = desc.parse::<IntegratorDesc>().unwrap().build();
+            integrator.radiance(rng, r, ctx)
+        };
+        assert_eq!(radiance("normals", &r), display(rgb!(0.5, 0.5, 1.0)));
+        assert_eq!(radiance("depth", &r), rgb!(4.0, 4.0, 4.0));
+        assert_eq!(radiance("depth:8", &r), display(rgb!(0.5, 0.5, 0.5)));
+        assert_eq!(radiance("ao", &r), rgb!(1.0, 1.0, 1.0));
+        for desc in &["normals", "uv", "depth", "material", "ao"] {
+            assert_eq!(radiance(desc, &miss), Color::black(), "{}", desc);
+        }
+
+        assert_eq!(
+            "AO:2.5".parse::<IntegratorDesc>().unwrap(),
+            IntegratorDesc::AmbientOcclusion {
+                distance: Some(2.5)
+            }
+        );
+        assert!("normals:2".parse::<IntegratorDesc>().is_err());
+        assert!("depth:-1".parse::<IntegratorDesc>().is_err());
+        assert!("whitted".parse::<IntegratorDesc>().is_err());
+    }
+}
@@ -0,0 +1,45 @@
+//! A path tracer following the "Ray Tracing in One Weekend" series.
+//!
+//! Scenes are a [`worlds::World`] of [`hittable::Hittable`] objects plus a
+//! [`camera::Camera`]; [`render::Renderer`] turns them into a
+//! [`framebuffer::Framebuffer`] of linear radiance.
+
+#![allow(clippy::upper_case_acronyms)]
+
+#[macro_use]
+pub mod util;
+
+pub mod aabb;
+pub mod aarect;
+pub mod bdpt;
+pub mod bvh;
+pub mod camera;
+pub mod checkpoint;
+pub mod color;
+pub mod constant_medium;
+pub mod cuboid;
+pub mod framebuffer;
+pub mod hittable;
+pub mod hittable_list;
+pub mod integrator;
+pub mod material;
+pub mod microfacet;
+pub mod mlt;
+pub mod moving_sphere;
+pub mod obj;
+pub mod onb;
+pub mod pdf;
+pub mod perlin;
+pub mod photon;
+pub mod prelude;
+pub mod ray;
+pub mod render;
+pub mod scene;
+pub mod scene_file;
+pub mod spectrum;
+pub mod sphere;
+pub mod texture;
+pub mod tile;
+pub mod triangle;
+pub mod vec3;
+pub mod worlds;
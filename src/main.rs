@@ -1,218 +1,568 @@
-use prelude::*;
-
-use camera::*;
-use color::*;
-use pdf::*;
-use worlds::*;
-
-use indicatif::ParallelProgressIterator;
-use rand::Rng;
-use rayon::prelude::*;
-use std::sync::Arc;
-
-#[macro_use]
-mod util;
-
-mod aabb;
-mod aarect;
-mod bvh;
-mod camera;
-mod color;
-mod constant_medium;
-mod cuboid;
-mod hittable;
-mod hittable_list;
-mod material;
-mod moving_sphere;
-mod onb;
-mod pdf;
-mod perlin;
-mod prelude;
-mod ray;
-mod sphere;
-mod texture;
-mod vec3;
-mod worlds;
+use raytracer::{
+    checkpoint::*, framebuffer::*, integrator::*, prelude::*, render::*, scene::*, tile::*,
+};
+
+use indicatif::{ProgressBar, ProgressStyle};
+use std::{
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+use structopt::StructOpt;
 
 #[global_allocator]
 static GLOBAL: mimallocator::Mimalloc = mimallocator::Mimalloc;
 
-fn ray_color(
-    rng: &mut impl Rng,
-    r: &Ray,
-    background: Color,
-    world: Arc<World>,
-    depth: u32,
-) -> Color {
-    if depth == 0 {
-        return Color::black();
-    }
-
-    if let Some(rec) = world.world().hit(r, 0.001, f32::INFINITY) {
-        let emitted = rec.mat_ptr.emitted(&rec);
-        if let Some(ScatterRecord {
-            specular_ray,
-            attenuation,
-            pdf_ptr,
-        }) = rec.mat_ptr.scatter(rng, r, &rec)
-        {
-            if let Some(specular) = specular_ray {
-                return attenuation * ray_color(rng, &specular, background, world, depth - 1);
-            }
+/// Renders one of the built-in scenes or a scene file to an image file.
+#[derive(Debug, StructOpt)]
+#[structopt(name = "raytracer")]
+struct Opt {
+    #[structopt(subcommand)]
+    command: Option<Command>,
 
-            let p: Box<dyn PDF>;
-            if world.lights().objects.is_empty() {
-                p = pdf_ptr.unwrap();
-            } else {
-                let light_pdf = HittablePDF::new(world.lights(), rec.p);
-                p = Box::new(MixturePDF::new(light_pdf, pdf_ptr.unwrap()));
-            }
+    /// Built-in scene name or path to a scene file [default: two_spheres]
+    #[structopt(short, long)]
+    scene: Option<String>,
 
-            let scattered = Ray::new(rec.p, p.generate(rng), r.time());
-            let pdf_val = p.value(scattered.direction());
+    /// Image width in pixels (defaults to the scene's aspect ratio)
+    #[structopt(long, parse(try_from_str = parse_positive))]
+    width: Option<u32>,
 
-            emitted
-                + attenuation
-                    * rec.mat_ptr.scattering_pdf(r, &rec, &scattered)
-                    * ray_color(rng, &scattered, background, world.clone(), depth - 1)
-                    / pdf_val
-        } else {
-            emitted
-        }
+    /// Image height in pixels
+    #[structopt(long, parse(try_from_str = parse_positive))]
+    height: Option<u32>,
+
+    /// Samples per pixel (the most per pixel with `--adaptive`); when resuming, the new total
+    #[structopt(long, parse(try_from_str = parse_positive))]
+    spp: Option<u32>,
+
+    /// Maximum number of bounces per path [default: 50]
+    #[structopt(long, parse(try_from_str = parse_positive))]
+    max_depth: Option<u32>,
+
+    /// Bounces before paths may be terminated at random by Russian roulette [default: 3]
+    #[structopt(long)]
+    roulette_depth: Option<u32>,
+
+    /// What to render: path, direct (lighting only), bdpt (bidirectional), sppm[:radius]
+    /// (progressive photon mapping), mlt[:mutations] (Metropolis, mutations per sample),
+    /// ao[:distance] (ambient occlusion), or for debugging normals, uv, depth[:far], material
+    /// or bvh[:max] (traversal cost heatmap) [default: path]
+    #[structopt(long)]
+    integrator: Option<IntegratorDesc>,
+
+    /// Trace one wavelength per sample instead of RGB, so glass with a Cauchy or Sellmeier
+    /// index of refraction disperses light (path, direct, bdpt, sppm and mlt)
+    #[structopt(long)]
+    spectral: bool,
+
+    /// Samples per pixel added by each progressive pass
+    #[structopt(long, default_value = "16", parse(try_from_str = parse_positive))]
+    pass_spp: u32,
+
+    /// Seconds between writes of the partial image (and checkpoint) during the render
+    #[structopt(long, default_value = "30", parse(try_from_str = parse_non_negative))]
+    preview_interval: f32,
+
+    /// Only render the pixels between two corners, as `x0,y0,x1,y1` from the top left;
+    /// coordinates with a decimal point are fractions of the image size
+    #[structopt(long, parse(try_from_str = parse_crop))]
+    crop: Option<CropWindow>,
+
+    /// Write just the crop window, rather than the full image with black around it
+    #[structopt(long)]
+    cropped: bool,
+
+    /// Size of the square tiles handed out to render threads, in pixels [default: 32]
+    #[structopt(long, parse(try_from_str = parse_positive))]
+    tile_size: Option<u32>,
+
+    /// Order in which tiles are rendered: scanline, spiral or hilbert
+    #[structopt(long, default_value = "spiral")]
+    tile_order: TileOrder,
+
+    /// Only render every n-th tile starting from the k-th, as `k/n`, to split the render
+    /// between n processes; combine their checkpoints with `merge`
+    #[structopt(long, requires = "checkpoint")]
+    tiles: Option<TileShare>,
+
+    /// Start each pixel at this sample index, to split the render between processes by sample
+    /// range; combine their checkpoints with `merge`
+    #[structopt(long, requires = "checkpoint")]
+    spp_offset: Option<u32>,
+
+    /// Keep sampling each pixel until its relative error is below this, up to `--spp` samples
+    #[structopt(long, parse(try_from_str = parse_positive))]
+    adaptive: Option<f32>,
+
+    /// Samples every pixel takes before adaptive sampling may stop it [default: 16]
+    #[structopt(long, parse(try_from_str = parse_positive))]
+    min_spp: Option<u32>,
+
+    /// Write the number of samples taken per pixel as a grayscale image, white at `--spp`
+    #[structopt(long, parse(from_os_str))]
+    sample_map: Option<PathBuf>,
+
+    /// Save the accumulated samples here along with each preview, to resume the render later
+    #[structopt(long, parse(from_os_str))]
+    checkpoint: Option<PathBuf>,
+
+    /// Continue the render saved in a checkpoint, which keeps being updated unless
+    /// `--checkpoint` names another file
+    #[structopt(
+        long,
+        parse(from_os_str),
+        conflicts_with_all = &[
+            "scene", "width", "height", "max-depth", "roulette-depth", "integrator", "spectral",
+            "seed", "look-from", "look-at", "vfov", "aperture", "focus-dist",
+            "tile-size", "tiles", "spp-offset",
+        ]
+    )]
+    resume: Option<PathBuf>,
+
+    /// Output image; the format is chosen by extension (png, jpg, ppm, bmp, tga, hdr, pfm)
+    #[structopt(short, long, default_value = "image.png", parse(from_os_str))]
+    output: PathBuf,
+
+    /// Number of render threads (defaults to the number of CPUs)
+    #[structopt(short = "j", long, parse(try_from_str = parse_positive))]
+    threads: Option<usize>,
+
+    /// Seed for scene generation and sampling (random if omitted)
+    #[structopt(long)]
+    seed: Option<u64>,
+
+    /// Camera position, as `x,y,z`
+    #[structopt(long, parse(try_from_str = parse_vec3))]
+    look_from: Option<Point3>,
+
+    /// Camera target, as `x,y,z`
+    #[structopt(long, parse(try_from_str = parse_vec3))]
+    look_at: Option<Point3>,
+
+    /// Vertical field of view in degrees
+    #[structopt(long, parse(try_from_str = parse_vfov))]
+    vfov: Option<f32>,
+
+    /// Lens aperture diameter
+    #[structopt(long, parse(try_from_str = parse_non_negative))]
+    aperture: Option<f32>,
+
+    /// Distance to the plane in focus
+    #[structopt(long, parse(try_from_str = parse_positive))]
+    focus_dist: Option<f32>,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Combines the checkpoints of a render split with `--tiles` or `--spp-offset` into one
+    /// image, averaging each pixel over the samples of all parts
+    Merge {
+        /// Checkpoints of the parts
+        #[structopt(required = true, parse(from_os_str))]
+        parts: Vec<PathBuf>,
+
+        /// Output image; the format is chosen by extension (png, jpg, ppm, bmp, tga, hdr, pfm)
+        #[structopt(short, long, default_value = "image.png", parse(from_os_str))]
+        output: PathBuf,
+
+        /// Also save the merged samples as a checkpoint, to resume or merge further
+        #[structopt(long, parse(from_os_str))]
+        checkpoint: Option<PathBuf>,
+    },
+}
+
+fn parse_positive<T>(s: &str) -> Result<T, String>
+where
+    T: std::str::FromStr + PartialOrd + Default,
+    T::Err: std::fmt::Display,
+{
+    let x = s.parse::<T>().map_err(|e| e.to_string())?;
+    if x > T::default() {
+        Ok(x)
     } else {
-        background
+        Err(format!("`{}` must be greater than 0", s))
     }
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Image
-    let mut aspect_ratio = 16.0 / 9.0;
-    let mut image_height: u32 = 300;
-    const MAX_DEPTH: u32 = 50;
-    let mut samples_per_pixel = 100;
+fn parse_non_negative(s: &str) -> Result<f32, String> {
+    let x = s.parse::<f32>().map_err(|e| e.to_string())?;
+    if x >= 0.0 {
+        Ok(x)
+    } else {
+        Err(format!("`{}` must not be negative", s))
+    }
+}
 
-    // World
-    let scene = std::env::var("SCENE")
-        .ok()
-        .and_then(|x| x.parse::<i32>().ok())
-        .unwrap_or(2);
-    let world;
+fn parse_vfov(s: &str) -> Result<f32, String> {
+    let x = s.parse::<f32>().map_err(|e| e.to_string())?;
+    if x > 0.0 && x < 180.0 {
+        Ok(x)
+    } else {
+        Err(format!("`{}` must be between 0 and 180 degrees", s))
+    }
+}
 
-    // Camera
-    let mut look_from = point!(13.0, 2.0, 3.0);
-    let mut look_at = Point3::origin();
+fn parse_vec3(s: &str) -> Result<Vec3, String> {
+    let e = s
+        .split(',')
+        .map(|x| {
+            x.trim()
+                .parse::<f32>()
+                .map_err(|e| format!("`{}`: {}", x, e))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
 
-    let vup = vec3!(0.0, 1.0, 0.0);
-    let focus_dist = 10.0;
-    let mut aperture = 0.0;
-    let mut vfov = 20.0;
-    let mut background = rgb!(0.70, 0.80, 1.00);
+    match e.as_slice() {
+        &[x, y, z] => Ok(Vec3::new(x, y, z)),
+        _ => Err(format!("`{}` must have three components, as `x,y,z`", s)),
+    }
+}
 
-    match scene {
-        1 => {
-            world = World::random_scene();
+fn parse_crop(s: &str) -> Result<CropWindow, String> {
+    let parts: Vec<&str> = s.split(',').map(str::trim).collect();
+    if parts.len() != 4 {
+        return Err(format!(
+            "`{}` must have four coordinates, as `x0,y0,x1,y1`",
+            s
+        ));
+    }
 
-            aperture = 0.1;
+    let crop = if parts.iter().any(|x| x.contains('.')) {
+        let e = parts
+            .iter()
+            .map(|x| x.parse::<f32>().map_err(|e| format!("`{}`: {}", x, e)))
+            .collect::<Result<Vec<_>, _>>()?;
+        if e.iter().any(|x| !(0.0..=1.0).contains(x)) {
+            return Err(format!("`{}`: fractions must be between 0 and 1", s));
+        }
+        CropWindow::Normalized {
+            min: (e[0], e[1]),
+            max: (e[2], e[3]),
         }
-        2 => {
-            world = World::two_spheres();
+    } else {
+        let e = parts
+            .iter()
+            .map(|x| x.parse::<u32>().map_err(|e| format!("`{}`: {}", x, e)))
+            .collect::<Result<Vec<_>, _>>()?;
+        CropWindow::Pixels {
+            min: (e[0], e[1]),
+            max: (e[2], e[3]),
+        }
+    };
+
+    match crop {
+        CropWindow::Pixels { min, max } if min.0 < max.0 && min.1 < max.1 => Ok(crop),
+        CropWindow::Normalized { min, max } if min.0 < max.0 && min.1 < max.1 => Ok(crop),
+        _ => Err(format!(
+            "`{}`: the first corner must be above and left of the second",
+            s
+        )),
+    }
+}
+
+fn load_scene(name: &str, seed: u64) -> Result<Scene, Box<dyn std::error::Error>> {
+    if let Some(scene) = Scene::builtin(name, seed) {
+        return Ok(scene);
+    }
+
+    if std::path::Path::new(name).is_file() {
+        return Ok(Scene::load(name)?);
+    }
+
+    Err(format!(
+        "unknown scene `{}`: expected a scene file or one of {}",
+        name,
+        BUILTIN_SCENES.join(", ")
+    )
+    .into())
+}
+
+fn main() {
+    if let Err(e) = run(Opt::from_args()) {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run(opt: Opt) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(threads) = opt.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()?;
+    }
+
+    if let Some(Command::Merge {
+        parts,
+        output,
+        checkpoint,
+    }) = &opt.command
+    {
+        return merge(parts, output, checkpoint.as_ref());
+    }
+
+    // Fail on a bad output path before spending time on the render
+    OutputFormat::from_path(&opt.output)?;
+    if let Some(path) = &opt.sample_map {
+        if !matches!(OutputFormat::from_path(path)?, OutputFormat::Ldr(_)) {
+            return Err("the sample map must be an 8-bit image format".into());
         }
-        3 => {
-            world = World::two_perlin_spheres();
+    }
+
+    let (mut scene, header, mut accumulator) = match &opt.resume {
+        Some(path) => {
+            let Checkpoint {
+                header,
+                accumulator,
+            } = Checkpoint::load(path)?;
+
+            let mut scene = load_scene(&header.scene, header.seed)?;
+            scene.image = header.image;
+            scene.camera = header.camera;
+
+            (scene, header, accumulator)
         }
-        4 => {
-            world = World::earth();
+        None => {
+            let (scene, header) = new_render(&opt)?;
+            let accumulator = Accumulator::new(scene.image.image_width(), scene.image.image_height);
+
+            (scene, header, accumulator)
         }
-        5 => {
-            world = World::simple_light();
+    };
+    let mut header = header;
+    if let Some(spp) = opt.spp {
+        scene.image.samples_per_pixel = spp;
+    }
+    if opt.crop.is_some() {
+        scene.image.crop = opt.crop;
+    }
+    header.image.samples_per_pixel = scene.image.samples_per_pixel;
+    header.image.crop = scene.image.crop;
 
-            background = Color::black();
-            look_from = point!(26.0, 3.0, 6.0);
-            look_at = point!(0.0, 2.0, 0.0);
-            // samples_per_pixel = 400;
+    if let Some(max_error) = opt.adaptive {
+        header.adaptive = Some(AdaptiveSettings {
+            max_error,
+            ..header.adaptive.unwrap_or_default()
+        });
+    }
+    match &mut header.adaptive {
+        Some(adaptive) => {
+            adaptive.max_samples = scene.image.samples_per_pixel;
+            adaptive.min_samples = opt.min_spp.unwrap_or(adaptive.min_samples);
+            if adaptive.min_samples > adaptive.max_samples {
+                return Err(format!(
+                    "--min-spp {} is more than the {} samples per pixel allowed",
+                    adaptive.min_samples, adaptive.max_samples
+                )
+                .into());
+            }
         }
-        6 => {
-            world = World::cornell_box();
-
-            aspect_ratio = 1.0;
-            image_height = 600;
-            background = Color::black();
-            look_from = point!(278.0, 278.0, -800.0);
-            look_at = point!(278.0, 278.0, 0.0);
-            vfov = 40.0;
+        None if opt.min_spp.is_some() => {
+            return Err("--min-spp only applies to adaptive renders".into());
         }
-        7 => {
-            world = World::cornell_smoke();
-
-            aspect_ratio = 1.0;
-            image_height = 600;
-            // samples_per_pixel = 1000;
-            background = Color::black();
-            look_from = point!(278.0, 278.0, -800.0);
-            look_at = point!(278.0, 278.0, 0.0);
-            vfov = 40.0;
+        None => {}
+    }
+
+    let settings = RenderSettings {
+        max_depth: header.max_depth,
+        roulette_depth: header.roulette_depth,
+        integrator: header.integrator,
+        spectral: header.spectral,
+        seed: header.seed,
+        tile_size: header.tile_size,
+        tile_order: opt.tile_order,
+        share: header.share,
+        sample_offset: header.sample_offset,
+        ..RenderSettings::for_scene(&scene)
+    };
+    let region = settings.region();
+    if region.area() == 0 {
+        return Err("the crop window does not cover any pixel of the image".into());
+    }
+    let tiles = settings.tiles();
+    if tiles.is_empty() {
+        return Err(format!(
+            "the image has fewer than {} tiles to share",
+            settings.share.map_or(0, |share| share.index + 1)
+        )
+        .into());
+    }
+    let renderer = Renderer::new(settings);
+    let cam = scene.camera.build(scene.image.aspect_ratio);
+
+    let checkpoint_path = opt.checkpoint.as_ref().or(opt.resume.as_ref());
+    let save = |accumulator: &Accumulator| -> Result<(), Box<dyn std::error::Error>> {
+        let image = accumulator.resolve();
+        let mut sample_map = accumulator.sample_map(settings.samples_per_pixel);
+        if opt.cropped {
+            image.crop(region).save(&opt.output)?;
+            sample_map = image::imageops::crop(
+                &mut sample_map,
+                region.x,
+                region.y,
+                region.width,
+                region.height,
+            )
+            .to_image();
+        } else {
+            image.save(&opt.output)?;
         }
-        _ => {
-            world = World::final_scene();
-
-            aspect_ratio = 1.0;
-            image_height = 800;
-            // samples_per_pixel = 10_000;
-            samples_per_pixel = 10;
-            background = Color::black();
-            look_from = point!(478.0, 278.0, -600.0);
-            look_at = point!(278.0, 278.0, 0.0);
-            vfov = 40.0;
+        if let Some(path) = &opt.sample_map {
+            sample_map.save(path)?;
+        }
+        if let Some(path) = checkpoint_path {
+            Checkpoint {
+                header: header.clone(),
+                accumulator: accumulator.clone(),
+            }
+            .save(path)?;
         }
+        Ok(())
     };
 
-    let world = Arc::new(world);
+    // Progressive passes, with a partial image every `preview_interval`
+    let target = settings.samples_per_pixel;
+    let progress = ProgressBar::new(0).with_style(
+        ProgressStyle::default_bar()
+            .template("[{elapsed_precise}] {wide_bar} {pos}/{len} tiles {msg}"),
+    );
+    let renderer = renderer.with_progress(progress.clone());
+
+    let interval = Duration::from_secs_f32(opt.preview_interval);
+    let mut last_preview = Instant::now();
+    loop {
+        let done = match &header.adaptive {
+            Some(adaptive) => {
+                let active = renderer.render_adaptive_pass(
+                    &scene.world,
+                    &cam,
+                    &mut accumulator,
+                    opt.pass_spp,
+                    adaptive,
+                );
+                progress.set_message(&format!("({} pixels active)", active));
+                active == 0
+            }
+            None => {
+                let done = tiles
+                    .iter()
+                    .map(|&tile| accumulator.min_samples_in(tile))
+                    .min()
+                    .unwrap_or(target);
+                let remaining = target.saturating_sub(done);
+                if remaining > 0 {
+                    let samples = opt.pass_spp.min(remaining);
+                    progress.set_message(&format!(
+                        "({}/{} spp)",
+                        target - remaining + samples,
+                        target
+                    ));
+                    renderer.render_pass(&scene.world, &cam, &mut accumulator, samples);
+                }
+                remaining == 0
+            }
+        };
+        if done {
+            break;
+        }
+
+        if last_preview.elapsed() >= interval {
+            save(&accumulator)?;
+            last_preview = Instant::now();
+        }
+    }
+    progress.finish();
 
-    let image_width = (image_height as f32 * aspect_ratio) as u32;
+    save(&accumulator)
+}
 
-    let cam = Camera::new(
-        look_from,
-        look_at,
-        vup,
-        vfov,
-        aspect_ratio,
-        aperture,
-        focus_dist,
-        0.0,
-        1.0,
-    );
+fn merge(
+    parts: &[PathBuf],
+    output: &PathBuf,
+    checkpoint: Option<&PathBuf>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    OutputFormat::from_path(output)?;
 
-    // Render
-    println!("P3\n{} {}\n255", image_width, image_height);
-
-    let result: Vec<String> = (0..image_height)
-        .into_par_iter()
-        .rev()
-        .progress_count(image_height.into())
-        .map(|j| {
-            (0..image_width)
-                .into_par_iter()
-                .map(|i| {
-                    let mut pixel_color = Color::black();
-                    let mut rng = rand::thread_rng();
-
-                    for _ in 0..samples_per_pixel {
-                        let u = (i as f32 + rng.gen::<f32>()) / (image_width - 1) as f32;
-                        let v = (j as f32 + rng.gen::<f32>()) / (image_height - 1) as f32;
-
-                        let r = cam.get_ray(&mut rng, u, v);
-                        pixel_color +=
-                            ray_color(&mut rng, &r, background, world.clone(), MAX_DEPTH);
-                    }
-
-                    let mut buffer = String::new();
-                    write_color(&mut buffer, pixel_color, samples_per_pixel).unwrap();
-                    buffer
-                })
-                .collect()
-        })
-        .collect();
-    print!("{}", result.join(""));
+    let mut merged = Checkpoint::load(&parts[0])?;
+    for path in &parts[1..] {
+        merged
+            .merge(&Checkpoint::load(path)?)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+    }
+
+    merged.accumulator.resolve().save(output)?;
+    if let Some(path) = checkpoint {
+        merged.save(path)?;
+    }
 
     Ok(())
 }
+
+/// Sets up a fresh render from the command line options.
+fn new_render(opt: &Opt) -> Result<(Scene, CheckpointHeader), Box<dyn std::error::Error>> {
+    let seed = opt.seed.unwrap_or_else(rand::random);
+    if opt.seed.is_none() {
+        eprintln!("Using seed {}", seed);
+    }
+
+    // World
+    let name = opt.scene.as_deref().unwrap_or("two_spheres");
+    let mut scene = load_scene(name, seed)?;
+
+    // Image
+    if let Some(height) = opt.height {
+        scene.image.image_height = height;
+    }
+    if let Some(width) = opt.width {
+        if opt.height.is_none() {
+            scene.image.image_height =
+                ((width as f32 / scene.image.aspect_ratio).round() as u32).max(1);
+        }
+        scene.image.aspect_ratio = width as f32 / scene.image.image_height as f32;
+    }
+
+    let (width, height) = (scene.image.image_width(), scene.image.image_height);
+    if width < 2 || height < 2 {
+        return Err(format!(
+            "image must be at least 2x2 pixels, got {}x{}",
+            width, height
+        )
+        .into());
+    }
+
+    // Camera
+    let camera = &mut scene.camera;
+    camera.look_from = opt.look_from.unwrap_or(camera.look_from);
+    camera.look_at = opt.look_at.unwrap_or(camera.look_at);
+    camera.vfov = opt.vfov.unwrap_or(camera.vfov);
+    camera.aperture = opt.aperture.unwrap_or(camera.aperture);
+    camera.focus_dist = opt.focus_dist.unwrap_or(camera.focus_dist);
+    if (camera.look_from - camera.look_at).length_squared() == 0.0 {
+        return Err("camera position and target must differ".into());
+    }
+
+    // Scene files are resumed by absolute path, in case the working directory changes
+    let scene_name = if BUILTIN_SCENES.contains(&name) {
+        name.to_owned()
+    } else {
+        std::fs::canonicalize(name)?.to_string_lossy().into_owned()
+    };
+    let header = CheckpointHeader {
+        scene: scene_name,
+        seed,
+        image: scene.image,
+        camera: scene.camera,
+        max_depth: opt.max_depth.unwrap_or(50),
+        roulette_depth: opt
+            .roulette_depth
+            .unwrap_or(RenderSettings::default().roulette_depth),
+        integrator: opt.integrator.unwrap_or_default(),
+        spectral: opt.spectral,
+        adaptive: None,
+        tile_size: opt.tile_size.unwrap_or(RenderSettings::default().tile_size),
+        share: opt.tiles,
+        sample_offset: opt.spp_offset.unwrap_or(0),
+        merged: Vec::new(),
+    };
+
+    Ok((scene, header))
+}
@@ -1,7 +1,9 @@
 use crate::prelude::*;
 
-use crate::pdf::*;
+use crate::{color::luminance, microfacet::*, onb::*, pdf::*};
 
+use rand::Rng;
+use serde::{Deserialize, Serialize};
 use std::{fmt::Debug, sync::Arc};
 
 pub trait Material: Sync + Send + Debug {
@@ -16,9 +18,23 @@ pub trait Material: Sync + Send + Debug {
     fn scattering_pdf(&self, _r_in: &Ray, _rec: &HitRecord, _scattered: &Ray) -> f32 {
         0.0
     }
+    /// BSDF times the cosine for light arriving along `scattered` and leaving along `r_in`.
+    fn scattering(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        srec: &ScatterRecord,
+        scattered: &Ray,
+    ) -> Color {
+        srec.attenuation * self.scattering_pdf(r_in, rec, scattered)
+    }
     fn emitted(&self, _rec: &HitRecord) -> Color {
         Color::black()
     }
+    /// Fraction of light left after travelling `distance` inside the material to reach `rec`.
+    fn transmittance(&self, _rec: &HitRecord, _distance: f32) -> Color {
+        rgb!(1.0, 1.0, 1.0)
+    }
 }
 
 pub struct ScatterRecord {
@@ -68,51 +84,242 @@ impl Material for Lambertian {
 }
 
 // Metal
+/// A conductor with microfacets following the GGX distribution. Roughness 0 is a mirror.
 #[derive(Debug)]
 pub struct Metal {
-    pub albedo: Color,
-    pub fuzz: f32,
+    /// Index of refraction, per channel
+    pub eta: Color,
+    /// Extinction coefficient, per channel
+    pub k: Color,
+    /// Microfacet normals, around the axes of `OrthonormalBasis::from_w` of the normal
+    pub distribution: TrowbridgeReitz,
 }
 
 impl Metal {
-    pub fn new(albedo: Color, fuzz: f32) -> Arc<Self> {
-        Arc::new(Self { albedo, fuzz })
+    pub fn new(albedo: Color, roughness: f32) -> Arc<Self> {
+        let (eta, k) = Self::ior_for(albedo);
+        Self::from_ior(eta, k, roughness, roughness)
+    }
+
+    pub fn new_rgbf(r: f32, g: f32, b: f32, roughness: f32) -> Arc<Self> {
+        Self::new(Color::new(r, g, b), roughness)
+    }
+
+    /// A metal with its own optical constants and roughness along and across its brushing.
+    pub fn from_ior(eta: Color, k: Color, roughness_u: f32, roughness_v: f32) -> Arc<Self> {
+        Arc::new(Self {
+            eta,
+            k,
+            distribution: TrowbridgeReitz::from_roughness(roughness_u, roughness_v),
+        })
     }
 
-    pub fn new_rgbf(r: f32, g: f32, b: f32, fuzz: f32) -> Arc<Self> {
-        Self::new(Color::new(r, g, b), fuzz)
+    pub fn measured(conductor: Conductor, roughness: f32) -> Arc<Self> {
+        let (eta, k) = conductor.ior();
+        Self::from_ior(eta, k, roughness, roughness)
+    }
+
+    /// Optical constants of a made-up metal reflecting `albedo` head-on.
+    pub fn ior_for(albedo: Color) -> (Color, Color) {
+        let [r, g, b] = albedo.to_array();
+        let k = |reflectance: f32| {
+            let reflectance = clamp(reflectance, 0.0, 0.999);
+            2.0 * (reflectance / (1.0 - reflectance)).sqrt()
+        };
+        (rgb!(1.0, 1.0, 1.0), Color::new(k(r), k(g), k(b)))
     }
 }
 
 impl Material for Metal {
     fn scatter(
         &self,
-        rng: &mut dyn rand::RngCore,
+        _rng: &mut dyn rand::RngCore,
         r_in: &Ray,
         rec: &HitRecord,
     ) -> Option<ScatterRecord> {
-        let reflected = reflect(r_in.direction().unit_vector(), rec.normal);
+        let unit_direction = r_in.direction().unit_vector();
+        if self.distribution.is_smooth() {
+            let cos = -unit_direction.dot(rec.normal);
+            return Some(ScatterRecord {
+                specular_ray: Some(
+                    Ray::new(rec.p, reflect(unit_direction, rec.normal), r_in.time())
+                        .with_wavelength(r_in.wavelength()),
+                ),
+                attenuation: fresnel_conductor(cos, self.eta, self.k),
+                pdf_ptr: None,
+            });
+        }
+
         Some(ScatterRecord {
-            specular_ray: Some(Ray::new(
-                rec.p,
-                reflected + self.fuzz * random_in_unit_sphere(rng),
-                0.0,
+            specular_ray: None,
+            attenuation: rgb!(1.0, 1.0, 1.0),
+            pdf_ptr: Some(MicrofacetPDF::new(
+                rec.normal,
+                -unit_direction,
+                self.distribution,
             )),
-            attenuation: self.albedo,
-            pdf_ptr: None,
         })
     }
+
+    /// The BRDF times the cosine, but without the Fresnel term, which `scattering` adds.
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f32 {
+        let (wo, wi) = local_directions(rec, r_in, scattered);
+        if wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return 0.0;
+        }
+        let h = (wo + wi).unit_vector();
+        self.distribution.d(h) * self.distribution.g(wo, wi) / (4.0 * wo.z())
+    }
+
+    fn scattering(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        srec: &ScatterRecord,
+        scattered: &Ray,
+    ) -> Color {
+        let (wo, wi) = local_directions(rec, r_in, scattered);
+        let h = (wo + wi).unit_vector();
+        srec.attenuation
+            * fresnel_conductor(wo.dot(h), self.eta, self.k)
+            * self.scattering_pdf(r_in, rec, scattered)
+    }
+}
+
+/// `r_in` reversed and `scattered`, in the frame microfacet distributions are oriented along.
+fn local_directions(rec: &HitRecord, r_in: &Ray, scattered: &Ray) -> (Vec3, Vec3) {
+    let uvw = OrthonormalBasis::from_w(rec.normal);
+    (
+        uvw.to_local(-r_in.direction().unit_vector()),
+        uvw.to_local(scattered.direction().unit_vector()),
+    )
+}
+
+/// Metals with measured optical constants, for `Metal::measured`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Conductor {
+    Aluminium,
+    Chromium,
+    Copper,
+    Gold,
+    Iron,
+    Silver,
+}
+
+impl Conductor {
+    /// Index of refraction and extinction coefficient for red, green and blue.
+    pub fn ior(self) -> (Color, Color) {
+        let (eta, k) = match self {
+            Conductor::Aluminium => ([1.657, 0.880, 0.521], [9.224, 6.270, 4.837]),
+            Conductor::Chromium => ([4.370, 2.917, 1.655], [5.206, 4.231, 3.755]),
+            Conductor::Copper => ([0.200, 0.924, 1.102], [3.913, 2.453, 2.142]),
+            Conductor::Gold => ([0.143, 0.375, 1.442], [3.983, 2.386, 1.603]),
+            Conductor::Iron => ([2.911, 2.950, 2.585], [3.089, 2.932, 2.767]),
+            Conductor::Silver => ([0.155, 0.117, 0.138], [4.828, 3.122, 2.147]),
+        };
+        (Color::from_array(eta), Color::from_array(k))
+    }
+}
+
+/// Index of refraction of a dielectric, which may vary with wavelength.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Ior {
+    Constant(f32),
+    /// Cauchy's equation, `a + b / λ²` for λ in micrometers
+    Cauchy {
+        a: f32,
+        b: f32,
+    },
+    /// The Sellmeier equation, `n² = 1 + Σ b λ² / (λ² - c)` for λ in micrometers
+    Sellmeier {
+        b: [f32; 3],
+        c: [f32; 3],
+    },
+}
+
+impl Ior {
+    /// Wavelength of the index of RGB renders, in nanometers
+    pub const D_LINE: f32 = 587.6;
+
+    /// Schott N-BK7, a common optical glass
+    pub const BK7: Ior = Ior::Sellmeier {
+        b: [1.039_612, 0.231_792_34, 1.010_469_5],
+        c: [0.006_000_699, 0.020_017_914, 103.560_65],
+    };
+
+    /// Diamond, which disperses light about twice as much as glass
+    pub const DIAMOND: Ior = Ior::Sellmeier {
+        b: [4.3356, 0.3306, 0.0],
+        c: [0.011_236, 0.030_625, 0.0],
+    };
+
+    /// The index at `wavelength` in nanometers, or at `D_LINE` for RGB rays.
+    pub fn at(&self, wavelength: Option<f32>) -> f32 {
+        let lambda = wavelength.unwrap_or(Self::D_LINE) / 1000.0;
+        let lambda2 = lambda * lambda;
+        match *self {
+            Ior::Constant(n) => n,
+            Ior::Cauchy { a, b } => a + b / lambda2,
+            Ior::Sellmeier { b, c } => {
+                let sum: f32 = b
+                    .iter()
+                    .zip(&c)
+                    .map(|(b, c)| b * lambda2 / (lambda2 - c))
+                    .sum();
+                (1.0 + sum).sqrt()
+            }
+        }
+    }
 }
 
 // Dielectric
+/// Glass and the like, with GGX microfacets when rough (Walter et al. 2007). Light inside is
+/// absorbed following the Beer-Lambert law.
 #[derive(Debug)]
 pub struct Dielectric {
-    pub ref_idx: f32,
+    pub ior: Ior,
+    /// Microfacet normals, around the axes of `OrthonormalBasis::from_w` of the normal
+    pub distribution: TrowbridgeReitz,
+    /// Fraction of light absorbed per unit length inside, per channel
+    pub absorption: Color,
 }
 
 impl Dielectric {
     pub fn new(ref_idx: f32) -> Arc<Self> {
-        Arc::new(Self { ref_idx })
+        Self::rough(ref_idx, 0.0)
+    }
+
+    /// Frosted glass, for roughness in `0..=1`.
+    pub fn rough(ref_idx: f32, roughness: f32) -> Arc<Self> {
+        Self::tinted(Ior::Constant(ref_idx), roughness, rgb!(1.0, 1.0, 1.0), 1.0)
+    }
+
+    /// Colored glass, letting through `transmittance` of the light after `distance` inside it.
+    /// Each channel of `transmittance` is in `(0, 1]` and `distance` is positive.
+    pub fn tinted(ior: Ior, roughness: f32, transmittance: Color, distance: f32) -> Arc<Self> {
+        let [r, g, b] = transmittance.to_array();
+        assert!(
+            [r, g, b].iter().all(|&t| t > 0.0 && t <= 1.0),
+            "transmittance out of (0, 1]"
+        );
+        assert!(distance > 0.0, "distance not positive");
+        let absorption = |t: f32| -t.ln() / distance;
+        Arc::new(Self {
+            ior,
+            distribution: TrowbridgeReitz::from_roughness(roughness, roughness),
+            absorption: Color::new(absorption(r), absorption(g), absorption(b)),
+        })
+    }
+
+    /// Relative index of refraction across the surface at the wavelength of `r_in`.
+    fn eta(&self, r_in: &Ray, rec: &HitRecord) -> f32 {
+        let ior = self.ior.at(r_in.wavelength());
+        if rec.front_face {
+            ior
+        } else {
+            1.0 / ior
+        }
     }
 }
 
@@ -126,32 +333,38 @@ fn schlick(cosine: f32, ref_idx: f32) -> f32 {
 impl Material for Dielectric {
     fn scatter(
         &self,
-        _: &mut dyn rand::RngCore,
+        rng: &mut dyn rand::RngCore,
         r_in: &Ray,
         rec: &HitRecord,
     ) -> Option<ScatterRecord> {
-        let ray_direction;
-        let scattered;
-
         let attenuation = rgb!(1.0, 1.0, 1.0);
-        let etai_over_etat = if rec.front_face {
-            1.0 / self.ref_idx
-        } else {
-            self.ref_idx
-        };
-
         let unit_direction = r_in.direction().unit_vector();
+        if !self.distribution.is_smooth() {
+            return Some(ScatterRecord {
+                specular_ray: None,
+                attenuation,
+                pdf_ptr: Some(MicrofacetPDF::refracting(
+                    rec.normal,
+                    -unit_direction,
+                    self.distribution,
+                    self.eta(r_in, rec),
+                )),
+            });
+        }
+
+        let etai_over_etat = 1.0 / self.eta(r_in, rec);
 
         let cos_theta = -unit_direction.dot(rec.normal).min(1.0);
         let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
         let reflect_prob = schlick(cos_theta, etai_over_etat);
 
-        if etai_over_etat * sin_theta > 1.0 || rand::random::<f32>() < reflect_prob {
-            ray_direction = reflect(unit_direction, rec.normal);
+        let ray_direction = if etai_over_etat * sin_theta > 1.0 || rng.gen::<f32>() < reflect_prob {
+            reflect(unit_direction, rec.normal)
         } else {
-            ray_direction = refract(unit_direction, rec.normal, etai_over_etat);
-        }
-        scattered = Ray::new(rec.p, ray_direction, r_in.time());
+            refract(unit_direction, rec.normal, etai_over_etat)
+        };
+        let scattered =
+            Ray::new(rec.p, ray_direction, r_in.time()).with_wavelength(r_in.wavelength());
 
         Some(ScatterRecord {
             specular_ray: Some(scattered),
@@ -159,6 +372,260 @@ impl Material for Dielectric {
             pdf_ptr: None,
         })
     }
+
+    fn transmittance(&self, rec: &HitRecord, distance: f32) -> Color {
+        if rec.front_face {
+            return rgb!(1.0, 1.0, 1.0);
+        }
+        let [r, g, b] = (self.absorption * -distance).to_array();
+        Color::new(r.exp(), g.exp(), b.exp())
+    }
+
+    /// The BSDF times the cosine, written to be the same both ways through the surface.
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f32 {
+        let (wo, wi) = local_directions(rec, r_in, scattered);
+        rough_dielectric(wo, wi, self.eta(r_in, rec), &self.distribution)
+    }
+}
+
+/// The BSDF times the cosine of a rough dielectric boundary, for local directions `wo` and `wi`.
+fn rough_dielectric(wo: Vec3, wi: Vec3, eta: f32, distribution: &TrowbridgeReitz) -> f32 {
+    if wo.z() <= 0.0 {
+        return 0.0;
+    }
+
+    if wi.z() > 0.0 {
+        let h = (wo + wi).unit_vector();
+        fresnel_dielectric(wo.dot(h), eta) * distribution.d(h) * distribution.g(wo, wi)
+            / (4.0 * wo.z())
+    } else if wi.z() < 0.0 {
+        let h = refraction_half_vector(wo, wi, eta);
+        let (cos_o, cos_i) = (wo.dot(h), wi.dot(h));
+        if cos_o <= 0.0 || cos_i >= 0.0 {
+            return 0.0;
+        }
+        // The same fraction gets through both ways. From inside, close to the critical angle,
+        // f32 cannot tell how close, so take it from outside.
+        let transmittance = if eta >= 1.0 {
+            1.0 - fresnel_dielectric(cos_o, eta)
+        } else {
+            1.0 - fresnel_dielectric(-cos_i, 1.0 / eta)
+        };
+        // Walter et al.'s BTDF lacks the `eta` for radiance and has `eta²` for importance,
+        // so this one in between is symmetric
+        transmittance * distribution.d(h) * distribution.g(wo, wi) * eta * cos_o * -cos_i
+            / (wo.z() * (cos_o + eta * cos_i).powi(2))
+    } else {
+        0.0
+    }
+}
+
+// Principled
+/// Burley's principled BSDF (2012, with the transmission of 2015), its parameters textures.
+#[derive(Debug)]
+pub struct Principled {
+    /// Albedo of the diffuse part, tint of the glass and head-on reflectance of metals
+    pub base_color: Arc<dyn Texture>,
+    /// Blends from a dielectric, at 0, to a metal
+    pub metallic: Arc<dyn Texture>,
+    pub roughness: Arc<dyn Texture>,
+    /// Head-on reflectance of the dielectric, 0.5 being an index of refraction of 1.5
+    pub specular: Arc<dyn Texture>,
+    /// A glossy, colorless coat on top
+    pub clearcoat: Arc<dyn Texture>,
+    /// Extra reflectance towards grazing angles, as of cloth
+    pub sheen: Arc<dyn Texture>,
+    /// Blends the dielectric from opaque, at 0, to rough glass
+    pub transmission: Arc<dyn Texture>,
+}
+
+impl Principled {
+    /// Burley's clearcoat is a lot glossier than the surface under it
+    const CLEARCOAT_ROUGHNESS: f32 = 0.1;
+
+    /// A plastic of `base_color` and roughness 0.5.
+    pub fn new(base_color: Arc<dyn Texture>) -> Self {
+        let constant = |value: f32| -> Arc<dyn Texture> {
+            Arc::new(SolidColor {
+                color_value: Color::from_scalar(value),
+            })
+        };
+        Self {
+            base_color,
+            metallic: constant(0.0),
+            roughness: constant(0.5),
+            specular: constant(0.5),
+            clearcoat: constant(0.0),
+            sheen: constant(0.0),
+            transmission: constant(0.0),
+        }
+    }
+
+    /// The parameters at `rec`, and the lobes they make up.
+    fn lobes(&self, rec: &HitRecord) -> PrincipledLobes {
+        let scalar = |texture: &Arc<dyn Texture>| {
+            let [r, g, b] = texture.value(rec.u, rec.v, rec.p).to_array();
+            clamp((r + g + b) / 3.0, 0.0, 1.0)
+        };
+        let base_color = self.base_color.value(rec.u, rec.v, rec.p);
+        let metallic = scalar(&self.metallic);
+        let roughness = scalar(&self.roughness);
+        let specular = scalar(&self.specular);
+        let transmission = scalar(&self.transmission);
+        let dielectric = 1.0 - metallic;
+
+        // The index whose head-on reflectance is `0.08 * specular`, kept off 1 where refraction
+        // would no longer bend light
+        let ior = (2.0 / (1.0 - (0.08 * specular).sqrt()) - 1.0).max(1.01);
+        PrincipledLobes {
+            base_color,
+            roughness,
+            sheen: scalar(&self.sheen),
+            clearcoat: scalar(&self.clearcoat),
+            diffuse: dielectric * (1.0 - transmission),
+            specular: 1.0 - dielectric * transmission,
+            glass: dielectric * transmission,
+            f0: Color::from_scalar(0.08 * specular * dielectric) + base_color * metallic,
+            distribution: TrowbridgeReitz::from_roughness(roughness, roughness),
+            eta: if rec.front_face { ior } else { 1.0 / ior },
+        }
+    }
+
+    fn bsdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        let (wo, wi) = local_directions(rec, r_in, scattered);
+        self.lobes(rec).scattering(wo, wi)
+    }
+}
+
+/// `Principled` evaluated at a point.
+struct PrincipledLobes {
+    base_color: Color,
+    roughness: f32,
+    sheen: f32,
+    clearcoat: f32,
+    /// Weights of the diffuse and sheen, the specular reflection and the glass lobes
+    diffuse: f32,
+    specular: f32,
+    glass: f32,
+    /// Head-on reflectance of the specular lobe
+    f0: Color,
+    distribution: TrowbridgeReitz,
+    /// Index of refraction beyond the surface for the glass, relative to the side of `rec`
+    eta: f32,
+}
+
+impl PrincipledLobes {
+    /// The BSDF times the cosine, for local directions `wo` and `wi`.
+    fn scattering(&self, wo: Vec3, wi: Vec3) -> Color {
+        if wo.z() <= 0.0 {
+            return Color::black();
+        }
+        let glass = self.glass * rough_dielectric(wo, wi, self.eta, &self.distribution);
+        if wi.z() <= 0.0 {
+            // Light crosses a closed object twice, so each crossing takes the root of the tint
+            let [r, g, b] = self.base_color.to_array();
+            return Color::new(r.sqrt(), g.sqrt(), b.sqrt()) * glass;
+        }
+
+        let h = (wo + wi).unit_vector();
+        let cos_d = wi.dot(h);
+        let white = rgb!(1.0, 1.0, 1.0);
+
+        // Burley's diffuse darkens smooth surfaces at grazing angles, and brightens rough ones
+        let fd90 = 0.5 + 2.0 * self.roughness * cos_d * cos_d;
+        let retro = (1.0 + (fd90 - 1.0) * schlick_weight(wo.z()))
+            * (1.0 + (fd90 - 1.0) * schlick_weight(wi.z()));
+        let brightness = luminance(self.base_color);
+        let tint = if brightness > 0.0 {
+            self.base_color / brightness
+        } else {
+            white
+        };
+        let sheen = (white + tint) * (0.5 * self.sheen * schlick_weight(cos_d));
+        let diffuse = (self.base_color * (retro / PI) + sheen) * (self.diffuse * wi.z());
+
+        let microfacet = |distribution: &TrowbridgeReitz| {
+            distribution.d(h) * distribution.g(wo, wi) / (4.0 * wo.z())
+        };
+        let fresnel = self.f0 + (white - self.f0) * schlick_weight(cos_d);
+        let specular = fresnel * (self.specular * microfacet(&self.distribution));
+        let clearcoat = 0.25
+            * self.clearcoat
+            * (0.04 + 0.96 * schlick_weight(cos_d))
+            * microfacet(&Self::clearcoat_distribution());
+
+        diffuse + specular + Color::from_scalar(clearcoat + glass)
+    }
+
+    fn clearcoat_distribution() -> TrowbridgeReitz {
+        let roughness = Principled::CLEARCOAT_ROUGHNESS;
+        TrowbridgeReitz::from_roughness(roughness, roughness)
+    }
+
+    /// Directions from each lobe, picked about in proportion to what it reflects along `wo`.
+    fn pdf(&self, normal: Vec3, wo: Vec3) -> MixturePDF<'static> {
+        let fresnel = |f0: f32| f0 + (1.0 - f0) * schlick_weight(wo.dot(normal));
+        let mut lobes: Vec<(f32, Box<dyn PDF>)> = Vec::new();
+        if self.diffuse > 0.0 {
+            let albedo = luminance(self.base_color).max(self.sheen);
+            lobes.push((self.diffuse * albedo.max(0.05), CosinePDF::new(normal)));
+        }
+        if self.specular > 0.0 {
+            lobes.push((
+                self.specular * fresnel(luminance(self.f0)).max(0.05),
+                MicrofacetPDF::new(normal, wo, self.distribution),
+            ));
+        }
+        if self.glass > 0.0 {
+            lobes.push((
+                self.glass,
+                MicrofacetPDF::refracting(normal, wo, self.distribution, self.eta),
+            ));
+        }
+        if self.clearcoat > 0.0 {
+            lobes.push((
+                0.25 * self.clearcoat * fresnel(0.04),
+                MicrofacetPDF::new(normal, wo, Self::clearcoat_distribution()),
+            ));
+        }
+        MixturePDF::weighted(lobes)
+    }
+}
+
+/// Weight of Schlick's Fresnel approximation at `cos` to the normal.
+fn schlick_weight(cos: f32) -> f32 {
+    (1.0 - clamp(cos, 0.0, 1.0)).powi(5)
+}
+
+impl Material for Principled {
+    fn scatter(
+        &self,
+        _rng: &mut dyn rand::RngCore,
+        r_in: &Ray,
+        rec: &HitRecord,
+    ) -> Option<ScatterRecord> {
+        let wo = -r_in.direction().unit_vector();
+        Some(ScatterRecord {
+            specular_ray: None,
+            attenuation: rgb!(1.0, 1.0, 1.0),
+            pdf_ptr: Some(Box::new(self.lobes(rec).pdf(rec.normal, wo))),
+        })
+    }
+
+    /// Luminance of the BSDF times the cosine, whose color `scattering` gives.
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f32 {
+        luminance(self.bsdf(r_in, rec, scattered))
+    }
+
+    fn scattering(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        srec: &ScatterRecord,
+        scattered: &Ray,
+    ) -> Color {
+        srec.attenuation * self.bsdf(r_in, rec, scattered)
+    }
 }
 
 // Diffuse light
@@ -214,7 +681,8 @@ impl Material for Isotropic {
         r_in: &Ray,
         rec: &HitRecord,
     ) -> Option<ScatterRecord> {
-        let scattered = Ray::new(rec.p, random_in_unit_sphere(rng), r_in.time());
+        let scattered = Ray::new(rec.p, random_in_unit_sphere(rng), r_in.time())
+            .with_wavelength(r_in.wavelength());
         let attenuation = self.albedo.value(rec.u, rec.v, rec.p);
 
         Some(ScatterRecord {
@@ -224,3 +692,240 @@ impl Material for Isotropic {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    #[test]
+    fn rough_glass_conserves_energy() {
+        let rng = &mut SmallRng::seed_from_u64(0);
+        let glass: Arc<dyn Material> = Dielectric::rough(1.5, 0.5);
+        let outward = vec3!(0.0, 1.0, 0.0);
+
+        // Seen from outside and from inside, everything is reflected or transmitted, except for
+        // the little light the microfacets shadow. Transmission scales light by the index of
+        // refraction it leaves behind relative to the one it enters, which crossing back undoes.
+        for &(from, eta) in &[
+            (vec3!(0.3, 1.0, 0.0), 1.5),
+            (vec3!(0.3, -1.0, 0.0), 1.0 / 1.5),
+        ] {
+            let r_in = Ray::new(from, -from, 0.0);
+            let rec = HitRecord::new(
+                &r_in,
+                outward,
+                Point3::origin(),
+                1.0,
+                0.0,
+                0.0,
+                glass.clone(),
+            );
+            const N: usize = 200_000;
+            let albedo = (0..N)
+                .map(|_| {
+                    let scattered = Ray::new(Point3::origin(), random_unit_vector(rng), 0.0);
+                    let crossed = scattered.direction().dot(rec.normal) < 0.0;
+                    let scale = if crossed { eta } else { 1.0 };
+                    scale * glass.scattering_pdf(&r_in, &rec, &scattered)
+                })
+                .sum::<f32>()
+                / N as f32
+                * 4.0
+                * PI;
+            assert!(albedo > 0.9 && albedo < 1.01, "{}", albedo);
+        }
+    }
+
+    #[test]
+    fn rough_materials_reciprocal() {
+        let rng = &mut SmallRng::seed_from_u64(0);
+        let materials: [Arc<dyn Material>; 3] = [
+            Metal::measured(Conductor::Copper, 0.4),
+            Dielectric::rough(1.5, 0.4),
+            Arc::new(Principled {
+                metallic: gray(0.3),
+                clearcoat: gray(0.5),
+                sheen: gray(0.5),
+                transmission: gray(0.5),
+                ..Principled::new(CheckerTexture::new(
+                    rgb!(0.8, 0.2, 0.1),
+                    rgb!(0.1, 0.3, 0.9),
+                ))
+            }),
+        ];
+        let outward = Vec3::new(0.1, 1.0, 0.3).unit_vector();
+
+        // The BSDF for light going from `from` to `to`, both pointing away from the surface
+        let bsdf = |material: &Arc<dyn Material>, from: Vec3, to: Vec3| {
+            let r_in = Ray::new(from, -from, 0.0);
+            let rec = HitRecord::new(
+                &r_in,
+                outward,
+                Point3::origin(),
+                1.0,
+                0.0,
+                0.0,
+                material.clone(),
+            );
+            let scattered = Ray::new(Point3::origin(), to, 0.0);
+            material.scattering_pdf(&r_in, &rec, &scattered) / to.dot(rec.normal).abs()
+        };
+
+        for material in &materials {
+            let mut nonzero = 0;
+            for _ in 0..1000 {
+                let (a, b) = (random_unit_vector(rng), random_unit_vector(rng));
+                let (ab, ba) = (bsdf(material, a, b), bsdf(material, b, a));
+                assert!(
+                    (ab - ba).abs() <= 1e-2 * ab.max(ba),
+                    "{} {} {:?} {:?}",
+                    ab,
+                    ba,
+                    a,
+                    b
+                );
+                if ab > 0.0 {
+                    nonzero += 1;
+                }
+            }
+            assert!(nonzero > 100);
+        }
+    }
+
+    #[test]
+    fn glass_disperses_light() {
+        // N-BK7 is specified by its index at the d line, and bends blue light more than red
+        assert!((Ior::BK7.at(None) - 1.5168).abs() < 1e-4);
+        assert!(Ior::BK7.at(Some(486.1)) > Ior::BK7.at(Some(656.3)));
+        let cauchy = Ior::Cauchy { a: 1.5, b: 0.01 };
+        assert!((cauchy.at(Some(500.0)) - 1.54).abs() < 1e-6);
+        assert_eq!(Ior::Constant(1.5).at(Some(400.0)), 1.5);
+
+        let rng = &mut SmallRng::seed_from_u64(0);
+        let glass: Arc<dyn Material> =
+            Dielectric::tinted(Ior::DIAMOND, 0.0, rgb!(1.0, 1.0, 1.0), 1.0);
+        let from = vec3!(1.0, 1.0, 0.0);
+        // Sine of the angle to the normal light of `wavelength` refracts to
+        let refracted = |rng: &mut SmallRng, wavelength| {
+            let r_in = Ray::new(from, -from, 0.0).with_wavelength(wavelength);
+            let rec = HitRecord::new(
+                &r_in,
+                vec3!(0.0, 1.0, 0.0),
+                Point3::origin(),
+                1.0,
+                0.0,
+                0.0,
+                glass.clone(),
+            );
+            loop {
+                let srec = glass.scatter(rng, &r_in, &rec).unwrap();
+                let scattered = srec.specular_ray.unwrap();
+                assert_eq!(scattered.wavelength(), wavelength);
+                if scattered.direction().y() < 0.0 {
+                    return -scattered.direction().unit_vector().x();
+                }
+            }
+        };
+
+        let sin_in = 0.5_f32.sqrt();
+        for &wavelength in &[None, Some(450.0), Some(650.0)] {
+            let sin_out = refracted(rng, wavelength);
+            assert!(
+                (sin_in / sin_out - Ior::DIAMOND.at(wavelength)).abs() < 1e-4,
+                "{} {}",
+                sin_in / sin_out,
+                Ior::DIAMOND.at(wavelength)
+            );
+        }
+        assert!(refracted(rng, Some(450.0)) < refracted(rng, Some(650.0)));
+    }
+
+    fn gray(value: f32) -> Arc<dyn Texture> {
+        Arc::new(SolidColor {
+            color_value: Color::from_scalar(value),
+        })
+    }
+
+    #[test]
+    fn principled_sampling_matches_bsdf() {
+        let rng = &mut SmallRng::seed_from_u64(0);
+        let white = gray(1.0);
+        let materials: [Arc<dyn Material>; 4] = [
+            Arc::new(Principled::new(white.clone())),
+            Arc::new(Principled {
+                metallic: gray(1.0),
+                roughness: gray(0.3),
+                ..Principled::new(white.clone())
+            }),
+            Arc::new(Principled {
+                roughness: gray(1.0),
+                clearcoat: gray(1.0),
+                sheen: gray(1.0),
+                ..Principled::new(gray(0.3))
+            }),
+            Arc::new(Principled {
+                transmission: gray(1.0),
+                ..Principled::new(white)
+            }),
+        ];
+        let from = Vec3::new(0.6, 1.0, 0.2).unit_vector();
+        let r_in = Ray::new(from, -from, 0.0);
+
+        for material in &materials {
+            let rec = HitRecord::new(
+                &r_in,
+                vec3!(0.0, 1.0, 0.0),
+                Point3::origin(),
+                1.0,
+                0.0,
+                0.0,
+                material.clone(),
+            );
+            let srec = material.scatter(rng, &r_in, &rec).unwrap();
+            let pdf = srec.pdf_ptr.as_ref().unwrap();
+            // Glass scales light it lets through by the index of refraction, 1.5 here
+            let scale = |scattered: &Ray| {
+                if scattered.direction().y() < 0.0 {
+                    1.5
+                } else {
+                    1.0
+                }
+            };
+
+            // The albedo integrated over all directions, and estimated from the material's
+            // own samples, which only agree if the density matches what `scatter` generates
+            const N: usize = 200_000;
+            let uniform = (0..N)
+                .map(|_| {
+                    let scattered = Ray::new(Point3::origin(), random_unit_vector(rng), 0.0);
+                    scale(&scattered) * material.scattering_pdf(&r_in, &rec, &scattered)
+                })
+                .sum::<f32>()
+                / N as f32
+                * 4.0
+                * PI;
+            let sampled = (0..N)
+                .map(|_| {
+                    let direction = pdf.generate(rng);
+                    let pdf_val = pdf.value(rng, direction);
+                    if pdf_val <= 0.0 {
+                        return 0.0;
+                    }
+                    let scattered = Ray::new(Point3::origin(), direction, 0.0);
+                    scale(&scattered) * material.scattering_pdf(&r_in, &rec, &scattered) / pdf_val
+                })
+                .sum::<f32>()
+                / N as f32;
+
+            assert!(uniform > 0.2 && uniform < 1.05, "{}", uniform);
+            assert!(
+                (uniform - sampled).abs() < 0.03 * uniform,
+                "{} {}",
+                uniform,
+                sampled
+            );
+        }
+    }
+}
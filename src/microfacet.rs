@@ -0,0 +1,238 @@
+use crate::prelude::*;
+
+use rand::Rng;
+
+/// The Trowbridge-Reitz (GGX) distribution of microfacet normals around the z axis.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TrowbridgeReitz {
+    pub alpha_x: f32,
+    pub alpha_y: f32,
+}
+
+impl TrowbridgeReitz {
+    /// Narrower distributions are perfect mirrors as far as f32 goes
+    const MIN_ALPHA: f32 = 1e-3;
+
+    /// A distribution for perceptual roughness in `0..=1` along x and y.
+    pub fn from_roughness(roughness_x: f32, roughness_y: f32) -> Self {
+        let alpha = |roughness: f32| clamp(roughness, 0.0, 1.0).powi(2);
+        Self {
+            alpha_x: alpha(roughness_x),
+            alpha_y: alpha(roughness_y),
+        }
+    }
+
+    /// Whether the surface is a mirror, which only reflects in one direction.
+    pub fn is_smooth(&self) -> bool {
+        self.alpha_x.max(self.alpha_y) < Self::MIN_ALPHA
+    }
+
+    fn alphas(&self) -> (f32, f32) {
+        (
+            self.alpha_x.max(Self::MIN_ALPHA),
+            self.alpha_y.max(Self::MIN_ALPHA),
+        )
+    }
+
+    /// Density of microfacets with normal `h`, per unit solid angle and unit macro surface.
+    pub fn d(&self, h: Vec3) -> f32 {
+        if h.z() <= 0.0 {
+            return 0.0;
+        }
+        let (ax, ay) = self.alphas();
+        let e = (h.x() / ax).powi(2) + (h.y() / ay).powi(2) + h.z() * h.z();
+        1.0 / (PI * ax * ay * e * e)
+    }
+
+    /// Microfacet area hidden from `w` by others, relative to the visible area.
+    fn lambda(&self, w: Vec3) -> f32 {
+        let (ax, ay) = self.alphas();
+        let z2 = w.z() * w.z();
+        if z2 == 0.0 {
+            return f32::INFINITY;
+        }
+        let a2 = ((ax * w.x()).powi(2) + (ay * w.y()).powi(2)) / z2;
+        ((1.0 + a2).sqrt() - 1.0) / 2.0
+    }
+
+    /// Fraction of microfacets visible from `w`.
+    pub fn g1(&self, w: Vec3) -> f32 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    /// Fraction of microfacets visible from both `wo` and `wi`, height-correlated.
+    pub fn g(&self, wo: Vec3, wi: Vec3) -> f32 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// Samples a microfacet normal in proportion to the area it covers as seen from `wo`
+    /// (Heitz, "Sampling the GGX Distribution of Visible Normals", 2018).
+    pub fn sample_visible_normal<R: Rng + ?Sized>(&self, rng: &mut R, wo: Vec3) -> Vec3 {
+        let (ax, ay) = self.alphas();
+
+        // Stretch the view direction so the distribution becomes a hemisphere
+        let v = Vec3::new(ax * wo.x(), ay * wo.y(), wo.z()).unit_vector();
+        let len_sq = v.x() * v.x() + v.y() * v.y();
+        let t1 = if len_sq > 0.0 {
+            Vec3::new(-v.y(), v.x(), 0.0) / len_sq.sqrt()
+        } else {
+            vec3!(1.0, 0.0, 0.0)
+        };
+        let t2 = v.cross(t1);
+
+        // A point on the projected hemisphere, whose far half is foreshortened
+        let r = rng.gen::<f32>().sqrt();
+        let phi = 2.0 * PI * rng.gen::<f32>();
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + v.z());
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+        let n = p1 * t1 + p2 * t2 + (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt() * v;
+
+        // And back to the ellipsoid
+        Vec3::new(ax * n.x(), ay * n.y(), n.z().max(0.0)).unit_vector()
+    }
+
+    /// Density of `sample_visible_normal(wo)` returning `h`, per unit solid angle.
+    pub fn visible_normal_pdf(&self, wo: Vec3, h: Vec3) -> f32 {
+        if wo.z() <= 0.0 {
+            return 0.0;
+        }
+        self.g1(wo) * wo.dot(h).max(0.0) * self.d(h) / wo.z()
+    }
+}
+
+/// Fresnel reflectance of a dielectric with relative index of refraction `eta`, at `cos`.
+pub fn fresnel_dielectric(cos: f32, eta: f32) -> f32 {
+    let cos_i = clamp(cos, 0.0, 1.0);
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let perpendicular = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    0.5 * (parallel * parallel + perpendicular * perpendicular)
+}
+
+/// `w` refracted through a microfacet with normal `h`, unless it is reflected totally.
+pub fn refract_through(w: Vec3, h: Vec3, eta: f32) -> Option<Vec3> {
+    let cos_i = w.dot(h);
+    let sin2_t = (1.0 - cos_i * cos_i).max(0.0) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return None;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    Some(-w / eta + (cos_i / eta - cos_t) * h)
+}
+
+/// The microfacet normal on the side of `wo` that refracts it into `wi`.
+pub fn refraction_half_vector(wo: Vec3, wi: Vec3, eta: f32) -> Vec3 {
+    let h = (wo + eta * wi).unit_vector();
+    if h.z() < 0.0 {
+        -h
+    } else {
+        h
+    }
+}
+
+/// Fresnel reflectance of a conductor with complex index of refraction `eta + i k` relative to
+/// the outside, for light arriving at `cos` to the normal.
+pub fn fresnel_conductor(cos: f32, eta: Color, k: Color) -> Color {
+    let cos = clamp(cos, 0.0, 1.0);
+    let cos2 = cos * cos;
+    let sin2 = 1.0 - cos2;
+
+    let reflectance = |eta: f32, k: f32| {
+        let (eta2, k2) = (eta * eta, k * k);
+        let t0 = eta2 - k2 - sin2;
+        let a2_plus_b2 = (t0 * t0 + 4.0 * eta2 * k2).sqrt();
+        let t1 = a2_plus_b2 + cos2;
+        let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+        let t2 = 2.0 * cos * a;
+        let rs = (t1 - t2) / (t1 + t2);
+        let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+        let t4 = t2 * sin2;
+        let rp = rs * (t3 - t4) / (t3 + t4);
+        0.5 * (rs + rp)
+    };
+
+    let [eta_r, eta_g, eta_b] = eta.to_array();
+    let [k_r, k_g, k_b] = k.to_array();
+    Color::new(
+        reflectance(eta_r, k_r),
+        reflectance(eta_g, k_g),
+        reflectance(eta_b, k_b),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    #[test]
+    fn visible_normals_follow_their_pdf() {
+        let rng = &mut SmallRng::seed_from_u64(0);
+        let distribution = TrowbridgeReitz::from_roughness(0.7, 0.4);
+        let wo = Vec3::new(0.5, -0.3, 0.6).unit_vector();
+
+        // Fraction of the samples falling into a cone around some direction, against the
+        // integral of the pdf over it
+        let axis = Vec3::new(0.3, -0.1, 0.9).unit_vector();
+        let cos_cone = 0.95;
+        const N: usize = 200_000;
+        let hits = (0..N)
+            .filter(|_| distribution.sample_visible_normal(rng, wo).dot(axis) > cos_cone)
+            .count();
+        let integral: f32 = (0..N)
+            .map(|_| {
+                let h = random_unit_vector(rng);
+                if h.dot(axis) > cos_cone {
+                    distribution.visible_normal_pdf(wo, h)
+                } else {
+                    0.0
+                }
+            })
+            .sum::<f32>()
+            / N as f32
+            * 4.0
+            * PI;
+        let fraction = hits as f32 / N as f32;
+        assert!(
+            (fraction - integral).abs() < 0.05 * fraction,
+            "{} {}",
+            fraction,
+            integral
+        );
+    }
+
+    #[test]
+    fn refraction_follows_snell() {
+        let h = Vec3::new(0.3, 0.1, 1.0).unit_vector();
+        let wo = Vec3::new(-0.4, 0.5, 0.7).unit_vector();
+        let wi = refract_through(wo, h, 1.5).unwrap();
+        assert!((wi.length() - 1.0).abs() < 1e-5);
+        let sin = |w: Vec3| w.cross(h).length();
+        assert!((sin(wo) - 1.5 * sin(wi)).abs() < 1e-5);
+        assert!((refraction_half_vector(wo, wi, 1.5) - h).length() < 1e-5);
+
+        // Leaving glass at a grazing angle reflects everything
+        let grazing = Vec3::new(0.9, 0.0, 0.3).unit_vector();
+        assert!(refract_through(grazing, vec3!(0.0, 0.0, 1.0), 1.0 / 1.5).is_none());
+        assert_eq!(fresnel_dielectric(grazing.z(), 1.0 / 1.5), 1.0);
+    }
+
+    #[test]
+    fn conductor_reflectance() {
+        // A perfect absorber matched to the outside reflects nothing head-on...
+        let none = fresnel_conductor(1.0, rgb!(1.0, 1.0, 1.0), Color::black());
+        assert!(none.length() < 1e-6);
+        // ...a dielectric follows ((n - 1) / (n + 1))²...
+        let glass = fresnel_conductor(1.0, rgb!(1.5, 1.5, 1.5), Color::black());
+        assert!((glass.x() - 0.04).abs() < 1e-4);
+        // ...and everything turns into a mirror at grazing angles
+        let gold = fresnel_conductor(0.0, rgb!(0.143, 0.375, 1.442), rgb!(3.983, 2.386, 1.603));
+        assert!((gold - rgb!(1.0, 1.0, 1.0)).length() < 1e-4);
+    }
+}
@@ -0,0 +1,436 @@
+use crate::prelude::*;
+
+use crate::{
+    camera::*, color::*, framebuffer::*, integrator::*, render::*, spectrum::*, worlds::*,
+};
+
+use rand::{rngs::SmallRng, Rng, RngCore, SeedableRng};
+use rayon::prelude::*;
+use std::any::Any;
+
+/// Primary sample space Metropolis light transport (Kelemen et al.), running one splatting
+/// Markov chain per sample.
+#[derive(Debug)]
+pub struct Metropolis {
+    /// Steps of each Markov chain
+    pub mutations: u32,
+    /// Paths traced up front to start chains from
+    pub bootstrap_paths: u32,
+    /// Chance that a step draws an entirely new path instead of perturbing the current one
+    pub large_step_probability: f32,
+    /// Standard deviation of the perturbations of each random number
+    pub sigma: f32,
+}
+
+#[derive(Debug)]
+struct Bootstrap {
+    /// Bootstrap path `k` is traced with a `PrimarySampler` seeded from this and `k`
+    seed: u64,
+    /// Running sums of the bootstrap paths' luminance
+    cdf: Vec<f64>,
+    /// Mean luminance of the paths, over the whole image
+    brightness: f32,
+}
+
+impl Metropolis {
+    pub const DEFAULT_MUTATIONS: u32 = 64;
+
+    pub fn new(mutations: u32) -> Self {
+        Self {
+            mutations,
+            bootstrap_paths: 100_000,
+            large_step_probability: 0.3,
+            sigma: 0.01,
+        }
+    }
+
+    fn sampler(&self, seed: u64) -> PrimarySampler {
+        PrimarySampler::new(seed, self.sigma, self.large_step_probability)
+    }
+
+    /// Traces the path for the numbers of `sampler`: the pixel it lands on, from the top left,
+    /// and its color.
+    fn evaluate(&self, sampler: &mut PrimarySampler, ctx: &mut SampleContext) -> (u32, u32, Color) {
+        let RenderSettings {
+            image_width,
+            image_height,
+            ..
+        } = *ctx.settings;
+
+        let x = sampler.gen::<f32>() * image_width as f32;
+        let y = sampler.gen::<f32>() * image_height as f32;
+        let mut r = ctx.camera.get_ray(
+            sampler,
+            x / (image_width - 1) as f32,
+            y / (image_height - 1) as f32,
+        );
+        if ctx.settings.spectral {
+            r = r.with_wavelength(Some(sample_wavelength(sampler.gen())));
+        }
+        let radiance = sanitize(PathTracer.radiance(sampler, &r, ctx) * ray_weight(&r));
+
+        let i = (x as u32).min(image_width - 1);
+        let j = (y as u32).min(image_height - 1);
+        (i, image_height - 1 - j, radiance)
+    }
+
+    fn bootstrap(&self, world: &World, camera: &Camera, settings: &RenderSettings) -> Bootstrap {
+        // Pixel indices never reach this stream
+        let seed = mix_seed(settings.seed, u64::MAX - 1);
+
+        let luminances: Vec<f64> = (0..self.bootstrap_paths)
+            .into_par_iter()
+            .map_init(
+                || SampleContext {
+                    world,
+                    camera,
+                    settings,
+                    sample: 0,
+                    splats: Vec::new(),
+                    pass: None,
+                },
+                |ctx, k| {
+                    let (_, _, radiance) =
+                        self.evaluate(&mut self.sampler(mix_seed(seed, k as u64)), ctx);
+                    luminance(radiance) as f64
+                },
+            )
+            .collect();
+
+        let mut sum = 0.0;
+        let cdf: Vec<f64> = luminances
+            .into_iter()
+            .map(|luminance| {
+                sum += luminance;
+                sum
+            })
+            .collect();
+
+        Bootstrap {
+            seed,
+            cdf,
+            brightness: (sum / self.bootstrap_paths.max(1) as f64) as f32,
+        }
+    }
+}
+
+impl Integrator for Metropolis {
+    fn radiance(&self, rng: &mut dyn rand::RngCore, _r: &Ray, ctx: &mut SampleContext) -> Color {
+        let bootstrap = ctx
+            .pass
+            .and_then(|pass| pass.downcast_ref::<Bootstrap>())
+            .expect("Metropolis samples need the state of `Integrator::begin_pass`");
+        let total = match bootstrap.cdf.last() {
+            Some(&total) if total > 0.0 => total,
+            _ => return Color::black(),
+        };
+
+        // Start from a bootstrap path picked in proportion to its luminance, which makes the
+        // chain's first state follow the distribution it converges to
+        let u = rng.gen::<f64>() * total;
+        let k = bootstrap.cdf.partition_point(|&c| c <= u);
+        let mut sampler = self.sampler(mix_seed(bootstrap.seed, k as u64));
+        let mut current = self.evaluate(&mut sampler, ctx);
+        sampler.reseed(rng.next_u64());
+
+        // Splat the expected value of each step: both paths, weighted by whether the chain
+        // moves to the proposed one
+        let scale = bootstrap.brightness / self.mutations as f32;
+        for _ in 0..self.mutations {
+            sampler.start_iteration();
+            let proposed = self.evaluate(&mut sampler, ctx);
+
+            let current_luminance = luminance(current.2);
+            let proposed_luminance = luminance(proposed.2);
+            let accept = if current_luminance > 0.0 {
+                (proposed_luminance / current_luminance).min(1.0)
+            } else {
+                1.0
+            };
+
+            if accept > 0.0 {
+                ctx.splats.push(Splat {
+                    x: proposed.0,
+                    y: proposed.1,
+                    color: proposed.2 * (accept * scale / proposed_luminance),
+                });
+            }
+            if accept < 1.0 {
+                ctx.splats.push(Splat {
+                    x: current.0,
+                    y: current.1,
+                    color: current.2 * ((1.0 - accept) * scale / current_luminance),
+                });
+            }
+
+            if rng.gen::<f32>() < accept {
+                current = proposed;
+                sampler.accept();
+            } else {
+                sampler.reject();
+            }
+        }
+
+        Color::black()
+    }
+
+    fn begin_pass(
+        &self,
+        world: &World,
+        camera: &Camera,
+        settings: &RenderSettings,
+    ) -> Option<Box<dyn Any + Send + Sync>> {
+        Some(Box::new(self.bootstrap(world, camera, settings)))
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+struct PrimarySample {
+    value: f32,
+    /// Iteration it was last changed in
+    modified: u64,
+    backup: f32,
+    modified_backup: u64,
+}
+
+/// The random numbers of the current path, mutated lazily as `RngCore` hands them out.
+struct PrimarySampler {
+    rng: SmallRng,
+    samples: Vec<PrimarySample>,
+    /// Next number to hand out
+    index: usize,
+    iteration: u64,
+    large_step: bool,
+    last_large_step: u64,
+    sigma: f32,
+    large_step_probability: f32,
+}
+
+impl PrimarySampler {
+    /// A sampler whose first path draws fresh numbers from `seed`.
+    fn new(seed: u64, sigma: f32, large_step_probability: f32) -> Self {
+        Self {
+            rng: SmallRng::seed_from_u64(seed),
+            samples: Vec::new(),
+            index: 0,
+            iteration: 0,
+            large_step: true,
+            last_large_step: 0,
+            sigma,
+            large_step_probability,
+        }
+    }
+
+    /// Continues with mutations drawn from `seed`, so chains starting from the same path part.
+    fn reseed(&mut self, seed: u64) {
+        self.rng = SmallRng::seed_from_u64(seed);
+    }
+
+    fn start_iteration(&mut self) {
+        self.iteration += 1;
+        self.large_step = self.rng.gen::<f32>() < self.large_step_probability;
+        self.index = 0;
+    }
+
+    fn accept(&mut self) {
+        if self.large_step {
+            self.last_large_step = self.iteration;
+        }
+    }
+
+    fn reject(&mut self) {
+        for sample in &mut self.samples {
+            if sample.modified == self.iteration {
+                sample.value = sample.backup;
+                sample.modified = sample.modified_backup;
+            }
+        }
+        self.iteration -= 1;
+    }
+
+    fn next_sample(&mut self) -> f32 {
+        if self.index == self.samples.len() {
+            // A number no path used yet is as good as new from the last large step on
+            self.samples.push(PrimarySample {
+                value: self.rng.gen(),
+                modified: self.last_large_step,
+                ..PrimarySample::default()
+            });
+        }
+        let sample = &mut self.samples[self.index];
+        self.index += 1;
+
+        // Numbers not used since the last accepted large step were replaced by it
+        if sample.modified < self.last_large_step {
+            sample.value = self.rng.gen();
+            sample.modified = self.last_large_step;
+        }
+
+        sample.backup = sample.value;
+        sample.modified_backup = sample.modified;
+        if self.large_step {
+            sample.value = self.rng.gen();
+        } else {
+            // The perturbations of all the missed steps add up to a wider one
+            let steps = (self.iteration - sample.modified) as f32;
+            let u1 = 1.0 - self.rng.gen::<f32>();
+            let u2 = self.rng.gen::<f32>();
+            let normal = (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos();
+            let value = sample.value + normal * self.sigma * steps.sqrt();
+            sample.value = value - value.floor();
+            if sample.value >= 1.0 {
+                sample.value = 0.0;
+            }
+        }
+        sample.modified = self.iteration;
+
+        sample.value
+    }
+}
+
+impl RngCore for PrimarySampler {
+    fn next_u32(&mut self) -> u32 {
+        // `gen::<f32>()` keeps the top 24 bits, so this hands the number over exactly
+        ((self.next_sample() * 16_777_216.0) as u32).min(0xff_ffff) << 8
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        ((self.next_sample() as f64 * 9_007_199_254_740_992.0) as u64).min((1 << 53) - 1) << 11
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(4) {
+            let bytes = self.next_u32().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{hittable_list::*, scene::*, sphere::*};
+    use std::sync::Arc;
+
+    #[test]
+    fn matches_path_tracer() {
+        // A floor lit unevenly by a sphere light off to one side
+        let mut world = HittableList::new();
+        let mut lights = HittableList::new();
+        world.add(Sphere::new(
+            point!(0.0, -1000.0, 0.0),
+            1000.0,
+            Lambertian::new_rgb(0.5, 0.5, 0.5),
+        ));
+        let light: Arc<dyn Hittable> =
+            Sphere::new(point!(1.5, 1.5, 0.0), 0.5, DiffuseLight::white(8.0));
+        world.add(light.clone());
+        lights.add(light);
+        let world = World::new(world, lights);
+
+        let camera = CameraSettings {
+            look_from: point!(0.0, 6.0, 0.1),
+            look_at: point!(0.0, 0.0, 0.0),
+            vfov: 60.0,
+            ..CameraSettings::default()
+        }
+        .build(1.0);
+        let renderer = Renderer::new(RenderSettings {
+            image_width: 8,
+            image_height: 8,
+            samples_per_pixel: 256,
+            background: Color::black(),
+            seed: 2,
+            ..RenderSettings::default()
+        });
+
+        // Mean luminance of each quarter of the image
+        let quarters = |fb: Framebuffer| {
+            let mut sums = [0.0; 4];
+            for (index, &c) in fb.pixels().iter().enumerate() {
+                let (x, y) = (index % 8, index / 8);
+                sums[x / 4 + 2 * (y / 4)] += luminance(c) / 16.0;
+            }
+            sums
+        };
+        let path = quarters(renderer.render(&world, &camera));
+
+        let renderer = Renderer::new(RenderSettings {
+            samples_per_pixel: 64,
+            ..*renderer.settings()
+        })
+        .with_integrator(Arc::new(Metropolis::new(32)));
+        let metropolis = quarters(renderer.render(&world, &camera));
+
+        // The bootstrap sets the overall brightness and the chains how it spreads over the
+        // image, so check both separately
+        let total = |quarters: [f32; 4]| quarters.iter().sum::<f32>();
+        let (path_total, metropolis_total) = (total(path), total(metropolis));
+        assert!(
+            (metropolis_total - path_total).abs() < 0.1 * path_total,
+            "path {:?} metropolis {:?}",
+            path,
+            metropolis
+        );
+        for (&path, &metropolis) in path.iter().zip(&metropolis) {
+            let (path, metropolis) = (path / path_total, metropolis / metropolis_total);
+            assert!(
+                (metropolis - path).abs() < 0.1 * path,
+                "path share {} metropolis share {}",
+                path,
+                metropolis
+            );
+        }
+    }
+
+    #[test]
+    fn reused_across_scenes() {
+        // A light whose brightness the bootstrap has to find anew for each scene
+        let scene = |brightness: f32| {
+            let mut world = HittableList::new();
+            let mut lights = HittableList::new();
+            world.add(Sphere::new(
+                point!(0.0, -1000.0, 0.0),
+                1000.0,
+                Lambertian::new_rgb(0.5, 0.5, 0.5),
+            ));
+            let light: Arc<dyn Hittable> =
+                Sphere::new(point!(0.0, 1.5, 0.0), 0.5, DiffuseLight::white(brightness));
+            world.add(light.clone());
+            lights.add(light);
+            World::new(world, lights)
+        };
+        let camera = CameraSettings {
+            look_from: point!(0.0, 6.0, 0.1),
+            look_at: point!(0.0, 0.0, 0.0),
+            ..CameraSettings::default()
+        }
+        .build(1.0);
+        let settings = RenderSettings {
+            image_width: 4,
+            image_height: 4,
+            samples_per_pixel: 4,
+            background: Color::black(),
+            ..RenderSettings::default()
+        };
+        let integrator = || {
+            Arc::new(Metropolis {
+                bootstrap_paths: 1000,
+                ..Metropolis::new(8)
+            })
+        };
+
+        let reused = Renderer::new(settings).with_integrator(integrator());
+        reused.render(&scene(1.0), &camera);
+        let fresh = Renderer::new(settings).with_integrator(integrator());
+        assert_eq!(
+            reused.render(&scene(8.0), &camera).pixels(),
+            fresh.render(&scene(8.0), &camera).pixels()
+        );
+    }
+}
@@ -40,7 +40,13 @@ impl MovingSphere {
 }
 
 impl Hittable for MovingSphere {
-    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+    fn hit(
+        &self,
+        _rng: &mut dyn rand::RngCore,
+        r: &Ray,
+        t_min: f32,
+        t_max: f32,
+    ) -> Option<HitRecord> {
         let current_center = self.center(r.time());
         let oc = r.origin() - current_center;
         let a = r.direction().length_squared();
@@ -58,7 +64,7 @@ impl Hittable for MovingSphere {
                 let outward_normal = (p - current_center) / self.radius;
                 let (u, v) = Sphere::get_uv((p - current_center) / self.radius);
                 return Some(HitRecord::new(
-                    &r,
+                    r,
                     outward_normal,
                     p,
                     t,
@@ -75,7 +81,7 @@ impl Hittable for MovingSphere {
                 let outward_normal = (p - current_center) / self.radius;
                 let (u, v) = Sphere::get_uv((p - current_center) / self.radius);
                 return Some(HitRecord::new(
-                    &r,
+                    r,
                     outward_normal,
                     p,
                     t,
@@ -101,7 +107,7 @@ impl Hittable for MovingSphere {
         Some(surrounding_box(box0, box1))
     }
 
-    fn pdf_value(&self, _o: Point3, _v: Vec3) -> f32 {
+    fn pdf_value(&self, _rng: &mut dyn rand::RngCore, _o: Point3, _v: Vec3) -> f32 {
         0.0
     }
 }
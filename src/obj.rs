@@ -0,0 +1,175 @@
+use crate::prelude::*;
+
+use crate::{hittable_list::*, triangle::*};
+
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+#[derive(Debug)]
+pub enum ObjError {
+    Load(PathBuf, tobj::LoadError),
+    Image(PathBuf, image::ImageError),
+    Empty(PathBuf),
+}
+
+impl Display for ObjError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ObjError::Load(path, e) => write!(f, "could not load {}: {}", path.display(), e),
+            ObjError::Image(path, e) => {
+                write!(f, "could not load image {}: {}", path.display(), e)
+            }
+            ObjError::Empty(path) => write!(f, "{} has no triangles", path.display()),
+        }
+    }
+}
+
+impl std::error::Error for ObjError {}
+
+/// Loads a Wavefront OBJ file as one [`TriangleMesh`] per object.
+///
+/// `material` is used for every mesh when given. Otherwise materials come from the MTL
+/// libraries referenced by the file, with a grey Lambertian for faces that have none.
+pub fn load_obj(
+    path: impl AsRef<Path>,
+    material: Option<Arc<dyn Material>>,
+) -> Result<HittableList, ObjError> {
+    let path = path.as_ref();
+    let options = tobj::LoadOptions {
+        single_index: true,
+        triangulate: true,
+        ..Default::default()
+    };
+    let (models, mtl) =
+        tobj::load_obj(path, &options).map_err(|e| ObjError::Load(path.to_path_buf(), e))?;
+
+    let default_material = material
+        .clone()
+        .unwrap_or_else(|| Lambertian::new_rgb(0.73, 0.73, 0.73));
+    let materials = match material {
+        Some(_) => vec![],
+        None => {
+            let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+            mtl.map_err(|e| ObjError::Load(path.to_path_buf(), e))?
+                .iter()
+                .map(|m| mtl_material(m, base_dir))
+                .collect::<Result<Vec<_>, _>>()?
+        }
+    };
+
+    let mut list = HittableList::new();
+    for model in models {
+        let mesh = model.mesh;
+        if mesh.indices.is_empty() {
+            continue;
+        }
+
+        let positions = mesh
+            .positions
+            .chunks_exact(3)
+            .map(|p| point!(p[0], p[1], p[2]))
+            .collect();
+        let normals = mesh
+            .normals
+            .chunks_exact(3)
+            .map(|n| Vec3::new(n[0], n[1], n[2]))
+            .collect();
+        let uvs = mesh
+            .texcoords
+            .chunks_exact(2)
+            .map(|uv| (uv[0], uv[1]))
+            .collect();
+        let indices = mesh
+            .indices
+            .chunks_exact(3)
+            .map(|i| [i[0], i[1], i[2]])
+            .collect();
+        let mat_ptr = mesh
+            .material_id
+            .and_then(|id| materials.get(id))
+            .unwrap_or(&default_material)
+            .clone();
+
+        list.add(TriangleMesh::new(MeshData::new(
+            positions, normals, uvs, indices, mat_ptr,
+        )));
+    }
+
+    if list.objects.is_empty() {
+        return Err(ObjError::Empty(path.to_path_buf()));
+    }
+
+    Ok(list)
+}
+
+/// Maps an MTL material onto the closest material this renderer has: emissive (`Ke`),
+/// transparent (`d` < 1 or a refraction `illum` model), mirror-like (`illum` 3, 5 or 8, with
+/// the specular exponent as roughness), and diffuse otherwise.
+fn mtl_material(m: &tobj::Material, base_dir: &Path) -> Result<Arc<dyn Material>, ObjError> {
+    let emission = m
+        .unknown_param
+        .get("Ke")
+        .map(|ke| {
+            ke.split_whitespace()
+                .filter_map(|x| x.parse::<f32>().ok())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    if let [r, g, b] = emission[..] {
+        if r > 0.0 || g > 0.0 || b > 0.0 {
+            return Ok(DiffuseLight::new_rgb(r, g, b));
+        }
+    }
+
+    Ok(match m.illumination_model {
+        Some(4) | Some(6) | Some(7) | Some(9) => Dielectric::new(ior(m)),
+        _ if m.dissolve < 1.0 => Dielectric::new(ior(m)),
+        Some(3) | Some(5) | Some(8) => {
+            let [r, g, b] = m.specular;
+            // Phong exponent to the matching microfacet width, whose square root is roughness
+            Metal::new_rgbf(r, g, b, (2.0 / (m.shininess + 2.0)).powf(0.25))
+        }
+        _ if !m.diffuse_texture.is_empty() => {
+            let path = base_dir.join(&m.diffuse_texture);
+            let texture = ImageTexture::open(&path).map_err(|e| ObjError::Image(path, e))?;
+            Lambertian::new(texture)
+        }
+        _ => {
+            let [r, g, b] = m.diffuse;
+            Lambertian::new_rgb(r, g, b)
+        }
+    })
+}
+
+fn ior(m: &tobj::Material) -> f32 {
+    if m.optical_density > 1.0 {
+        m.optical_density
+    } else {
+        1.5
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    #[test]
+    fn load_pyramid() {
+        let rng = &mut SmallRng::seed_from_u64(0);
+        let list = load_obj("assets/pyramid.obj", None).unwrap();
+        assert_eq!(list.objects.len(), 1);
+
+        let r = Ray::new(point!(0.0, 5.0, 0.0), vec3!(0.0, -1.0, 0.0), 0.0);
+        let rec = list.hit(rng, &r, 0.001, f32::INFINITY).unwrap();
+        assert!((rec.t - 3.5).abs() < 1e-5);
+        assert!(rec.front_face);
+
+        let missing = load_obj("assets/missing.obj", None);
+        assert!(matches!(missing, Err(ObjError::Load(..))));
+    }
+}
@@ -22,6 +22,11 @@ impl OrthonormalBasis {
         a.x() * self.u() + a.y() * self.v() + a.z() * self.w()
     }
 
+    /// The inverse of `local`: `a` in terms of `u`, `v` and `w`.
+    pub fn to_local(&self, a: Vec3) -> Vec3 {
+        Vec3::new(a.dot(self.u()), a.dot(self.v()), a.dot(self.w()))
+    }
+
     pub fn from_w(n: Vec3) -> Self {
         let w = n.unit_vector();
         let any_vec = if w.x().abs() > 0.9 {
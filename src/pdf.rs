@@ -1,11 +1,12 @@
 use crate::prelude::*;
 
-use crate::{hittable_list::*, onb::*};
+use crate::{hittable_list::*, microfacet::*, onb::*};
 
+use rand::Rng;
 use std::fmt::Debug;
 
 pub trait PDF: Debug {
-    fn value(&self, direction: Vec3) -> f32;
+    fn value(&self, rng: &mut dyn rand::RngCore, direction: Vec3) -> f32;
     fn generate(&self, rng: &mut dyn rand::RngCore) -> Vec3;
 }
 
@@ -24,7 +25,7 @@ impl CosinePDF {
 }
 
 impl PDF for CosinePDF {
-    fn value(&self, direction: Vec3) -> f32 {
+    fn value(&self, _rng: &mut dyn rand::RngCore, direction: Vec3) -> f32 {
         let cos = direction.unit_vector().dot(self.uvw.w());
         (cos / PI).max(0.0)
     }
@@ -34,6 +35,86 @@ impl PDF for CosinePDF {
     }
 }
 
+/// Directions mirrored off, or for a dielectric also refracted through, visible microfacets.
+#[derive(Debug)]
+pub struct MicrofacetPDF {
+    uvw: OrthonormalBasis,
+    /// Direction towards the viewer, in terms of `uvw`
+    wo: Vec3,
+    distribution: TrowbridgeReitz,
+    /// Index of refraction beyond the surface, relative to the side of `wo`
+    eta: Option<f32>,
+}
+
+impl MicrofacetPDF {
+    /// For a surface with normal `normal` seen from `wo`, which points away from it.
+    pub fn new(normal: Vec3, wo: Vec3, distribution: TrowbridgeReitz) -> Box<Self> {
+        let uvw = OrthonormalBasis::from_w(normal);
+        Box::new(Self {
+            wo: uvw.to_local(wo.unit_vector()),
+            uvw,
+            distribution,
+            eta: None,
+        })
+    }
+
+    /// Like `new`, for the boundary of a medium with index of refraction `eta` relative to the
+    /// side of `wo`.
+    pub fn refracting(
+        normal: Vec3,
+        wo: Vec3,
+        distribution: TrowbridgeReitz,
+        eta: f32,
+    ) -> Box<Self> {
+        Box::new(Self {
+            eta: Some(eta),
+            ..*Self::new(normal, wo, distribution)
+        })
+    }
+}
+
+impl PDF for MicrofacetPDF {
+    fn value(&self, _rng: &mut dyn rand::RngCore, direction: Vec3) -> f32 {
+        let wo = self.wo;
+        let wi = self.uvw.to_local(direction.unit_vector());
+        match self.eta {
+            _ if wi.z() > 0.0 => {
+                let h = (wo + wi).unit_vector();
+                let reflectance = self
+                    .eta
+                    .map_or(1.0, |eta| fresnel_dielectric(wo.dot(h), eta));
+                reflectance * self.distribution.visible_normal_pdf(wo, h) / (4.0 * wo.dot(h))
+            }
+            Some(eta) if wi.z() < 0.0 => {
+                let h = refraction_half_vector(wo, wi, eta);
+                let (cos_o, cos_i) = (wo.dot(h), wi.dot(h));
+                if cos_o <= 0.0 || cos_i >= 0.0 {
+                    return 0.0;
+                }
+                // Jacobian of the refracted direction with respect to the microfacet normal
+                let jacobian = eta * eta * -cos_i / (cos_o + eta * cos_i).powi(2);
+                (1.0 - fresnel_dielectric(cos_o, eta))
+                    * self.distribution.visible_normal_pdf(wo, h)
+                    * jacobian
+            }
+            _ => 0.0,
+        }
+    }
+
+    fn generate(&self, rng: &mut dyn rand::RngCore) -> Vec3 {
+        let h = self.distribution.sample_visible_normal(rng, self.wo);
+        let wi = match self.eta {
+            Some(eta) if rng.gen::<f32>() >= fresnel_dielectric(self.wo.dot(h), eta) => {
+                refract_through(self.wo, h, eta).filter(|wi| wi.z() < 0.0)
+            }
+            _ => Some(reflect(-self.wo, h)).filter(|wi| wi.z() > 0.0),
+        };
+        // `value` would count a direction that ended up on the wrong side of the surface
+        // towards the other lobe, so drop it with one it gives no density
+        wi.map_or_else(Vec3::origin, |wi| self.uvw.local(wi))
+    }
+}
+
 #[derive(Debug)]
 pub struct HittablePDF<'a> {
     o: Point3,
@@ -47,34 +128,168 @@ impl<'a> HittablePDF<'a> {
 }
 
 impl PDF for HittablePDF<'_> {
-    fn value(&self, direction: Vec3) -> f32 {
-        self.ptr.pdf_value(self.o, direction)
+    fn value(&self, rng: &mut dyn rand::RngCore, direction: Vec3) -> f32 {
+        self.ptr.pdf_value(rng, self.o, direction)
     }
     fn generate(&self, rng: &mut dyn rand::RngCore) -> Vec3 {
         self.ptr.random(rng, self.o)
     }
 }
 
+/// Picks one of several densities at random, each with the probability it is weighted by.
 #[derive(Debug)]
 pub struct MixturePDF<'a> {
-    p: [Box<dyn PDF + 'a>; 2],
+    p: Vec<(f32, Box<dyn PDF + 'a>)>,
 }
 
 impl<'a> MixturePDF<'a> {
     pub fn new(p1: Box<dyn PDF + 'a>, p2: Box<dyn PDF + 'a>) -> Self {
-        Self { p: [p1, p2] }
+        Self::weighted(vec![(0.5, p1), (0.5, p2)])
+    }
+
+    /// A mixture of densities paired with weights, which are normalized to add up to 1.
+    pub fn weighted(p: Vec<(f32, Box<dyn PDF + 'a>)>) -> Self {
+        let total: f32 = p.iter().map(|(weight, _)| weight).sum();
+        Self {
+            p: p.into_iter()
+                .map(|(weight, pdf)| (weight / total, pdf))
+                .collect(),
+        }
     }
 }
 
 impl PDF for MixturePDF<'_> {
-    fn value(&self, direction: Vec3) -> f32 {
-        0.5 * self.p[0].value(direction) + 0.5 * self.p[1].value(direction)
+    fn value(&self, rng: &mut dyn rand::RngCore, direction: Vec3) -> f32 {
+        self.p
+            .iter()
+            .map(|(weight, pdf)| weight * pdf.value(rng, direction))
+            .sum()
     }
     fn generate(&self, rng: &mut dyn rand::RngCore) -> Vec3 {
-        if rand::random::<f32>() < 0.5 {
-            self.p[0].generate(rng)
-        } else {
-            self.p[1].generate(rng)
+        let mut u = rng.gen::<f32>();
+        for (weight, pdf) in &self.p {
+            if u < *weight {
+                return pdf.generate(rng);
+            }
+            u -= weight;
+        }
+        // Rounding left `u` past the last weight
+        self.p.last().expect("empty mixture").1.generate(rng)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::sphere::*;
+
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    /// Monte Carlo estimate of the integral of `pdf` over the sphere of directions.
+    fn integrate(pdf: &dyn PDF, rng: &mut SmallRng) -> f32 {
+        const N: usize = 200_000;
+        let sum: f32 = (0..N)
+            .map(|_| {
+                let direction = random_in_unit_sphere(rng).unit_vector();
+                pdf.value(rng, direction)
+            })
+            .sum();
+        sum / N as f32 * 4.0 * PI
+    }
+
+    #[test]
+    fn cosine_normalized() {
+        let rng = &mut SmallRng::seed_from_u64(0);
+        let pdf = CosinePDF::new(Vec3::new(1.0, 2.0, -0.5));
+        assert!((integrate(&*pdf, rng) - 1.0).abs() < 0.02);
+    }
+
+    #[test]
+    fn microfacet_normalized() {
+        let rng = &mut SmallRng::seed_from_u64(0);
+        // Some reflections go below the surface, which `value` does not count
+        let distribution = TrowbridgeReitz::from_roughness(0.3, 0.6);
+        let normal = Vec3::new(0.2, 1.0, -0.4);
+        let wo = Vec3::new(1.0, 0.3, 0.2);
+        let pdf = MicrofacetPDF::new(normal, wo, distribution);
+        let below = (0..100_000)
+            .filter(|_| pdf.generate(rng).dot(normal) <= 0.0)
+            .count() as f32
+            / 100_000.0;
+        assert!((integrate(&*pdf, rng) - (1.0 - below)).abs() < 0.02);
+    }
+
+    #[test]
+    fn refracting_microfacet_samples_follow_value() {
+        let rng = &mut SmallRng::seed_from_u64(0);
+        let distribution = TrowbridgeReitz::from_roughness(0.5, 0.5);
+        let normal = Vec3::new(0.0, 1.0, 0.0);
+        let wo = Vec3::new(0.3, 1.0, 0.1).unit_vector();
+
+        // From outside and from inside glass, where much is reflected totally, the fraction of
+        // samples in cones around the reflected and the refracted direction should match the
+        // integral of `value` over them
+        for &eta in &[1.5, 1.0 / 1.5] {
+            let pdf = MicrofacetPDF::refracting(normal, wo, distribution, eta);
+            let refracted = refract(-wo, normal, 1.0 / eta);
+            for &axis in &[reflect(-wo, normal), refracted] {
+                let in_cone = |direction: Vec3| direction.unit_vector().dot(axis) > 0.9;
+                const N: usize = 200_000;
+                let fraction =
+                    (0..N).filter(|_| in_cone(pdf.generate(rng))).count() as f32 / N as f32;
+                let integral = (0..N)
+                    .map(|_| {
+                        let direction = random_unit_vector(rng);
+                        if in_cone(direction) {
+                            pdf.value(rng, direction)
+                        } else {
+                            0.0
+                        }
+                    })
+                    .sum::<f32>()
+                    / N as f32
+                    * 4.0
+                    * PI;
+                assert!(
+                    (fraction - integral).abs() < 0.05 * fraction,
+                    "{} {}",
+                    fraction,
+                    integral
+                );
+            }
         }
     }
+
+    #[test]
+    fn hittable_normalized() {
+        let rng = &mut SmallRng::seed_from_u64(0);
+        let mut lights = HittableList::new();
+        lights.add(Sphere::new(
+            point!(0.0, 3.0, 0.0),
+            1.5,
+            Lambertian::new_rgb(0.5, 0.5, 0.5),
+        ));
+        let pdf = HittablePDF::new(&lights, Point3::origin());
+        assert!((integrate(&*pdf, rng) - 1.0).abs() < 0.02);
+
+        // Generated directions all point towards the sphere
+        for _ in 0..100 {
+            let direction = pdf.generate(rng);
+            assert!(pdf.value(rng, direction) > 0.0);
+        }
+    }
+
+    #[test]
+    fn weighted_mixture() {
+        let rng = &mut SmallRng::seed_from_u64(0);
+        let up = vec3!(0.0, 1.0, 0.0);
+        let pdf = MixturePDF::weighted(vec![(3.0, CosinePDF::new(up)), (1.0, CosinePDF::new(-up))]);
+        assert!((integrate(&pdf, rng) - 1.0).abs() < 0.02);
+
+        // Each density is picked in proportion to its weight
+        const N: usize = 100_000;
+        let above = (0..N).filter(|_| pdf.generate(rng).y() > 0.0).count();
+        assert!((above as f32 / N as f32 - 0.75).abs() < 0.01);
+    }
 }
@@ -2,7 +2,6 @@ use crate::prelude::*;
 
 use itertools::Itertools;
 use rand::Rng;
-use std::mem::MaybeUninit;
 #[derive(Debug)]
 pub struct Perlin {
     ran_vec: Vec<Vec3>,
@@ -14,17 +13,17 @@ pub struct Perlin {
 impl Perlin {
     const POINT_COUNT: usize = 256;
 
-    pub fn new() -> Self {
-        let ran_vec = rand::thread_rng()
+    pub fn new<R: Rng + ?Sized>(rng: &mut R) -> Self {
+        let ran_vec = (&mut *rng)
             .sample_iter(rand::distributions::Uniform::new(-1.0, 1.0))
             .tuples()
             .map(|(e0, e1, e2)| Vec3::new(e0, e1, e2))
             .take(Self::POINT_COUNT)
             .collect();
 
-        let perm_x = Self::perlin_generate_perm();
-        let perm_y = Self::perlin_generate_perm();
-        let perm_z = Self::perlin_generate_perm();
+        let perm_x = Self::perlin_generate_perm(rng);
+        let perm_y = Self::perlin_generate_perm(rng);
+        let perm_z = Self::perlin_generate_perm(rng);
 
         Self {
             ran_vec,
@@ -42,24 +41,16 @@ impl Perlin {
         let j = p.y().floor() as i32;
         let k = p.z().floor() as i32;
 
-        let c = {
-            let mut c: [[[MaybeUninit<Vec3>; 2]; 2]; 2] =
-                unsafe { MaybeUninit::uninit().assume_init() };
-
-            for di in 0..2 {
-                for dj in 0..2 {
-                    for dk in 0..2 {
-                        c[di][dj][dk] = MaybeUninit::new(
-                            self.ran_vec[self.perm_x[((i + di as i32) & 255) as usize]
-                                ^ self.perm_y[((j + dj as i32) & 255) as usize]
-                                ^ self.perm_z[((k + dk as i32) & 255) as usize]],
-                        );
-                    }
+        let mut c = [[[Vec3::origin(); 2]; 2]; 2];
+        for (di, plane) in c.iter_mut().enumerate() {
+            for (dj, row) in plane.iter_mut().enumerate() {
+                for (dk, cell) in row.iter_mut().enumerate() {
+                    *cell = self.ran_vec[self.perm_x[((i + di as i32) & 255) as usize]
+                        ^ self.perm_y[((j + dj as i32) & 255) as usize]
+                        ^ self.perm_z[((k + dk as i32) & 255) as usize]];
                 }
             }
-
-            unsafe { std::mem::transmute::<_, [[[Vec3; 2]; 2]; 2]>(c) }
-        };
+        }
 
         Self::perlin_interp(&c, u, v, w)
     }
@@ -80,18 +71,17 @@ impl Perlin {
 }
 
 impl Perlin {
-    fn perlin_generate_perm() -> Vec<usize> {
+    fn perlin_generate_perm<R: Rng + ?Sized>(rng: &mut R) -> Vec<usize> {
         let mut p = Vec::with_capacity(Self::POINT_COUNT);
         for i in 0..Self::POINT_COUNT {
             p.push(i);
         }
-        Self::permute(&mut p);
+        Self::permute(rng, &mut p);
 
         p
     }
 
-    fn permute(p: &mut Vec<usize>) {
-        let mut rng = rand::thread_rng();
+    fn permute<R: Rng + ?Sized>(rng: &mut R, p: &mut [usize]) {
         for i in (1..Self::POINT_COUNT).rev() {
             let target = rng.gen_range(0..i);
             p.swap(i, target);
@@ -105,14 +95,15 @@ impl Perlin {
         let ww = w * w * (3.0 - 2.0 * w);
         let mut accum = 0.0;
 
-        for i in 0..2 {
-            for j in 0..2 {
-                for k in 0..2 {
-                    let weight_v = Vec3::new(u - i as f32, v - j as f32, w - k as f32);
-                    accum += (i as f32 * uu + (1.0 - i as f32) * (1.0 - uu))
-                        * (j as f32 * vv + (1.0 - j as f32) * (1.0 - vv))
-                        * (k as f32 * ww + (1.0 - k as f32) * (1.0 - ww))
-                        * c[i][j][k].dot(weight_v);
+        for (i, plane) in c.iter().enumerate() {
+            for (j, row) in plane.iter().enumerate() {
+                for (k, cell) in row.iter().enumerate() {
+                    let (i, j, k) = (i as f32, j as f32, k as f32);
+                    let weight_v = Vec3::new(u - i, v - j, w - k);
+                    accum += (i * uu + (1.0 - i) * (1.0 - uu))
+                        * (j * vv + (1.0 - j) * (1.0 - vv))
+                        * (k * ww + (1.0 - k) * (1.0 - ww))
+                        * cell.dot(weight_v);
                 }
             }
         }
@@ -0,0 +1,571 @@
+use crate::prelude::*;
+
+use crate::{camera::*, integrator::*, render::*, spectrum::*, worlds::*};
+
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+use std::{
+    any::Any,
+    fmt::Debug,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+};
+
+/// Light that arrived at a diffuse surface.
+#[derive(Debug, Copy, Clone)]
+pub struct Photon {
+    pub p: Point3,
+    /// Direction it came from
+    pub wi: Vec3,
+    /// Flux it carries
+    pub power: Color,
+}
+
+/// A kd-tree of photons, for finding those near a point.
+pub struct PhotonMap {
+    /// Balanced tree stored in place, each range's middle photon being its root
+    photons: Vec<Photon>,
+    /// Axis each photon splits its subtree on
+    axes: Vec<u8>,
+}
+
+impl PhotonMap {
+    pub fn new(mut photons: Vec<Photon>) -> Self {
+        let mut axes = vec![0; photons.len()];
+        build(&mut photons, &mut axes);
+        Self { photons, axes }
+    }
+
+    pub fn len(&self) -> usize {
+        self.photons.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.photons.is_empty()
+    }
+
+    /// Calls `f` with every photon within `radius` of `p`.
+    pub fn for_each_near(&self, p: Point3, radius: f32, mut f: impl FnMut(&Photon)) {
+        self.search(0, self.photons.len(), p, radius * radius, &mut f);
+    }
+
+    fn search(
+        &self,
+        lo: usize,
+        hi: usize,
+        p: Point3,
+        radius_squared: f32,
+        f: &mut impl FnMut(&Photon),
+    ) {
+        if lo >= hi {
+            return;
+        }
+
+        let mid = lo + (hi - lo) / 2;
+        let photon = &self.photons[mid];
+        if (photon.p - p).length_squared() <= radius_squared {
+            f(photon);
+        }
+
+        let axis = self.axes[mid] as usize;
+        let d = p.to_array()[axis] - photon.p.to_array()[axis];
+        let (near, far) = if d < 0.0 {
+            ((lo, mid), (mid + 1, hi))
+        } else {
+            ((mid + 1, hi), (lo, mid))
+        };
+        self.search(near.0, near.1, p, radius_squared, f);
+        if d * d <= radius_squared {
+            self.search(far.0, far.1, p, radius_squared, f);
+        }
+    }
+}
+
+impl Debug for PhotonMap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PhotonMap")
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+/// Sorts `photons` into a balanced kd-tree, splitting on the axis of widest spread.
+fn build(photons: &mut [Photon], axes: &mut [u8]) {
+    if photons.len() <= 1 {
+        return;
+    }
+
+    let (min, max) = photons.iter().fold(
+        (
+            Vec3::from_scalar(f32::INFINITY),
+            Vec3::from_scalar(-f32::INFINITY),
+        ),
+        |(min, max), photon| (min.min(photon.p), max.max(photon.p)),
+    );
+    let [x, y, z] = (max - min).to_array();
+    let axis = if x >= y && x >= z {
+        0
+    } else if y >= z {
+        1
+    } else {
+        2
+    };
+
+    let mid = photons.len() / 2;
+    photons.select_nth_unstable_by(mid, |a, b| {
+        a.p.to_array()[axis].total_cmp(&b.p.to_array()[axis])
+    });
+    axes[mid] = axis as u8;
+
+    let (left, right) = photons.split_at_mut(mid);
+    let (left_axes, right_axes) = axes.split_at_mut(mid);
+    build(left, left_axes);
+    build(&mut right[1..], &mut right_axes[1..]);
+}
+
+/// Stochastic progressive photon mapping (Knaus and Zwicker), one iteration per sample index.
+/// Spectral renders trace each iteration at one wavelength.
+#[derive(Debug)]
+pub struct PhotonMapping {
+    /// Photons emitted per iteration
+    pub photons: u32,
+    /// Gather radius of the first iteration; by default four pixels wide where it is taken.
+    pub radius: Option<f32>,
+}
+
+/// Photon maps of a pass's recent iterations, most recently used last.
+#[derive(Debug, Default)]
+struct Iterations(Mutex<Vec<(u32, Arc<OnceLock<Iteration>>)>>);
+
+#[derive(Debug)]
+struct Iteration {
+    map: PhotonMap,
+    /// Square of the gather radius, relative to the first iteration's
+    radius_scale: f32,
+}
+
+impl PhotonMapping {
+    pub const DEFAULT_PHOTONS: u32 = 100_000;
+
+    /// Share of the photon density each iteration keeps; lower values shrink the radius faster.
+    const ALPHA: f32 = 2.0 / 3.0;
+
+    /// Iterations whose photons are kept around, and so the most samples per pass.
+    const CACHED_ITERATIONS: usize = 16;
+
+    /// Photons traced with one RNG stream, so the photons do not depend on the thread count.
+    const PHOTONS_PER_TASK: u32 = 4096;
+
+    pub fn new(photons: u32, radius: Option<f32>) -> Self {
+        Self { photons, radius }
+    }
+
+    /// The photons of iteration `index`, traced by whichever thread needs them first.
+    fn iteration(&self, ctx: &SampleContext, index: u32) -> Arc<OnceLock<Iteration>> {
+        let cell = {
+            let iterations = ctx
+                .pass
+                .and_then(|pass| pass.downcast_ref::<Iterations>())
+                .expect("photon mapping samples need the state of `Integrator::begin_pass`");
+            let mut iterations = iterations.0.lock().unwrap();
+            let cell = match iterations.iter().position(|&(i, _)| i == index) {
+                Some(position) => iterations.remove(position).1,
+                None => Arc::new(OnceLock::new()),
+            };
+            iterations.push((index, cell.clone()));
+            if iterations.len() > Self::CACHED_ITERATIONS {
+                iterations.remove(0);
+            }
+            cell
+        };
+
+        cell.get_or_init(|| Iteration {
+            map: self.trace_photons(ctx, index),
+            radius_scale: (1..=index)
+                .map(|i| (i as f32 + Self::ALPHA) / (i as f32 + 1.0))
+                .product(),
+        });
+        cell
+    }
+
+    /// Wavelength that photons and camera rays share in iteration `index` of a spectral render.
+    fn iteration_wavelength(&self, settings: &RenderSettings, index: u32) -> Option<f32> {
+        if !settings.spectral {
+            return None;
+        }
+        let start = SmallRng::seed_from_u64(mix_seed(settings.seed, u64::MAX - 2)).gen();
+        Some(sample_wavelength(golden_sequence(start, index)))
+    }
+
+    /// Traces the photons of iteration `index` on plain threads, since a rayon thread waiting
+    /// for them could pick up render work that needs this very iteration.
+    fn trace_photons(&self, ctx: &SampleContext, index: u32) -> PhotonMap {
+        let SampleContext {
+            world,
+            camera,
+            settings,
+            ..
+        } = *ctx;
+
+        // Pixel indices never reach this stream
+        let seed = mix_seed(mix_seed(settings.seed, u64::MAX), index as u64);
+        let tasks = self.photons.div_ceil(Self::PHOTONS_PER_TASK);
+        let wavelength = self.iteration_wavelength(settings, index);
+        let next = AtomicU32::new(0);
+        let mut traced: Vec<(u32, Vec<Photon>)> = std::thread::scope(|scope| {
+            let threads: Vec<_> = (0..rayon::current_num_threads().min(tasks as usize))
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut traced = Vec::new();
+                        loop {
+                            let task = next.fetch_add(1, Ordering::Relaxed);
+                            if task >= tasks {
+                                return traced;
+                            }
+                            let rng = &mut SmallRng::seed_from_u64(mix_seed(seed, task as u64));
+                            let count = Self::PHOTONS_PER_TASK
+                                .min(self.photons - task * Self::PHOTONS_PER_TASK);
+                            let photons =
+                                self.trace_task(world, camera, settings, wavelength, rng, count);
+                            traced.push((task, photons));
+                        }
+                    })
+                })
+                .collect();
+            threads
+                .into_iter()
+                .flat_map(|thread| thread.join().unwrap())
+                .collect()
+        });
+
+        traced.sort_by_key(|&(task, _)| task);
+        PhotonMap::new(
+            traced
+                .into_iter()
+                .flat_map(|(_, photons)| photons)
+                .collect(),
+        )
+    }
+
+    fn trace_task(
+        &self,
+        world: &World,
+        camera: &Camera,
+        settings: &RenderSettings,
+        wavelength: Option<f32>,
+        rng: &mut SmallRng,
+        count: u32,
+    ) -> Vec<Photon> {
+        let mut photons = Vec::new();
+        for _ in 0..count {
+            let emission = match world.sample_emission(rng) {
+                Some(emission) => emission,
+                None => continue,
+            };
+            let cos = emission.rec.geometric_normal.dot(emission.direction).abs();
+            let mut power = emission.radiance * cos
+                / (emission.pdf_pos * emission.pdf_dir * self.photons as f32);
+            let emitted = max_component(power);
+            let mut ray = Ray::new(emission.rec.p, emission.direction, camera.shutter_time(rng))
+                .with_wavelength(wavelength);
+
+            for depth in 0..settings.max_depth {
+                let rec = match world.world().hit(rng, &ray, 0.001, f32::INFINITY) {
+                    Some(rec) => rec,
+                    None => break,
+                };
+                power = power
+                    * rec
+                        .mat_ptr
+                        .transmittance(&rec, rec.t * ray.direction().length());
+                let srec = match rec.mat_ptr.scatter(rng, &ray, &rec) {
+                    Some(srec) => srec,
+                    None => break,
+                };
+
+                if let Some(specular) = srec.specular_ray {
+                    power = power * srec.attenuation;
+                    ray = specular;
+                } else {
+                    // Light straight from the emitter is sampled at the gather points
+                    if depth > 0 {
+                        photons.push(Photon {
+                            p: rec.p,
+                            wi: -ray.direction().unit_vector(),
+                            power,
+                        });
+                    }
+
+                    let pdf = srec.pdf_ptr.as_ref().unwrap();
+                    let scattered = Ray::new(rec.p, pdf.generate(rng), ray.time())
+                        .with_wavelength(ray.wavelength());
+                    let pdf_val = pdf.value(rng, scattered.direction());
+                    if pdf_val <= 0.0 {
+                        break;
+                    }
+                    power = power * rec.mat_ptr.scattering(&ray, &rec, &srec, &scattered) / pdf_val;
+                    ray = scattered;
+                }
+
+                if depth + 1 >= settings.roulette_depth {
+                    let survival = (max_component(power) / emitted).min(0.95);
+                    if rng.gen::<f32>() >= survival {
+                        break;
+                    }
+                    power /= survival;
+                }
+            }
+        }
+
+        photons
+    }
+
+    /// Direct light and the photons gathered within `radius`, scaled down per iteration.
+    fn gather(
+        &self,
+        rng: &mut dyn rand::RngCore,
+        ctx: &SampleContext,
+        r: &Ray,
+        rec: &HitRecord,
+        srec: &ScatterRecord,
+        radius: f32,
+    ) -> Color {
+        let world = ctx.world;
+        let mut radiance = Color::black();
+
+        let lights = world.lights();
+        if !lights.objects.is_empty() {
+            let shadow = Ray::new(rec.p, lights.random(rng, rec.p), r.time());
+            let light_pdf = lights.pdf_value(rng, rec.p, shadow.direction());
+            let scattering = rec.mat_ptr.scattering(r, rec, srec, &shadow);
+            if light_pdf > 0.0 && scattering != Color::black() {
+                if let Some(light) = world.world().hit(rng, &shadow, 0.001, f32::INFINITY) {
+                    radiance += scattering * light.mat_ptr.emitted(&light) / light_pdf;
+                }
+            }
+        }
+
+        let iteration = self.iteration(ctx, ctx.sample);
+        let Iteration { map, radius_scale } = iteration.get().unwrap();
+        let radius_squared = radius * radius * radius_scale;
+        let mut flux = Color::black();
+        map.for_each_near(rec.p, radius_squared.sqrt(), |photon| {
+            let cos = rec.normal.dot(photon.wi);
+            if cos > 0.0 {
+                let wi = Ray::new(rec.p, photon.wi, r.time());
+                flux += photon.power * rec.mat_ptr.scattering(r, rec, srec, &wi) / cos;
+            }
+        });
+        radiance + flux / (PI * radius_squared)
+    }
+}
+
+fn max_component(c: Color) -> f32 {
+    let [r, g, b] = c.to_array();
+    r.max(g).max(b)
+}
+
+impl Integrator for PhotonMapping {
+    fn samples_per_pass(&self) -> Option<u32> {
+        Some(Self::CACHED_ITERATIONS as u32)
+    }
+
+    fn wavelength(&self, _u: f32, ctx: &SampleContext) -> Option<f32> {
+        self.iteration_wavelength(ctx.settings, ctx.sample)
+    }
+
+    fn begin_pass(
+        &self,
+        _world: &World,
+        _camera: &Camera,
+        _settings: &RenderSettings,
+    ) -> Option<Box<dyn Any + Send + Sync>> {
+        Some(Box::new(Iterations::default()))
+    }
+
+    fn radiance(&self, rng: &mut dyn rand::RngCore, r: &Ray, ctx: &mut SampleContext) -> Color {
+        let settings = ctx.settings;
+        let mut ray = *r;
+        let mut throughput = rgb!(1.0, 1.0, 1.0);
+        let mut radiance = Color::black();
+        let mut distance = 0.0;
+
+        for _ in 0..settings.max_depth {
+            let rec = match ctx.world.world().hit(rng, &ray, 0.001, f32::INFINITY) {
+                Some(rec) => rec,
+                None => return radiance + throughput * settings.background,
+            };
+            let length = rec.t * ray.direction().length();
+            distance += length;
+            throughput = throughput * rec.mat_ptr.transmittance(&rec, length);
+            radiance += throughput * rec.mat_ptr.emitted(&rec);
+
+            let srec = match rec.mat_ptr.scatter(rng, &ray, &rec) {
+                Some(srec) => srec,
+                None => break,
+            };
+            if let Some(specular) = srec.specular_ray {
+                throughput = throughput * srec.attenuation;
+                ray = specular;
+                continue;
+            }
+
+            let radius = self.radius.unwrap_or_else(|| {
+                let pixel_area =
+                    ctx.camera.image_area() / (settings.image_width * settings.image_height) as f32;
+                4.0 * distance * pixel_area.sqrt()
+            });
+            return radiance + throughput * self.gather(rng, ctx, &ray, &rec, &srec, radius);
+        }
+
+        radiance
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{color::*, framebuffer::*, hittable_list::*, scene::*, sphere::*};
+
+    #[test]
+    fn finds_photons_within_radius() {
+        let rng = &mut SmallRng::seed_from_u64(1);
+        let photons: Vec<Photon> = (0..500)
+            .map(|_| Photon {
+                p: point!(rng.gen::<f32>(), rng.gen::<f32>(), 0.25 * rng.gen::<f32>()),
+                wi: vec3!(0.0, 1.0, 0.0),
+                power: Color::from_scalar(rng.gen::<f32>()),
+            })
+            .collect();
+        let map = PhotonMap::new(photons.clone());
+        assert_eq!(map.len(), photons.len());
+
+        for _ in 0..20 {
+            let p = point!(rng.gen::<f32>(), rng.gen::<f32>(), rng.gen::<f32>());
+            let radius = 0.2 * rng.gen::<f32>();
+
+            let mut found = Vec::new();
+            map.for_each_near(p, radius, |photon| found.push(photon.power.x()));
+            let mut expected: Vec<f32> = photons
+                .iter()
+                .filter(|photon| (photon.p - p).length() <= radius)
+                .map(|photon| photon.power.x())
+                .collect();
+
+            found.sort_by(f32::total_cmp);
+            expected.sort_by(f32::total_cmp);
+            assert_eq!(found, expected);
+        }
+    }
+
+    #[test]
+    fn matches_path_tracer() {
+        // A glass ball focusing a sphere light onto a diffuse floor, with a ceiling above the
+        // light for some diffuse interreflection
+        let mut world = HittableList::new();
+        let mut lights = HittableList::new();
+        world.add(Sphere::new(
+            point!(0.0, -1000.0, 0.0),
+            1000.0,
+            Lambertian::new_rgb(0.5, 0.5, 0.5),
+        ));
+        world.add(Sphere::new(
+            point!(0.0, 1006.0, 0.0),
+            1000.0,
+            Lambertian::new_rgb(0.8, 0.8, 0.8),
+        ));
+        world.add(Sphere::new(
+            point!(0.0, 1.0, 0.0),
+            0.5,
+            Dielectric::new(1.5),
+        ));
+        let light: Arc<dyn Hittable> =
+            Sphere::new(point!(0.0, 4.0, 0.0), 0.5, DiffuseLight::white(16.0));
+        world.add(light.clone());
+        lights.add(light);
+        let world = World::new(world, lights);
+
+        let camera = CameraSettings {
+            look_from: point!(0.0, 5.0, 4.0),
+            look_at: point!(0.0, 0.0, 0.0),
+            vfov: 40.0,
+            ..CameraSettings::default()
+        }
+        .build(1.0);
+        let renderer = Renderer::new(RenderSettings {
+            image_width: 16,
+            image_height: 16,
+            samples_per_pixel: 32,
+            background: Color::black(),
+            seed: 3,
+            ..RenderSettings::default()
+        });
+
+        let mean = |fb: Framebuffer| {
+            let pixels = fb.pixels();
+            pixels.iter().map(|&c| luminance(c)).sum::<f32>() / pixels.len() as f32
+        };
+        let path = mean(renderer.render(&world, &camera));
+        // Spectral renders trace the photons of each iteration at one wavelength, and need more
+        // iterations for those to cover the spectrum
+        for &(spectral, samples_per_pixel) in &[(false, 32), (true, 128)] {
+            let photons = mean(
+                Renderer::new(RenderSettings {
+                    spectral,
+                    samples_per_pixel,
+                    ..*renderer.settings()
+                })
+                .with_integrator(Arc::new(PhotonMapping::new(5_000, Some(0.2))))
+                .render(&world, &camera),
+            );
+            assert!(
+                (photons - path).abs() < 0.03 * path,
+                "path {} photons {} (spectral: {})",
+                path,
+                photons,
+                spectral
+            );
+        }
+    }
+
+    #[test]
+    fn reused_across_scenes() {
+        let scene = |brightness: f32| {
+            let mut world = HittableList::new();
+            let mut lights = HittableList::new();
+            world.add(Sphere::new(
+                point!(0.0, -1000.0, 0.0),
+                1000.0,
+                Lambertian::new_rgb(0.5, 0.5, 0.5),
+            ));
+            let light: Arc<dyn Hittable> =
+                Sphere::new(point!(0.0, 1.5, 0.0), 0.5, DiffuseLight::white(brightness));
+            world.add(light.clone());
+            lights.add(light);
+            World::new(world, lights)
+        };
+        let camera = CameraSettings {
+            look_from: point!(0.0, 6.0, 0.1),
+            look_at: point!(0.0, 0.0, 0.0),
+            ..CameraSettings::default()
+        }
+        .build(1.0);
+        let settings = RenderSettings {
+            image_width: 4,
+            image_height: 4,
+            samples_per_pixel: 4,
+            background: Color::black(),
+            ..RenderSettings::default()
+        };
+        let integrator = || Arc::new(PhotonMapping::new(1_000, Some(0.2)));
+
+        let reused = Renderer::new(settings).with_integrator(integrator());
+        reused.render(&scene(1.0), &camera);
+        let fresh = Renderer::new(settings).with_integrator(integrator());
+        assert_eq!(
+            reused.render(&scene(8.0), &camera).pixels(),
+            fresh.render(&scene(8.0), &camera).pixels()
+        );
+    }
+}
@@ -1,6 +1,6 @@
 pub use crate::{aabb::*, hittable::*, material::*, ray::*, texture::*, util::*, vec3::*};
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, serde::Serialize, serde::Deserialize)]
 pub enum Plane {
     Xy,
     Xz,
@@ -1,14 +1,27 @@
 use crate::prelude::*;
 
+#[derive(Debug, Copy, Clone)]
 pub struct Ray {
     orig: Point3,
     dir: Vec3,
     tm: f32,
+    /// In nanometers, for rays of spectral renders; RGB rays carry every wavelength at once
+    wavelength: Option<f32>,
 }
 
 impl Ray {
     pub fn new(orig: Point3, dir: Vec3, tm: f32) -> Self {
-        Self { orig, dir, tm }
+        Self {
+            orig,
+            dir,
+            tm,
+            wavelength: None,
+        }
+    }
+
+    /// The same ray, carrying light of `wavelength` only if that is not `None`.
+    pub fn with_wavelength(self, wavelength: Option<f32>) -> Self {
+        Self { wavelength, ..self }
     }
 
     pub fn origin(&self) -> Point3 {
@@ -20,6 +33,9 @@ impl Ray {
     pub fn time(&self) -> f32 {
         self.tm
     }
+    pub fn wavelength(&self) -> Option<f32> {
+        self.wavelength
+    }
 
     pub fn at(&self, t: f32) -> Point3 {
         self.orig + t * self.dir
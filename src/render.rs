@@ -0,0 +1,662 @@
+use crate::prelude::*;
+
+use crate::{
+    camera::*, color::*, framebuffer::*, integrator::*, scene::*, spectrum::*, tile::*, worlds::*,
+};
+
+use indicatif::ProgressBar;
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    any::Any,
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+#[derive(Debug, Copy, Clone)]
+pub struct RenderSettings {
+    pub image_width: u32,
+    pub image_height: u32,
+    pub samples_per_pixel: u32,
+    pub max_depth: u32,
+    /// Number of bounces before paths may be terminated by Russian roulette.
+    pub roulette_depth: u32,
+    pub background: Color,
+    pub integrator: IntegratorDesc,
+    /// Each sample of pixel `(i, j)` uses an RNG seeded from `seed`, the pixel index and the
+    /// sample index, so the image does not depend on how the work is scheduled.
+    pub seed: u64,
+    /// Pixels are rendered in square tiles of this size, handed out to threads in `tile_order`.
+    pub tile_size: u32,
+    pub tile_order: TileOrder,
+    /// Only render these pixels; the others are left as they are. Cropped pixels are sampled
+    /// exactly like in the full image.
+    pub crop: Option<PixelRect>,
+    /// Only render this share of the tiles, for splitting a render between processes.
+    pub share: Option<TileShare>,
+    /// Index of the first sample of each pixel, so renders with different offsets take
+    /// different samples and can be merged.
+    pub sample_offset: u32,
+    /// Trace a single wavelength per camera sample.
+    pub spectral: bool,
+    /// Show a progress bar on stderr.
+    pub progress: bool,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        let image = ImageSettings::default();
+
+        Self {
+            image_width: image.image_width(),
+            image_height: image.image_height,
+            samples_per_pixel: image.samples_per_pixel,
+            max_depth: 50,
+            roulette_depth: 3,
+            background: rgb!(0.70, 0.80, 1.00),
+            integrator: IntegratorDesc::default(),
+            seed: 0,
+            tile_size: 32,
+            tile_order: TileOrder::default(),
+            crop: None,
+            share: None,
+            sample_offset: 0,
+            spectral: false,
+            progress: false,
+        }
+    }
+}
+
+impl RenderSettings {
+    /// Takes the resolution, sample count, crop window and background from `scene`.
+    pub fn for_scene(scene: &Scene) -> Self {
+        let (width, height) = (scene.image.image_width(), scene.image.image_height);
+
+        Self {
+            image_width: width,
+            image_height: height,
+            samples_per_pixel: scene.image.samples_per_pixel,
+            background: scene.background,
+            crop: scene.image.crop.map(|crop| {
+                crop.to_pixels(width, height)
+                    .unwrap_or(PixelRect::new(0, 0, 0, 0))
+            }),
+            ..Self::default()
+        }
+    }
+
+    /// The pixels to render: the crop window, or else the whole image.
+    pub fn region(&self) -> PixelRect {
+        let full = PixelRect::full(self.image_width, self.image_height);
+        self.crop
+            .map_or(Some(full), |crop| crop.intersect(&full))
+            .unwrap_or(PixelRect::new(0, 0, 0, 0))
+    }
+
+    /// The tiles to render, in order: those of `region` that belong to the share.
+    pub fn tiles(&self) -> Vec<PixelRect> {
+        tiles(self.region(), self.tile_size, self.tile_order, self.share)
+    }
+}
+
+/// Stops sampling a pixel once `Accumulator::error` is below `max_error` there and at its
+/// neighbors, which keeps pixels whose first samples all happened to agree from stopping early.
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AdaptiveSettings {
+    pub min_samples: u32,
+    pub max_samples: u32,
+    pub max_error: f32,
+}
+
+impl Default for AdaptiveSettings {
+    fn default() -> Self {
+        Self {
+            min_samples: 16,
+            max_samples: 1024,
+            max_error: 0.01,
+        }
+    }
+}
+
+impl AdaptiveSettings {
+    /// Which pixels of `accumulator` still need samples, out of those in `tiles`.
+    pub fn active(&self, accumulator: &Accumulator, tiles: &[PixelRect]) -> Vec<bool> {
+        let (width, height) = (accumulator.width(), accumulator.height());
+        let samples = accumulator.samples();
+
+        let mut included = vec![false; samples.len()];
+        for (x, y) in tiles.iter().flat_map(PixelRect::pixels) {
+            included[(y * width + x) as usize] = true;
+        }
+
+        let unconverged: Vec<bool> = PixelRect::full(width, height)
+            .pixels()
+            .zip(samples)
+            .map(|((x, y), &n)| {
+                included[(y * width + x) as usize]
+                    && (n < self.min_samples || accumulator.error(x, y) > self.max_error)
+            })
+            .collect();
+
+        PixelRect::full(width, height)
+            .pixels()
+            .zip(samples)
+            .map(|((x, y), &n)| {
+                included[(y * width + x) as usize]
+                    && n < self.max_samples
+                    && (y.saturating_sub(1)..(y + 2).min(height)).any(|ny| {
+                        (x.saturating_sub(1)..(x + 2).min(width))
+                            .any(|nx| unconverged[(ny * width + nx) as usize])
+                    })
+            })
+            .collect()
+    }
+}
+
+pub struct Renderer {
+    settings: RenderSettings,
+    integrator: Arc<dyn Integrator>,
+    progress: ProgressBar,
+}
+
+impl Renderer {
+    pub fn new(settings: RenderSettings) -> Self {
+        let progress = if settings.progress {
+            ProgressBar::new(0)
+        } else {
+            ProgressBar::hidden()
+        };
+
+        Self {
+            settings,
+            integrator: settings.integrator.build(),
+            progress,
+        }
+    }
+
+    /// Renders with `integrator` instead of the one named in the settings.
+    pub fn with_integrator(self, integrator: Arc<dyn Integrator>) -> Self {
+        Self { integrator, ..self }
+    }
+
+    /// Reports on `progress` instead: each pass adds its tiles to the length and advances it
+    /// as they finish.
+    pub fn with_progress(self, progress: ProgressBar) -> Self {
+        Self { progress, ..self }
+    }
+
+    pub fn settings(&self) -> &RenderSettings {
+        &self.settings
+    }
+
+    /// Renders `world` as seen from `camera`. The image must be at least 2x2 pixels.
+    pub fn render(&self, world: &World, camera: &Camera) -> Framebuffer {
+        let mut accumulator =
+            Accumulator::new(self.settings.image_width, self.settings.image_height);
+        self.render_pass(
+            world,
+            camera,
+            &mut accumulator,
+            self.settings.samples_per_pixel,
+        );
+
+        accumulator.resolve()
+    }
+
+    /// Adds `samples` samples to every pixel of `accumulator`, which must match the image size.
+    /// Each pixel continues from the samples it already has, so a render can be split into
+    /// passes, stopped and resumed with the same result as rendering it in one go.
+    pub fn render_pass(
+        &self,
+        world: &World,
+        camera: &Camera,
+        accumulator: &mut Accumulator,
+        samples: u32,
+    ) {
+        self.pass(world, camera, accumulator, |_| samples);
+    }
+
+    /// Adds up to `samples` samples to the pixels `adaptive` considers unfinished, and returns
+    /// how many pixels that was. Rendering is done once this returns 0.
+    pub fn render_adaptive_pass(
+        &self,
+        world: &World,
+        camera: &Camera,
+        accumulator: &mut Accumulator,
+        samples: u32,
+        adaptive: &AdaptiveSettings,
+    ) -> usize {
+        let active = adaptive.active(accumulator, &self.settings.tiles());
+        let budget: Vec<u32> = active
+            .iter()
+            .zip(accumulator.samples())
+            .map(|(&active, &n)| {
+                if !active {
+                    0
+                } else if n < adaptive.min_samples {
+                    // Reach the minimum first, so the error estimate is meaningful
+                    samples
+                        .max(adaptive.min_samples - n)
+                        .min(adaptive.max_samples - n)
+                } else {
+                    samples.min(adaptive.max_samples - n)
+                }
+            })
+            .collect();
+
+        self.pass(world, camera, accumulator, |index| budget[index]);
+        active.iter().filter(|&&active| active).count()
+    }
+
+    /// Adds `budget(index)` samples to each pixel of `RenderSettings::tiles`, indexed like
+    /// `Accumulator::samples`, in as many passes as `Integrator::samples_per_pass` asks for.
+    fn pass(
+        &self,
+        world: &World,
+        camera: &Camera,
+        accumulator: &mut Accumulator,
+        budget: impl Fn(usize) -> u32 + Sync,
+    ) {
+        let limit = match self.integrator.samples_per_pass() {
+            Some(limit) => limit.max(1),
+            None => return self.single_pass(world, camera, accumulator, budget),
+        };
+
+        let width = self.settings.image_width;
+        let most = self
+            .settings
+            .tiles()
+            .iter()
+            .flat_map(PixelRect::pixels)
+            .map(|(x, y)| budget((y * width + x) as usize))
+            .max()
+            .unwrap_or(0);
+        for done in (0..most).step_by(limit as usize) {
+            self.single_pass(world, camera, accumulator, |index| {
+                budget(index).saturating_sub(done).min(limit)
+            });
+        }
+    }
+
+    /// Adds each tile to the accumulator as soon as it is done, and its splats in tile order.
+    fn single_pass(
+        &self,
+        world: &World,
+        camera: &Camera,
+        accumulator: &mut Accumulator,
+        budget: impl Fn(usize) -> u32 + Sync,
+    ) {
+        let RenderSettings {
+            image_width,
+            image_height,
+            sample_offset,
+            ..
+        } = self.settings;
+        assert_eq!(
+            (accumulator.width(), accumulator.height()),
+            (image_width, image_height)
+        );
+
+        let tiles = self.settings.tiles();
+        self.progress.inc_length(tiles.len() as u64);
+        let pass = self.integrator.begin_pass(world, camera, &self.settings);
+
+        // Along with the accumulator, the index of the next tile whose splats are due and the
+        // splats of the tiles after it that are already done
+        let state = Mutex::new((accumulator, 0, BTreeMap::new()));
+        tiles
+            .iter()
+            .enumerate()
+            .par_bridge()
+            .for_each(|(index, tile)| {
+                // Tiles do not overlap, so these counts stay valid until the results are added
+                let first: Vec<u32> = {
+                    let (acc, _, _) = &*state.lock().unwrap();
+                    tile.pixels()
+                        .map(|(x, y)| sample_offset + acc.sample_count(x, y))
+                        .collect()
+                };
+
+                let mut splats = Vec::new();
+                let samples: Vec<PixelSamples> = tile
+                    .pixels()
+                    .zip(first)
+                    .map(|((x, y), first)| {
+                        let samples = budget((y * image_width + x) as usize);
+                        let j = image_height - 1 - y;
+                        self.sample_pixel(
+                            world,
+                            camera,
+                            pass.as_deref(),
+                            x,
+                            j,
+                            first,
+                            samples,
+                            &mut splats,
+                        )
+                    })
+                    .collect();
+
+                let (acc, next, pending) = &mut *state.lock().unwrap();
+                for ((x, y), samples) in tile.pixels().zip(samples) {
+                    acc.add(x, y, samples);
+                }
+                pending.insert(index, splats);
+                while let Some(splats) = pending.remove(next) {
+                    for splat in splats {
+                        acc.splat(splat);
+                    }
+                    *next += 1;
+                }
+                self.progress.inc(1);
+            });
+    }
+
+    /// Takes samples `first..first + samples` of pixel `(i, j)`, counting `j` from the bottom.
+    /// Light they find for other pixels is added to `splats`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn sample_pixel(
+        &self,
+        world: &World,
+        camera: &Camera,
+        pass: Option<&(dyn Any + Send + Sync)>,
+        i: u32,
+        j: u32,
+        first: u32,
+        samples: u32,
+        splats: &mut Vec<Splat>,
+    ) -> PixelSamples {
+        let RenderSettings {
+            image_width,
+            image_height,
+            seed,
+            ..
+        } = self.settings;
+
+        // Every sample has its own stream, so the result does not depend on how the samples
+        // are split into passes
+        let pixel_seed = mix_seed(seed, j as u64 * image_width as u64 + i as u64);
+
+        let mut ctx = SampleContext {
+            world,
+            camera,
+            settings: &self.settings,
+            sample: first,
+            splats: Vec::new(),
+            pass,
+        };
+        // Wavelengths are spread over the pixel's samples rather than drawn independently,
+        // which takes most of the color noise out of spectral renders. Sample indices never
+        // reach the stream of their start
+        let wavelength_start = SmallRng::seed_from_u64(mix_seed(pixel_seed, u64::MAX)).gen();
+
+        let mut sum = Color::black();
+        let mut sum_sq = 0.0;
+        for sample in first..first + samples {
+            let mut rng = SmallRng::seed_from_u64(mix_seed(pixel_seed, sample as u64));
+            let u = (i as f32 + rng.gen::<f32>()) / (image_width - 1) as f32;
+            let v = (j as f32 + rng.gen::<f32>()) / (image_height - 1) as f32;
+
+            let mut r = camera.get_ray(&mut rng, u, v);
+            ctx.sample = sample;
+            if self.settings.spectral {
+                let u = golden_sequence(wavelength_start, sample);
+                r = r.with_wavelength(self.integrator.wavelength(u, &ctx));
+            }
+            let radiance = self.integrator.radiance(&mut rng, &r, &mut ctx);
+            let color = sanitize(radiance * ray_weight(&r));
+            sum += color;
+            sum_sq += luminance(color).powi(2);
+        }
+        splats.extend(ctx.splats.into_iter().map(|splat| Splat {
+            color: sanitize(splat.color),
+            ..splat
+        }));
+
+        PixelSamples {
+            sum,
+            sum_sq,
+            count: samples,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The pixels of a render with `threads` threads, bit for bit.
+    fn render_bits(scene: &Scene, settings: RenderSettings, threads: usize) -> Vec<[u32; 3]> {
+        let camera = scene.camera.build(1.0);
+        let renderer = Renderer::new(settings);
+        let fb = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .unwrap()
+            .install(|| renderer.render(&scene.world, &camera));
+        fb.pixels()
+            .iter()
+            .map(|c| {
+                let [r, g, b] = c.to_array();
+                [r.to_bits(), g.to_bits(), b.to_bits()]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn same_seed_same_image() {
+        let scene = Scene::builtin("cornell_smoke", 1).unwrap();
+        let settings = RenderSettings {
+            image_width: 12,
+            image_height: 12,
+            samples_per_pixel: 4,
+            seed: 42,
+            ..RenderSettings::for_scene(&scene)
+        };
+        assert_eq!(
+            render_bits(&scene, settings, 1),
+            render_bits(&scene, settings, 3)
+        );
+    }
+
+    #[test]
+    fn same_splats_on_any_thread_count() {
+        let scene = Scene::builtin("cornell_box", 1).unwrap();
+        let settings = RenderSettings {
+            image_width: 24,
+            image_height: 24,
+            samples_per_pixel: 4,
+            seed: 7,
+            tile_size: 4,
+            integrator: IntegratorDesc::Bidirectional,
+            ..RenderSettings::for_scene(&scene)
+        };
+        assert_eq!(
+            render_bits(&scene, settings, 1),
+            render_bits(&scene, settings, 4)
+        );
+    }
+
+    #[test]
+    fn same_metropolis_image_on_any_thread_count() {
+        let scene = Scene::builtin("cornell_box", 1).unwrap();
+        let settings = RenderSettings {
+            image_width: 24,
+            image_height: 24,
+            samples_per_pixel: 4,
+            seed: 7,
+            tile_size: 4,
+            integrator: IntegratorDesc::Metropolis { mutations: 32 },
+            ..RenderSettings::for_scene(&scene)
+        };
+        assert_eq!(
+            render_bits(&scene, settings, 1),
+            render_bits(&scene, settings, 4)
+        );
+    }
+
+    #[test]
+    fn passes_accumulate() {
+        let scene = Scene::builtin("cornell_box", 1).unwrap();
+        let camera = scene.camera.build(1.0);
+        let renderer = Renderer::new(RenderSettings {
+            image_width: 6,
+            image_height: 6,
+            ..RenderSettings::for_scene(&scene)
+        });
+
+        let mut passes = Accumulator::new(6, 6);
+        renderer.render_pass(&scene.world, &camera, &mut passes, 2);
+        renderer.render_pass(&scene.world, &camera, &mut passes, 3);
+        assert_eq!(passes.min_samples(), 5);
+
+        // Same samples as a single pass, up to the order they are summed in
+        let mut single = Accumulator::new(6, 6);
+        renderer.render_pass(&scene.world, &camera, &mut single, 5);
+        for (&a, &b) in passes.sums().iter().zip(single.sums()) {
+            assert!((a - b).length() <= 1e-5 * a.length().max(1.0));
+        }
+    }
+
+    #[test]
+    fn crop_matches_full_render() {
+        let scene = Scene::builtin("cornell_box", 1).unwrap();
+        let camera = scene.camera.build(1.0);
+        let settings = RenderSettings {
+            image_width: 10,
+            image_height: 10,
+            samples_per_pixel: 2,
+            tile_size: 3,
+            ..RenderSettings::for_scene(&scene)
+        };
+        let crop = PixelRect::new(2, 5, 4, 3);
+
+        let full = Renderer::new(settings).render(&scene.world, &camera);
+        let cropped = Renderer::new(RenderSettings {
+            crop: Some(crop),
+            ..settings
+        })
+        .render(&scene.world, &camera);
+
+        for (x, y) in PixelRect::full(10, 10).pixels() {
+            if crop.contains(x, y) {
+                assert_eq!(cropped.get(x, y), full.get(x, y));
+            } else {
+                assert_eq!(cropped.get(x, y), Color::black());
+            }
+        }
+    }
+
+    #[test]
+    fn roulette_unbiased() {
+        let scene = Scene::builtin("two_spheres", 1).unwrap();
+        let camera = scene.camera.build(1.0);
+        // Lit by the sky alone, so a few samples already give a stable mean
+        let settings = RenderSettings {
+            image_width: 8,
+            image_height: 8,
+            samples_per_pixel: 64,
+            ..RenderSettings::for_scene(&scene)
+        };
+
+        let mean = |roulette_depth| {
+            let image = Renderer::new(RenderSettings {
+                roulette_depth,
+                ..settings
+            })
+            .render(&scene.world, &camera);
+            image.pixels().iter().map(|&c| luminance(c)).sum::<f32>() / 64.0
+        };
+
+        let full = mean(settings.max_depth);
+        let roulette = mean(1);
+        assert!(
+            (roulette - full).abs() < 0.05 * full,
+            "{} != {}",
+            roulette,
+            full
+        );
+    }
+
+    #[test]
+    fn spectral_matches_rgb() {
+        let scene = Scene::builtin("two_spheres", 1).unwrap();
+        let camera = scene.camera.build(1.0);
+        let settings = RenderSettings {
+            image_width: 8,
+            image_height: 8,
+            samples_per_pixel: 512,
+            ..RenderSettings::for_scene(&scene)
+        };
+
+        // Nothing disperses light, so only the noise differs, though single wavelengths make
+        // for much more of it
+        let mean = |spectral| {
+            let image = Renderer::new(RenderSettings {
+                spectral,
+                ..settings
+            })
+            .render(&scene.world, &camera);
+            image
+                .pixels()
+                .iter()
+                .fold(Color::black(), |sum, &c| sum + c)
+                / 64.0
+        };
+        let (rgb, spectral) = (mean(false), mean(true));
+        assert!(
+            (spectral - rgb).length() < 0.02 * rgb.length(),
+            "{} != {}",
+            spectral,
+            rgb
+        );
+    }
+
+    #[test]
+    fn adaptive_active_pixels() {
+        let adaptive = AdaptiveSettings {
+            min_samples: 4,
+            max_samples: 64,
+            max_error: 0.01,
+        };
+        let samples = |sum: f32, sum_sq: f32, count| PixelSamples {
+            sum: Color::new(sum, sum, sum),
+            sum_sq,
+            count,
+        };
+
+        // Every pixel saw constant radiance 0.5, except one noisy pixel in the corner
+        let mut accumulator = Accumulator::new(4, 3);
+        for y in 0..3 {
+            for x in 0..4 {
+                accumulator.add(x, y, samples(4.0, 2.0, 8));
+            }
+        }
+        accumulator.add(0, 0, samples(4.0, 16.0, 8));
+
+        let active = adaptive.active(&accumulator, &[PixelRect::full(4, 3)]);
+        let expected = [
+            true, true, false, false, //
+            true, true, false, false, //
+            false, false, false, false,
+        ];
+        assert_eq!(active, expected);
+
+        // Pixels below the minimum always need more samples, those at the maximum never do
+        let mut accumulator = Accumulator::new(2, 2);
+        accumulator.add(0, 0, samples(3.0, 4.5, 2));
+        accumulator.add(1, 1, samples(64.0, 256.0, 64));
+        assert_eq!(
+            adaptive.active(&accumulator, &[PixelRect::full(2, 2)]),
+            [true, true, true, false]
+        );
+
+        // Pixels outside the tiles are never active
+        assert_eq!(
+            adaptive.active(&accumulator, &[PixelRect::new(1, 0, 1, 2)]),
+            [false, true, false, false]
+        );
+    }
+}
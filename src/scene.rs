@@ -0,0 +1,168 @@
+use crate::prelude::*;
+
+use crate::{camera::*, scene_file::*, tile::*, worlds::*};
+
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ImageSettings {
+    pub aspect_ratio: f32,
+    pub image_height: u32,
+    pub samples_per_pixel: u32,
+    /// Only render this part of the image
+    pub crop: Option<CropWindow>,
+}
+
+impl Default for ImageSettings {
+    fn default() -> Self {
+        Self {
+            aspect_ratio: 16.0 / 9.0,
+            image_height: 300,
+            samples_per_pixel: 100,
+            crop: None,
+        }
+    }
+}
+
+impl ImageSettings {
+    pub fn image_width(&self) -> u32 {
+        (self.image_height as f32 * self.aspect_ratio).round() as u32
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CameraSettings {
+    pub look_from: Point3,
+    pub look_at: Point3,
+    pub vup: Vec3,
+    pub vfov: f32,
+    pub aperture: f32,
+    pub focus_dist: f32,
+    pub time0: f32,
+    pub time1: f32,
+}
+
+impl Default for CameraSettings {
+    fn default() -> Self {
+        Self {
+            look_from: point!(13.0, 2.0, 3.0),
+            look_at: Point3::origin(),
+            vup: vec3!(0.0, 1.0, 0.0),
+            vfov: 20.0,
+            aperture: 0.0,
+            focus_dist: 10.0,
+            time0: 0.0,
+            time1: 1.0,
+        }
+    }
+}
+
+impl CameraSettings {
+    pub fn build(&self, aspect_ratio: f32) -> Camera {
+        Camera::new(
+            self.look_from,
+            self.look_at,
+            self.vup,
+            self.vfov,
+            aspect_ratio,
+            self.aperture,
+            self.focus_dist,
+            self.time0,
+            self.time1,
+        )
+    }
+}
+
+pub const BUILTIN_SCENES: [&str; 8] = [
+    "random_scene",
+    "two_spheres",
+    "two_perlin_spheres",
+    "earth",
+    "simple_light",
+    "cornell_box",
+    "cornell_smoke",
+    "final_scene",
+];
+
+pub struct Scene {
+    pub world: World,
+    pub image: ImageSettings,
+    pub camera: CameraSettings,
+    pub background: Color,
+}
+
+impl Scene {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SceneError> {
+        SceneFile::load(path)
+    }
+
+    /// Builds one of the `BUILTIN_SCENES` by name, using `seed` for procedurally placed objects.
+    pub fn builtin(name: &str, seed: u64) -> Option<Self> {
+        let mut image = ImageSettings::default();
+        let mut camera = CameraSettings::default();
+        let mut background = rgb!(0.70, 0.80, 1.00);
+
+        let world = match name {
+            "random_scene" => {
+                camera.aperture = 0.1;
+
+                World::random_scene(seed)
+            }
+            "two_spheres" => World::two_spheres(),
+            "two_perlin_spheres" => World::two_perlin_spheres(seed),
+            "earth" => World::earth(),
+            "simple_light" => {
+                background = Color::black();
+                camera.look_from = point!(26.0, 3.0, 6.0);
+                camera.look_at = point!(0.0, 2.0, 0.0);
+                // image.samples_per_pixel = 400;
+
+                World::simple_light(seed)
+            }
+            "cornell_box" => {
+                image.aspect_ratio = 1.0;
+                image.image_height = 600;
+                background = Color::black();
+                camera.look_from = point!(278.0, 278.0, -800.0);
+                camera.look_at = point!(278.0, 278.0, 0.0);
+                camera.vfov = 40.0;
+
+                World::cornell_box()
+            }
+            "cornell_smoke" => {
+                image.aspect_ratio = 1.0;
+                image.image_height = 600;
+                // image.samples_per_pixel = 1000;
+                background = Color::black();
+                camera.look_from = point!(278.0, 278.0, -800.0);
+                camera.look_at = point!(278.0, 278.0, 0.0);
+                camera.vfov = 40.0;
+
+                World::cornell_smoke()
+            }
+            "final_scene" => {
+                image.aspect_ratio = 1.0;
+                image.image_height = 800;
+                // image.samples_per_pixel = 10_000;
+                image.samples_per_pixel = 10;
+                background = Color::black();
+                camera.look_from = point!(478.0, 278.0, -600.0);
+                camera.look_at = point!(278.0, 278.0, 0.0);
+                camera.vfov = 40.0;
+
+                World::final_scene(seed)
+            }
+            _ => return None,
+        };
+
+        Some(Self {
+            world,
+            image,
+            camera,
+            background,
+        })
+    }
+}
@@ -0,0 +1,575 @@
+use crate::prelude::*;
+
+use crate::{
+    aarect::*, bvh::*, constant_medium::*, cuboid::*, hittable_list::*, moving_sphere::*, obj::*,
+    scene::*, sphere::*, triangle::*, worlds::*,
+};
+
+use rand::{rngs::SmallRng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io(PathBuf, std::io::Error),
+    Parse(ron::Error),
+    Image(PathBuf, image::ImageError),
+    Obj(ObjError),
+    UnknownTexture(String),
+    UnknownMaterial(String),
+    /// A material with a parameter out of range, and what is wrong with it
+    InvalidMaterial(String, &'static str),
+    NestedLight,
+    EmptyBvh,
+}
+
+impl Display for SceneError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SceneError::Io(path, e) => write!(f, "could not read {}: {}", path.display(), e),
+            SceneError::Parse(e) => write!(f, "invalid scene file: {}", e),
+            SceneError::Image(path, e) => {
+                write!(f, "could not load image {}: {}", path.display(), e)
+            }
+            SceneError::Obj(e) => e.fmt(f),
+            SceneError::UnknownTexture(name) => write!(f, "unknown texture `{}`", name),
+            SceneError::UnknownMaterial(name) => write!(f, "unknown material `{}`", name),
+            SceneError::InvalidMaterial(name, problem) => {
+                write!(f, "invalid material `{}`: {}", name, problem)
+            }
+            SceneError::NestedLight => write!(f, "`Light` is only allowed at the top level"),
+            SceneError::EmptyBvh => write!(f, "`Bvh` needs at least one object"),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+/// A texture definition, shared by name between materials.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum TextureDesc {
+    Solid(Color),
+    Checker {
+        even: Color,
+        odd: Color,
+    },
+    /// Perlin turbulence; `seed` picks the noise pattern.
+    Noise {
+        scale: f32,
+        #[serde(default)]
+        seed: u64,
+    },
+    Image {
+        path: PathBuf,
+    },
+}
+
+/// Either an inline color or gray level, or the name of an entry in `textures`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum TextureRef {
+    Color(Color),
+    Value(f32),
+    Texture(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum MaterialDesc {
+    Lambertian {
+        albedo: TextureRef,
+    },
+    /// A rough metal reflecting `albedo` head-on, optionally brushed with `roughness_v`.
+    Metal {
+        albedo: Color,
+        roughness: f32,
+        #[serde(default)]
+        roughness_v: Option<f32>,
+    },
+    /// A rough metal with measured optical constants.
+    Conductor {
+        metal: Conductor,
+        roughness: f32,
+        #[serde(default)]
+        roughness_v: Option<f32>,
+    },
+    /// Glass, letting through `transmittance` of the light after `distance` inside it.
+    /// `ref_idx` is a number, Cauchy coefficients `(a, b)` or Sellmeier coefficients `(b, c)`.
+    Dielectric {
+        ref_idx: Ior,
+        #[serde(default)]
+        roughness: f32,
+        #[serde(default = "clear")]
+        transmittance: Color,
+        #[serde(default = "unit_distance")]
+        distance: f32,
+    },
+    /// Burley's principled BSDF, by default a plastic. Parameters besides `base_color` are in
+    /// `0..=1`.
+    Principled {
+        base_color: TextureRef,
+        #[serde(default = "zero")]
+        metallic: TextureRef,
+        #[serde(default = "half")]
+        roughness: TextureRef,
+        #[serde(default = "half")]
+        specular: TextureRef,
+        #[serde(default = "zero")]
+        clearcoat: TextureRef,
+        #[serde(default = "zero")]
+        sheen: TextureRef,
+        #[serde(default = "zero")]
+        transmission: TextureRef,
+    },
+    DiffuseLight {
+        emit: TextureRef,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ObjectDesc {
+    Sphere {
+        center: Point3,
+        radius: f32,
+        material: String,
+    },
+    MovingSphere {
+        center0: Point3,
+        center1: Point3,
+        time0: f32,
+        time1: f32,
+        radius: f32,
+        material: String,
+    },
+    /// An axis-aligned rectangle spanning `min..max` in the two axes of `plane`, at `k` on the
+    /// remaining one.
+    Rect {
+        plane: Plane,
+        min: (f32, f32),
+        max: (f32, f32),
+        k: f32,
+        material: String,
+    },
+    Cuboid {
+        min: Point3,
+        max: Point3,
+        material: String,
+    },
+    Triangle {
+        vertices: (Point3, Point3, Point3),
+        material: String,
+    },
+    /// A Wavefront OBJ file. Without `material`, its MTL materials are used.
+    Mesh {
+        path: PathBuf,
+        #[serde(default)]
+        material: Option<String>,
+    },
+    ConstantMedium {
+        boundary: Box<ObjectDesc>,
+        density: f32,
+        color: Color,
+    },
+    Translate {
+        offset: Vec3,
+        object: Box<ObjectDesc>,
+    },
+    RotateY {
+        angle: f32,
+        object: Box<ObjectDesc>,
+    },
+    FlipFace(Box<ObjectDesc>),
+    Bvh(Vec<ObjectDesc>),
+    /// Adds the object to both the world and the `lights` list. Top level only.
+    Light(Box<ObjectDesc>),
+}
+
+fn default_background() -> Color {
+    rgb!(0.70, 0.80, 1.00)
+}
+
+fn clear() -> Color {
+    rgb!(1.0, 1.0, 1.0)
+}
+
+fn unit_distance() -> f32 {
+    1.0
+}
+
+fn zero() -> TextureRef {
+    TextureRef::Value(0.0)
+}
+
+fn half() -> TextureRef {
+    TextureRef::Value(0.5)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename = "Scene")]
+pub struct SceneFile {
+    #[serde(default)]
+    pub image: ImageSettings,
+    #[serde(default)]
+    pub camera: CameraSettings,
+    #[serde(default = "default_background")]
+    pub background: Color,
+    #[serde(default)]
+    pub textures: BTreeMap<String, TextureDesc>,
+    #[serde(default)]
+    pub materials: BTreeMap<String, MaterialDesc>,
+    #[serde(default)]
+    pub objects: Vec<ObjectDesc>,
+    /// Objects sampled as lights without being part of the world, e.g. the boundary of a medium.
+    #[serde(default)]
+    pub lights: Vec<ObjectDesc>,
+}
+
+impl SceneFile {
+    pub fn load(path: impl AsRef<Path>) -> Result<Scene, SceneError> {
+        let path = path.as_ref();
+        let source =
+            std::fs::read_to_string(path).map_err(|e| SceneError::Io(path.to_path_buf(), e))?;
+        let file = Self::parse(&source)?;
+
+        file.build(path.parent().unwrap_or_else(|| Path::new("")))
+    }
+
+    pub fn parse(source: &str) -> Result<Self, SceneError> {
+        ron::from_str(source).map_err(SceneError::Parse)
+    }
+
+    /// Builds the scene, resolving relative image paths against `base_dir`.
+    pub fn build(&self, base_dir: &Path) -> Result<Scene, SceneError> {
+        let mut textures = BTreeMap::new();
+        for (name, desc) in &self.textures {
+            let texture: Arc<dyn Texture> = match desc {
+                TextureDesc::Solid(color) => Arc::new(SolidColor {
+                    color_value: *color,
+                }),
+                TextureDesc::Checker { even, odd } => CheckerTexture::new(*even, *odd),
+                TextureDesc::Noise { scale, seed } => {
+                    NoiseTexture::new(&mut SmallRng::seed_from_u64(*seed), *scale)
+                }
+                TextureDesc::Image { path } => {
+                    let path = base_dir.join(path);
+                    ImageTexture::open(&path).map_err(|e| SceneError::Image(path, e))?
+                }
+            };
+            textures.insert(name.as_str(), texture);
+        }
+
+        let texture = |r: &TextureRef| -> Result<Arc<dyn Texture>, SceneError> {
+            match r {
+                TextureRef::Color(color) => Ok(Arc::new(SolidColor {
+                    color_value: *color,
+                })),
+                TextureRef::Value(value) => Ok(Arc::new(SolidColor {
+                    color_value: Color::from_scalar(*value),
+                })),
+                TextureRef::Texture(name) => textures
+                    .get(name.as_str())
+                    .cloned()
+                    .ok_or_else(|| SceneError::UnknownTexture(name.clone())),
+            }
+        };
+
+        let mut materials = BTreeMap::new();
+        for (name, desc) in &self.materials {
+            let material: Arc<dyn Material> = match desc {
+                MaterialDesc::Lambertian { albedo } => Lambertian::new(texture(albedo)?),
+                MaterialDesc::Metal {
+                    albedo,
+                    roughness,
+                    roughness_v,
+                } => {
+                    let (eta, k) = Metal::ior_for(*albedo);
+                    Metal::from_ior(eta, k, *roughness, roughness_v.unwrap_or(*roughness))
+                }
+                MaterialDesc::Conductor {
+                    metal,
+                    roughness,
+                    roughness_v,
+                } => {
+                    let (eta, k) = metal.ior();
+                    Metal::from_ior(eta, k, *roughness, roughness_v.unwrap_or(*roughness))
+                }
+                MaterialDesc::Dielectric {
+                    ref_idx,
+                    roughness,
+                    transmittance,
+                    distance,
+                } => {
+                    let invalid = |problem| SceneError::InvalidMaterial(name.clone(), problem);
+                    if !transmittance
+                        .to_array()
+                        .iter()
+                        .all(|&t| t > 0.0 && t <= 1.0)
+                    {
+                        return Err(invalid("`transmittance` must be above 0 and at most 1"));
+                    }
+                    if *distance <= 0.0 {
+                        return Err(invalid("`distance` must be positive"));
+                    }
+                    Dielectric::tinted(*ref_idx, *roughness, *transmittance, *distance)
+                }
+                MaterialDesc::Principled {
+                    base_color,
+                    metallic,
+                    roughness,
+                    specular,
+                    clearcoat,
+                    sheen,
+                    transmission,
+                } => Arc::new(Principled {
+                    base_color: texture(base_color)?,
+                    metallic: texture(metallic)?,
+                    roughness: texture(roughness)?,
+                    specular: texture(specular)?,
+                    clearcoat: texture(clearcoat)?,
+                    sheen: texture(sheen)?,
+                    transmission: texture(transmission)?,
+                }),
+                MaterialDesc::DiffuseLight { emit } => Arc::new(DiffuseLight {
+                    emit: texture(emit)?,
+                }),
+            };
+            materials.insert(name.as_str(), material);
+        }
+
+        let builder = Builder {
+            base_dir,
+            materials,
+            time0: self.camera.time0,
+            time1: self.camera.time1,
+        };
+
+        let mut world = HittableList::new();
+        let mut lights = HittableList::new();
+        for desc in &self.objects {
+            if let ObjectDesc::Light(inner) = desc {
+                let object = builder.object(inner)?;
+                lights.add(object.clone());
+                world.add(object);
+            } else {
+                world.add(builder.object(desc)?);
+            }
+        }
+        for desc in &self.lights {
+            lights.add(builder.object(desc)?);
+        }
+
+        Ok(Scene {
+            world: World::new(world, lights),
+            image: self.image,
+            camera: self.camera,
+            background: self.background,
+        })
+    }
+}
+
+struct Builder<'a> {
+    base_dir: &'a Path,
+    materials: BTreeMap<&'a str, Arc<dyn Material>>,
+    time0: f32,
+    time1: f32,
+}
+
+impl Builder<'_> {
+    fn material(&self, name: &str) -> Result<Arc<dyn Material>, SceneError> {
+        self.materials
+            .get(name)
+            .cloned()
+            .ok_or_else(|| SceneError::UnknownMaterial(name.to_owned()))
+    }
+
+    fn object(&self, desc: &ObjectDesc) -> Result<Arc<dyn Hittable>, SceneError> {
+        Ok(match desc {
+            ObjectDesc::Sphere {
+                center,
+                radius,
+                material,
+            } => Sphere::new(*center, *radius, self.material(material)?),
+            ObjectDesc::MovingSphere {
+                center0,
+                center1,
+                time0,
+                time1,
+                radius,
+                material,
+            } => MovingSphere::new(
+                *center0,
+                *center1,
+                *time0,
+                *time1,
+                *radius,
+                self.material(material)?,
+            ),
+            ObjectDesc::Rect {
+                plane,
+                min,
+                max,
+                k,
+                material,
+            } => AARect::new(
+                point!(min.0, min.1, 0.0),
+                point!(max.0, max.1, 0.0),
+                *plane,
+                *k,
+                self.material(material)?,
+            ),
+            ObjectDesc::Cuboid { min, max, material } => {
+                Cuboid::new(*min, *max, self.material(material)?)
+            }
+            ObjectDesc::Triangle {
+                vertices: (a, b, c),
+                material,
+            } => Triangle::new(*a, *b, *c, self.material(material)?),
+            ObjectDesc::Mesh { path, material } => {
+                let material = material
+                    .as_deref()
+                    .map(|name| self.material(name))
+                    .transpose()?;
+                Arc::new(load_obj(self.base_dir.join(path), material).map_err(SceneError::Obj)?)
+            }
+            ObjectDesc::ConstantMedium {
+                boundary,
+                density,
+                color,
+            } => ConstantMedium::new(self.object(boundary)?, *density, *color),
+            ObjectDesc::Translate { offset, object } => {
+                Translate::new(self.object(object)?, *offset)
+            }
+            ObjectDesc::RotateY { angle, object } => RotateY::new(self.object(object)?, *angle),
+            ObjectDesc::FlipFace(object) => FlipFace::new(self.object(object)?),
+            ObjectDesc::Bvh(objects) => {
+                if objects.is_empty() {
+                    return Err(SceneError::EmptyBvh);
+                }
+
+                let mut list = HittableList::new();
+                for object in objects {
+                    list.add(self.object(object)?);
+                }
+                FlatBVH::new_with_list(list, self.time0, self.time1)
+            }
+            ObjectDesc::Light(_) => return Err(SceneError::NestedLight),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn example_scenes() {
+        for entry in std::fs::read_dir("scenes").unwrap() {
+            let path = entry.unwrap().path();
+            if let Err(e) = SceneFile::load(&path) {
+                panic!("{}: {}", path.display(), e);
+            }
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let unknown = SceneFile::parse(
+            r#"Scene(objects: [Sphere(center: (0.0, 0.0, 0.0), radius: 1.0, material: "x")])"#,
+        )
+        .unwrap()
+        .build(Path::new(""));
+        assert!(matches!(unknown, Err(SceneError::UnknownMaterial(name)) if name == "x"));
+
+        let nested = SceneFile::parse(
+            r#"Scene(
+                materials: { "x": Dielectric(ref_idx: 1.5) },
+                objects: [FlipFace(Light(Sphere(center: (0.0, 0.0, 0.0), radius: 1.0, material: "x")))],
+            )"#,
+        )
+        .unwrap()
+        .build(Path::new(""));
+        assert!(matches!(nested, Err(SceneError::NestedLight)));
+
+        // Glass that would amplify light, or absorb all of it however thin
+        for glass in &[
+            "transmittance: (0.5, 0.9, 0.9), distance: -1.0",
+            "transmittance: (1.0, 1.0, 1.0), distance: 0.0",
+            "transmittance: (0.0, 0.5, 0.5)",
+            "transmittance: (1.5, 1.0, 1.0)",
+        ] {
+            let invalid = SceneFile::parse(&format!(
+                r#"Scene(materials: {{ "glass": Dielectric(ref_idx: 1.5, {}) }})"#,
+                glass
+            ))
+            .unwrap()
+            .build(Path::new(""));
+            assert!(
+                matches!(invalid, Err(SceneError::InvalidMaterial(ref name, _)) if name == "glass"),
+                "{}",
+                glass
+            );
+        }
+    }
+
+    #[test]
+    fn indices_of_refraction() {
+        let scene = SceneFile::parse(
+            r#"Scene(materials: {
+                "constant": Dielectric(ref_idx: 1.5),
+                "cauchy": Dielectric(ref_idx: (a: 1.5, b: 0.004)),
+                "sellmeier": Dielectric(ref_idx: (b: (1.0, 0.2, 1.0), c: (0.006, 0.02, 100.0))),
+            })"#,
+        )
+        .unwrap();
+        let ior = |name: &str| match scene.materials[name] {
+            MaterialDesc::Dielectric { ref_idx, .. } => ref_idx,
+            _ => unreachable!(),
+        };
+        assert_eq!(ior("constant"), Ior::Constant(1.5));
+        assert_eq!(ior("cauchy"), Ior::Cauchy { a: 1.5, b: 0.004 });
+        assert_eq!(
+            ior("sellmeier"),
+            Ior::Sellmeier {
+                b: [1.0, 0.2, 1.0],
+                c: [0.006, 0.02, 100.0]
+            }
+        );
+    }
+
+    #[test]
+    fn principled_parameters() {
+        let scene = SceneFile::parse(
+            r#"Scene(
+                textures: { "scratches": Noise(scale: 4.0) },
+                materials: {
+                    "paint": Principled(
+                        base_color: Color((0.6, 0.1, 0.1)),
+                        metallic: Value(1.0),
+                        roughness: Texture("scratches"),
+                        clearcoat: Value(1.0),
+                    ),
+                },
+            )"#,
+        )
+        .unwrap();
+        match &scene.materials["paint"] {
+            MaterialDesc::Principled {
+                metallic,
+                roughness,
+                specular,
+                transmission,
+                ..
+            } => {
+                assert!(matches!(metallic, TextureRef::Value(v) if *v == 1.0));
+                assert!(matches!(roughness, TextureRef::Texture(name) if name == "scratches"));
+                assert!(matches!(specular, TextureRef::Value(v) if *v == 0.5));
+                assert!(matches!(transmission, TextureRef::Value(v) if *v == 0.0));
+            }
+            _ => unreachable!(),
+        }
+        assert!(scene.build(Path::new("")).is_ok());
+    }
+}
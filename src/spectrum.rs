@@ -0,0 +1,105 @@
+use crate::prelude::*;
+
+/// Wavelengths spectral renders sample, in nanometers.
+pub const VISIBLE: std::ops::Range<f32> = 360.0..830.0;
+
+/// Area under each channel of `xyz_to_rgb(cie_xyz(λ))` over `VISIBLE`.
+const CHANNEL_AREA: [f32; 3] = [128.335_16, 101.543_79, 97.116_89];
+
+/// The CIE 1931 color matching functions, from the multi-lobe fit of Wyman et al. (2013).
+pub fn cie_xyz(wavelength: f32) -> Vec3 {
+    let lobe = |mean: f32, sigma_below: f32, sigma_above: f32| {
+        let sigma = if wavelength < mean {
+            sigma_below
+        } else {
+            sigma_above
+        };
+        (-0.5 * ((wavelength - mean) / sigma).powi(2)).exp()
+    };
+
+    Vec3::new(
+        1.056 * lobe(599.8, 37.9, 31.0) + 0.362 * lobe(442.0, 16.0, 26.7)
+            - 0.065 * lobe(501.1, 20.4, 26.2),
+        0.821 * lobe(568.8, 46.9, 40.5) + 0.286 * lobe(530.9, 16.3, 31.1),
+        1.217 * lobe(437.0, 11.8, 36.0) + 0.681 * lobe(459.0, 26.0, 13.8),
+    )
+}
+
+/// Linear Rec. 709 color of CIE XYZ tristimulus values, for a D65 white point.
+pub fn xyz_to_rgb(xyz: Vec3) -> Color {
+    let [x, y, z] = xyz.to_array();
+    Color::new(
+        3.240_454 * x - 1.537_139 * y - 0.498_531 * z,
+        -0.969_266 * x + 1.876_011 * y + 0.041_556 * z,
+        0.055_643 * x - 0.204_026 * y + 1.057_225 * z,
+    )
+}
+
+/// A wavelength from `u` in `[0, 1)`, following the eye's sensitivity (Radziszewski et al.).
+pub fn sample_wavelength(u: f32) -> f32 {
+    538.0 - 138.888_89 * (0.856_910_6 - 1.827_502 * u).atanh()
+}
+
+/// Density of `sample_wavelength` per nanometer.
+pub fn wavelength_pdf(wavelength: f32) -> f32 {
+    if !VISIBLE.contains(&wavelength) {
+        return 0.0;
+    }
+    0.003_939_804 / (0.0072 * (wavelength - 538.0)).cosh().powi(2)
+}
+
+/// Color of radiance at a `wavelength` from `sample_wavelength`, white on average.
+pub fn wavelength_weight(wavelength: f32) -> Color {
+    let pdf = wavelength_pdf(wavelength);
+    if pdf == 0.0 {
+        return Color::black();
+    }
+    let [r, g, b] = xyz_to_rgb(cie_xyz(wavelength)).to_array();
+    let [area_r, area_g, area_b] = CHANNEL_AREA;
+    Color::new(r / area_r, g / area_g, b / area_b) / pdf
+}
+
+/// `wavelength_weight` of the wavelength of `r`, or white for RGB rays.
+pub fn ray_weight(r: &Ray) -> Color {
+    r.wavelength()
+        .map_or(rgb!(1.0, 1.0, 1.0), wavelength_weight)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sampled_wavelengths_average_to_white() {
+        // Stratified, so the mean converges fast
+        const N: usize = 100_000;
+        let mut sum = Color::black();
+        let mut pdf_integral = 0.0;
+        for k in 0..N {
+            let u = (k as f32 + 0.5) / N as f32;
+            let wavelength = sample_wavelength(u);
+            assert!(VISIBLE.contains(&wavelength), "{}", wavelength);
+            sum += wavelength_weight(wavelength);
+
+            let step = (VISIBLE.end - VISIBLE.start) / N as f32;
+            pdf_integral += wavelength_pdf(VISIBLE.start + (k as f32 + 0.5) * step) * step;
+        }
+        assert!((sum / N as f32 - rgb!(1.0, 1.0, 1.0)).length() < 1e-3);
+        assert!((pdf_integral - 1.0).abs() < 1e-4);
+
+        // The sampled density is the one the weights divide by
+        let (a, b) = (sample_wavelength(0.4), sample_wavelength(0.401));
+        assert!(((b - a) * wavelength_pdf(a) - 0.001).abs() < 2e-5);
+    }
+
+    #[test]
+    fn spectral_colors() {
+        let red = wavelength_weight(640.0).to_array();
+        assert!(red[0] > red[1] && red[0] > red[2]);
+        let green = wavelength_weight(530.0).to_array();
+        assert!(green[1] > green[0] && green[1] > green[2]);
+        let blue = wavelength_weight(450.0).to_array();
+        assert!(blue[2] > blue[0] && blue[2] > blue[1]);
+        assert_eq!(wavelength_weight(900.0), Color::black());
+    }
+}
@@ -19,6 +19,16 @@ impl Sphere {
         })
     }
 
+    /// Cosine of the half-angle of the cone from `o` towards the sphere; `None` from inside.
+    fn cone(&self, o: Point3) -> Option<f32> {
+        let ratio = self.radius * self.radius / (self.center - o).length_squared();
+        if ratio > 1.0 + 1e-4 {
+            None
+        } else {
+            Some((1.0 - ratio).max(0.0).sqrt())
+        }
+    }
+
     pub fn get_uv(p: Vec3) -> (f32, f32) {
         let phi = p.z().atan2(p.x());
         let theta = p.y().asin();
@@ -27,7 +37,13 @@ impl Sphere {
 }
 
 impl Hittable for Sphere {
-    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+    fn hit(
+        &self,
+        _rng: &mut dyn rand::RngCore,
+        r: &Ray,
+        t_min: f32,
+        t_max: f32,
+    ) -> Option<HitRecord> {
         let oc = r.origin() - self.center;
         let a = r.direction().length_squared();
         let half_b = oc.dot(r.direction());
@@ -44,7 +60,7 @@ impl Hittable for Sphere {
                 let outward_normal = (p - self.center) / self.radius;
                 let (u, v) = Self::get_uv((p - self.center) / self.radius);
                 return Some(HitRecord::new(
-                    &r,
+                    r,
                     outward_normal,
                     p,
                     t,
@@ -61,7 +77,7 @@ impl Hittable for Sphere {
                 let outward_normal = (p - self.center) / self.radius;
                 let (u, v) = Self::get_uv((p - self.center) / self.radius);
                 return Some(HitRecord::new(
-                    &r,
+                    r,
                     outward_normal,
                     p,
                     t,
@@ -84,14 +100,15 @@ impl Hittable for Sphere {
         ))
     }
 
-    fn pdf_value(&self, o: Point3, v: Vec3) -> f32 {
+    fn pdf_value(&self, rng: &mut dyn rand::RngCore, o: Point3, v: Vec3) -> f32 {
         if self
-            .hit(&Ray::new(o, v, 0.0), 0.001, f32::INFINITY)
+            .hit(rng, &Ray::new(o, v, 0.0), 0.001, f32::INFINITY)
             .is_some()
         {
-            let cos_theta_max =
-                (1.0 - self.radius * self.radius / (self.center - o).length_squared()).sqrt();
-            let solid_angle = 2.0 * PI * (1.0 - cos_theta_max);
+            let solid_angle = match self.cone(o) {
+                Some(cos_theta_max) => 2.0 * PI * (1.0 - cos_theta_max),
+                None => 4.0 * PI,
+            };
 
             1.0 / solid_angle
         } else {
@@ -100,10 +117,98 @@ impl Hittable for Sphere {
     }
 
     fn random(&self, rng: &mut dyn rand::RngCore, o: Vec3) -> Vec3 {
+        if self.cone(o).is_none() {
+            return random_unit_vector(rng);
+        }
         let direction = self.center - o;
         let distance_squared = direction.length_squared();
         let uvw = OrthonormalBasis::from_w(direction);
 
         uvw.local(random_to_sphere(rng, self.radius, distance_squared))
     }
+
+    fn area(&self) -> f32 {
+        4.0 * PI * self.radius * self.radius
+    }
+
+    fn sample_surface(&self, rng: &mut dyn rand::RngCore) -> Option<HitRecord> {
+        let normal = random_unit_vector(rng);
+        let (u, v) = Self::get_uv(normal);
+        Some(HitRecord {
+            p: self.center + self.radius * normal,
+            normal,
+            geometric_normal: normal,
+            t: 0.0,
+            u,
+            v,
+            front_face: true,
+            mat_ptr: self.mat_ptr.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    fn unit_sphere() -> Arc<Sphere> {
+        Sphere::new(Point3::origin(), 1.0, Lambertian::new_rgb(0.5, 0.5, 0.5))
+    }
+
+    #[test]
+    fn hit_from_outside() {
+        let rng = &mut SmallRng::seed_from_u64(0);
+        let r = Ray::new(point!(0.0, 0.0, -5.0), vec3!(0.0, 0.0, 2.0), 0.0);
+        let rec = unit_sphere().hit(rng, &r, 0.001, f32::INFINITY).unwrap();
+
+        assert_eq!(rec.t, 2.0);
+        assert_eq!(rec.p.to_array(), [0.0, 0.0, -1.0]);
+        assert_eq!(rec.normal.to_array(), [0.0, 0.0, -1.0]);
+        assert!(rec.front_face);
+    }
+
+    #[test]
+    fn hit_from_inside() {
+        let rng = &mut SmallRng::seed_from_u64(0);
+        let r = Ray::new(Point3::origin(), vec3!(0.0, 0.0, 1.0), 0.0);
+        let rec = unit_sphere().hit(rng, &r, 0.001, f32::INFINITY).unwrap();
+
+        assert_eq!(rec.t, 1.0);
+        assert_eq!(rec.normal.to_array(), [0.0, 0.0, -1.0]);
+        assert!(!rec.front_face);
+    }
+
+    #[test]
+    fn sampled_from_inside_and_on_surface() {
+        let rng = &mut SmallRng::seed_from_u64(0);
+        let sphere = unit_sphere();
+        for &o in &[point!(0.3, 0.0, 0.2), point!(0.0, 1.0, 0.0)] {
+            for _ in 0..100 {
+                let direction = sphere.random(rng, o);
+                let pdf = sphere.pdf_value(rng, o, direction);
+                assert!(pdf.is_finite() && pdf > 0.0, "{:?} {}", o, pdf);
+            }
+        }
+    }
+
+    #[test]
+    fn hit_interval() {
+        let rng = &mut SmallRng::seed_from_u64(0);
+        let r = Ray::new(point!(0.0, 0.0, -5.0), vec3!(0.0, 0.0, 1.0), 0.0);
+
+        // Near root outside the interval falls back to the far one
+        let rec = unit_sphere().hit(rng, &r, 4.5, f32::INFINITY).unwrap();
+        assert_eq!(rec.t, 6.0);
+        assert!(!rec.front_face);
+
+        assert!(unit_sphere().hit(rng, &r, 0.001, 3.9).is_none());
+        assert!(unit_sphere().hit(rng, &r, 6.1, f32::INFINITY).is_none());
+
+        let miss = Ray::new(point!(0.0, 1.01, -5.0), vec3!(0.0, 0.0, 1.0), 0.0);
+        assert!(unit_sphere()
+            .hit(rng, &miss, 0.001, f32::INFINITY)
+            .is_none());
+    }
 }
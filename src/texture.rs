@@ -53,9 +53,9 @@ pub struct NoiseTexture {
 }
 
 impl NoiseTexture {
-    pub fn new(scale: f32) -> Arc<Self> {
+    pub fn new<R: rand::Rng + ?Sized>(rng: &mut R, scale: f32) -> Arc<Self> {
         Arc::new(Self {
-            noise: Perlin::new(),
+            noise: Perlin::new(rng),
             scale,
         })
     }
@@ -78,18 +78,22 @@ impl ImageTexture {
     pub fn new(filename: impl AsRef<str>) -> Arc<Self> {
         let filename = filename.as_ref();
 
-        let image = image::open(filename)
-            .unwrap_or_else(|_| panic!("ERROR: Could not load texture image file: {}", filename));
+        Self::open(filename)
+            .unwrap_or_else(|_| panic!("ERROR: Could not load texture image file: {}", filename))
+    }
+
+    pub fn open(path: impl AsRef<std::path::Path>) -> image::ImageResult<Arc<Self>> {
+        let image = image::open(path)?;
 
-        Arc::new(Self { image })
+        Ok(Arc::new(Self { image }))
     }
 }
 
 impl Texture for ImageTexture {
     fn value(&self, u: f32, v: f32, _p: Point3) -> Color {
         let (width, height) = self.image.dimensions();
-        let u = u.max(0.0).min(1.0);
-        let v = 1.0 - v.max(0.0).min(1.0);
+        let u = u.clamp(0.0, 1.0);
+        let v = 1.0 - v.clamp(0.0, 1.0);
 
         let mut i = (u * width as f32) as u32;
         let mut j = (v * height as f32) as u32;
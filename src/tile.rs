@@ -0,0 +1,368 @@
+use serde::{Deserialize, Serialize};
+use std::{cmp::Ordering, fmt::Display, str::FromStr};
+
+/// A rectangle of pixels, with rows counted from the top of the image.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PixelRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl PixelRect {
+    pub fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// The whole of a `width`x`height` image.
+    pub fn full(width: u32, height: u32) -> Self {
+        Self::new(0, 0, width, height)
+    }
+
+    pub fn area(&self) -> u32 {
+        self.width * self.height
+    }
+
+    pub fn contains(&self, x: u32, y: u32) -> bool {
+        (self.x..self.x + self.width).contains(&x) && (self.y..self.y + self.height).contains(&y)
+    }
+
+    /// The overlap of both rectangles, if any.
+    pub fn intersect(&self, other: &Self) -> Option<Self> {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let x1 = (self.x + self.width).min(other.x + other.width);
+        let y1 = (self.y + self.height).min(other.y + other.height);
+
+        if x < x1 && y < y1 {
+            Some(Self::new(x, y, x1 - x, y1 - y))
+        } else {
+            None
+        }
+    }
+
+    /// Pixel coordinates, row by row.
+    pub fn pixels(&self) -> impl Iterator<Item = (u32, u32)> {
+        let Self {
+            x,
+            y,
+            width,
+            height,
+        } = *self;
+        (y..y + height).flat_map(move |y| (x..x + width).map(move |x| (x, y)))
+    }
+}
+
+/// A region of interest, with corners counted from the top left of the image. The lower corner
+/// is inclusive and the upper one exclusive.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum CropWindow {
+    Pixels {
+        min: (u32, u32),
+        max: (u32, u32),
+    },
+    /// As fractions of the image size, so it does not depend on the resolution
+    Normalized {
+        min: (f32, f32),
+        max: (f32, f32),
+    },
+}
+
+impl CropWindow {
+    /// The pixels covered in a `width`x`height` image, which may be none.
+    pub fn to_pixels(&self, width: u32, height: u32) -> Option<PixelRect> {
+        let (min, max) = match *self {
+            CropWindow::Pixels { min, max } => (min, max),
+            CropWindow::Normalized { min, max } => {
+                let scale = |t: f32, size: u32| (t * size as f32).max(0.0);
+                (
+                    (
+                        scale(min.0, width).floor() as u32,
+                        scale(min.1, height).floor() as u32,
+                    ),
+                    (
+                        scale(max.0, width).ceil() as u32,
+                        scale(max.1, height).ceil() as u32,
+                    ),
+                )
+            }
+        };
+
+        let rect = PixelRect::new(
+            min.0,
+            min.1,
+            max.0.saturating_sub(min.0),
+            max.1.saturating_sub(min.1),
+        );
+        rect.intersect(&PixelRect::full(width, height))
+    }
+}
+
+/// The order in which tiles are handed out to render threads. It only affects how the image
+/// fills in, not the result.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TileOrder {
+    /// Row by row from the top
+    Scanline,
+    /// Outwards from the center, where the subject usually is
+    #[default]
+    Spiral,
+    /// Along a Hilbert curve, so consecutive tiles are neighbors
+    Hilbert,
+}
+
+#[derive(Debug)]
+pub struct UnknownTileOrder(String);
+
+impl Display for UnknownTileOrder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "unknown tile order `{}`: expected one of scanline, spiral, hilbert",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for UnknownTileOrder {}
+
+impl FromStr for TileOrder {
+    type Err = UnknownTileOrder;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "scanline" => Ok(TileOrder::Scanline),
+            "spiral" => Ok(TileOrder::Spiral),
+            "hilbert" => Ok(TileOrder::Hilbert),
+            _ => Err(UnknownTileOrder(s.to_owned())),
+        }
+    }
+}
+
+/// One of `count` interleaved shares of the tiles of an image, for splitting a render between
+/// processes. Written as `index/count`, counting from 1.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TileShare {
+    /// Counted from 0
+    pub index: u32,
+    pub count: u32,
+}
+
+impl TileShare {
+    /// Whether the `n`th tile in scanline order belongs to this share.
+    pub fn contains(&self, n: u32) -> bool {
+        n % self.count == self.index
+    }
+}
+
+impl Display for TileShare {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.index + 1, self.count)
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidTileShare(String);
+
+impl Display for InvalidTileShare {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "invalid tile share `{}`: expected `k/n` with 1 <= k <= n",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for InvalidTileShare {}
+
+impl FromStr for TileShare {
+    type Err = InvalidTileShare;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || InvalidTileShare(s.to_owned());
+        let (k, n) = s.split_once('/').ok_or_else(err)?;
+        let k: u32 = k.trim().parse().map_err(|_| err())?;
+        let n: u32 = n.trim().parse().map_err(|_| err())?;
+
+        if 1 <= k && k <= n {
+            Ok(Self {
+                index: k - 1,
+                count: n,
+            })
+        } else {
+            Err(err())
+        }
+    }
+}
+
+/// Splits `region` into tiles of at most `size`x`size` pixels, in `order`. With a `share`,
+/// only the tiles of that share are kept.
+pub fn tiles(
+    region: PixelRect,
+    size: u32,
+    order: TileOrder,
+    share: Option<TileShare>,
+) -> Vec<PixelRect> {
+    assert!(size > 0);
+
+    let nx = region.width.div_ceil(size);
+    let ny = region.height.div_ceil(size);
+    let mut coords: Vec<(u32, u32)> = (0..ny)
+        .flat_map(|ty| (0..nx).map(move |tx| (tx, ty)))
+        .filter(|&(tx, ty)| match share {
+            Some(share) => share.contains(ty * nx + tx),
+            None => true,
+        })
+        .collect();
+
+    match order {
+        TileOrder::Scanline => {}
+        TileOrder::Spiral => {
+            // Ring by ring around the center, each ring in order of angle
+            let (cx, cy) = ((nx as f32 - 1.0) / 2.0, (ny as f32 - 1.0) / 2.0);
+            let key = |&(tx, ty): &(u32, u32)| {
+                let (dx, dy) = (tx as f32 - cx, ty as f32 - cy);
+                (dx.abs().max(dy.abs()), dy.atan2(dx))
+            };
+            coords.sort_by(|a, b| key(a).partial_cmp(&key(b)).unwrap_or(Ordering::Equal));
+        }
+        TileOrder::Hilbert => {
+            let n = nx.max(ny).next_power_of_two();
+            coords.sort_by_key(|&(tx, ty)| hilbert_index(n, tx, ty));
+        }
+    }
+
+    coords
+        .into_iter()
+        .map(|(tx, ty)| {
+            let x = region.x + tx * size;
+            let y = region.y + ty * size;
+            PixelRect::new(
+                x,
+                y,
+                size.min(region.x + region.width - x),
+                size.min(region.y + region.height - y),
+            )
+        })
+        .collect()
+}
+
+/// Distance of `(x, y)` along the Hilbert curve filling an `n`x`n` grid, `n` a power of two.
+fn hilbert_index(n: u32, mut x: u32, mut y: u32) -> u64 {
+    let mut d = 0;
+    let mut s = n / 2;
+    while s > 0 {
+        let rx = (x & s > 0) as u64;
+        let ry = (y & s > 0) as u64;
+        d += s as u64 * s as u64 * ((3 * rx) ^ ry);
+
+        // Rotate the quadrant so the curve inside it starts and ends at the right corners
+        if ry == 0 {
+            if rx == 1 {
+                x = n - 1 - x;
+                y = n - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+    d
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tiles_cover_region() {
+        let region = PixelRect::new(3, 5, 70, 45);
+        for &order in &[TileOrder::Scanline, TileOrder::Spiral, TileOrder::Hilbert] {
+            let tiles = tiles(region, 16, order, None);
+            assert_eq!(tiles.len(), 5 * 3, "{:?}", order);
+
+            let mut covered = vec![0; (80 * 50) as usize];
+            for tile in &tiles {
+                assert_eq!(tile.intersect(&region), Some(*tile), "{:?}", order);
+                for (x, y) in tile.pixels() {
+                    covered[(y * 80 + x) as usize] += 1;
+                }
+            }
+            for (x, y) in PixelRect::full(80, 50).pixels() {
+                let expected = region.contains(x, y) as u32;
+                assert_eq!(covered[(y * 80 + x) as usize], expected, "{:?}", order);
+            }
+        }
+    }
+
+    #[test]
+    fn crop_window() {
+        let pixels = CropWindow::Pixels {
+            min: (10, 20),
+            max: (30, 25),
+        };
+        assert_eq!(
+            pixels.to_pixels(100, 50),
+            Some(PixelRect::new(10, 20, 20, 5))
+        );
+        assert_eq!(
+            pixels.to_pixels(20, 50),
+            Some(PixelRect::new(10, 20, 10, 5))
+        );
+        assert_eq!(pixels.to_pixels(10, 50), None);
+
+        // Rounded outwards to whole pixels
+        let normalized = CropWindow::Normalized {
+            min: (0.25, 0.5),
+            max: (0.505, 1.0),
+        };
+        assert_eq!(
+            normalized.to_pixels(100, 50),
+            Some(PixelRect::new(25, 25, 26, 25))
+        );
+    }
+
+    #[test]
+    fn orders() {
+        let region = PixelRect::full(64, 64);
+
+        let spiral = tiles(region, 16, TileOrder::Spiral, None);
+        let first = spiral[0];
+        assert!(first.contains(24, 24) || first.contains(40, 40));
+        let last = spiral.last().unwrap();
+        assert!(last.x == 0 || last.x == 48 || last.y == 0 || last.y == 48);
+
+        // Consecutive tiles along the Hilbert curve share an edge
+        let hilbert = tiles(region, 16, TileOrder::Hilbert, None);
+        for pair in hilbert.windows(2) {
+            let dx = (pair[0].x as i32 - pair[1].x as i32).abs();
+            let dy = (pair[0].y as i32 - pair[1].y as i32).abs();
+            assert_eq!(dx + dy, 16);
+        }
+    }
+
+    #[test]
+    fn shares_partition_tiles() {
+        let region = PixelRect::full(100, 70);
+        let all = tiles(region, 16, TileOrder::Scanline, None);
+
+        let mut shared: Vec<PixelRect> = (1..=3)
+            .flat_map(|k| {
+                let share: TileShare = format!("{}/3", k).parse().unwrap();
+                tiles(region, 16, TileOrder::Hilbert, Some(share))
+            })
+            .collect();
+        shared.sort_by_key(|tile| (tile.y, tile.x));
+        assert_eq!(shared, all);
+
+        assert!("0/3".parse::<TileShare>().is_err());
+        assert!("4/3".parse::<TileShare>().is_err());
+        assert_eq!("2/3".parse::<TileShare>().unwrap().to_string(), "2/3");
+    }
+}
@@ -0,0 +1,467 @@
+use crate::prelude::*;
+
+use crate::{bvh::*, hittable_list::*};
+
+use rand::Rng;
+use std::{fmt::Debug, sync::Arc};
+
+/// Vertex data shared by all triangles of a mesh. `normals` and `uvs` are either empty or
+/// have one entry per position.
+pub struct MeshData {
+    positions: Vec<Point3>,
+    normals: Vec<Vec3>,
+    uvs: Vec<(f32, f32)>,
+    indices: Vec<[u32; 3]>,
+    mat_ptr: Arc<dyn Material>,
+}
+
+impl MeshData {
+    pub fn new(
+        positions: Vec<Point3>,
+        normals: Vec<Vec3>,
+        uvs: Vec<(f32, f32)>,
+        indices: Vec<[u32; 3]>,
+        mat_ptr: Arc<dyn Material>,
+    ) -> Self {
+        assert!(normals.is_empty() || normals.len() == positions.len());
+        assert!(uvs.is_empty() || uvs.len() == positions.len());
+        assert!(indices
+            .iter()
+            .flatten()
+            .all(|&i| (i as usize) < positions.len()));
+
+        Self {
+            positions,
+            normals,
+            uvs,
+            indices,
+            mat_ptr,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.indices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+
+    #[inline]
+    fn vertices(&self, index: usize) -> [Point3; 3] {
+        let [a, b, c] = self.indices[index];
+        [
+            self.positions[a as usize],
+            self.positions[b as usize],
+            self.positions[c as usize],
+        ]
+    }
+
+    fn area(&self, index: usize) -> f32 {
+        let [p0, p1, p2] = self.vertices(index);
+        0.5 * (p1 - p0).cross(p2 - p0).length()
+    }
+
+    /// Texture coordinates at barycentric coordinates `b1` and `b2` of a triangle, which are
+    /// those themselves without per-vertex ones.
+    fn uv(&self, index: usize, b1: f32, b2: f32) -> (f32, f32) {
+        if self.uvs.is_empty() {
+            return (b1, b2);
+        }
+        let b0 = 1.0 - b1 - b2;
+        let [i0, i1, i2] = self.indices[index];
+        let (uv0, uv1, uv2) = (
+            self.uvs[i0 as usize],
+            self.uvs[i1 as usize],
+            self.uvs[i2 as usize],
+        );
+        (
+            b0 * uv0.0 + b1 * uv1.0 + b2 * uv2.0,
+            b0 * uv0.1 + b1 * uv1.1 + b2 * uv2.1,
+        )
+    }
+}
+
+impl Debug for MeshData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MeshData")
+            .field("vertices", &self.positions.len())
+            .field("triangles", &self.indices.len())
+            .field("mat_ptr", &self.mat_ptr)
+            .finish()
+    }
+}
+
+// Triangle
+#[derive(Debug)]
+pub struct Triangle {
+    mesh: Arc<MeshData>,
+    index: usize,
+}
+
+impl Triangle {
+    /// A point uniformly distributed on the triangle, with its barycentric coordinates `b1`
+    /// and `b2`.
+    fn sample_point(&self, rng: &mut dyn rand::RngCore) -> (Point3, f32, f32) {
+        let [p0, p1, p2] = self.mesh.vertices(self.index);
+        let s = rng.gen::<f32>().sqrt();
+        let b1 = rng.gen::<f32>() * s;
+        let b0 = 1.0 - s;
+        let b2 = 1.0 - b0 - b1;
+
+        (b0 * p0 + b1 * p1 + b2 * p2, b1, b2)
+    }
+
+    pub fn new(p0: Point3, p1: Point3, p2: Point3, mat_ptr: Arc<dyn Material>) -> Arc<Self> {
+        let mesh = MeshData::new(vec![p0, p1, p2], vec![], vec![], vec![[0, 1, 2]], mat_ptr);
+
+        Arc::new(Self {
+            mesh: Arc::new(mesh),
+            index: 0,
+        })
+    }
+}
+
+impl Hittable for Triangle {
+    fn hit(
+        &self,
+        _rng: &mut dyn rand::RngCore,
+        r: &Ray,
+        t_min: f32,
+        t_max: f32,
+    ) -> Option<HitRecord> {
+        // Möller–Trumbore
+        let [p0, p1, p2] = self.mesh.vertices(self.index);
+        let e1 = p1 - p0;
+        let e2 = p2 - p0;
+
+        let pvec = r.direction().cross(e2);
+        let det = e1.dot(pvec);
+        // Parallel to the plane, up to rounding relative to the size of the triangle and the ray
+        let scale = e1.length_squared() * e2.length_squared() * r.direction().length_squared();
+        if det * det <= f32::EPSILON * f32::EPSILON * scale {
+            return None;
+        }
+        let inv_det = 1.0 / det;
+
+        let tvec = r.origin() - p0;
+        let b1 = tvec.dot(pvec) * inv_det;
+        if !(0.0..=1.0).contains(&b1) {
+            return None;
+        }
+
+        let qvec = tvec.cross(e1);
+        let b2 = r.direction().dot(qvec) * inv_det;
+        if b2 < 0.0 || b1 + b2 > 1.0 {
+            return None;
+        }
+
+        let t = e2.dot(qvec) * inv_det;
+        if t < t_min || t > t_max {
+            return None;
+        }
+
+        let (u, v) = self.mesh.uv(self.index, b1, b2);
+        let mut rec = HitRecord::new(
+            r,
+            e1.cross(e2).unit_vector(),
+            r.at(t),
+            t,
+            u,
+            v,
+            self.mesh.mat_ptr.clone(),
+        );
+
+        // Shade with the interpolated normal, kept on the side of the geometric one
+        if !self.mesh.normals.is_empty() {
+            let b0 = 1.0 - b1 - b2;
+            let [i0, i1, i2] = self.mesh.indices[self.index];
+            let normal = (b0 * self.mesh.normals[i0 as usize]
+                + b1 * self.mesh.normals[i1 as usize]
+                + b2 * self.mesh.normals[i2 as usize])
+                .unit_vector();
+            rec.normal = if rec.front_face { normal } else { -normal };
+        }
+
+        Some(rec)
+    }
+
+    fn bounding_box(&self, _t0: f32, _t1: f32) -> Option<AABB> {
+        let [p0, p1, p2] = self.mesh.vertices(self.index);
+        let padding = Vec3::from_scalar(0.0001);
+
+        Some(AABB::new(
+            p0.min(p1).min(p2) - padding,
+            p0.max(p1).max(p2) + padding,
+        ))
+    }
+
+    fn pdf_value(&self, rng: &mut dyn rand::RngCore, o: Point3, v: Vec3) -> f32 {
+        if let Some(rec) = self.hit(rng, &Ray::new(o, v, 0.0), 0.001, f32::INFINITY) {
+            let area = self.mesh.area(self.index);
+            let distance_squared = rec.t * rec.t * v.length_squared();
+            let cos = (v.dot(rec.geometric_normal) / v.length()).abs();
+
+            distance_squared / (cos * area)
+        } else {
+            0.0
+        }
+    }
+
+    fn random(&self, rng: &mut dyn rand::RngCore, o: Vec3) -> Vec3 {
+        let (p, _, _) = self.sample_point(rng);
+        p - o
+    }
+
+    fn area(&self) -> f32 {
+        self.mesh.area(self.index)
+    }
+
+    /// The record has the geometric normal, which emission and light sampling go by.
+    fn sample_surface(&self, rng: &mut dyn rand::RngCore) -> Option<HitRecord> {
+        let [p0, p1, p2] = self.mesh.vertices(self.index);
+        let normal = (p1 - p0).cross(p2 - p0).unit_vector();
+        let (p, b1, b2) = self.sample_point(rng);
+        let (u, v) = self.mesh.uv(self.index, b1, b2);
+        Some(HitRecord {
+            p,
+            normal,
+            geometric_normal: normal,
+            t: 0.0,
+            u,
+            v,
+            front_face: true,
+            mat_ptr: self.mesh.mat_ptr.clone(),
+        })
+    }
+}
+
+// Triangle mesh
+#[derive(Debug)]
+pub struct TriangleMesh {
+    mesh: Arc<MeshData>,
+    bvh: Arc<FlatBVH>,
+    /// Running sum of triangle areas, for sampling a point uniformly on the surface.
+    area_cdf: Vec<f32>,
+}
+
+impl TriangleMesh {
+    pub fn new(mesh: MeshData) -> Arc<Self> {
+        assert!(!mesh.is_empty(), "No triangle provided");
+
+        let mesh = Arc::new(mesh);
+        let mut triangles = HittableList::new();
+        let mut area_cdf = Vec::with_capacity(mesh.len());
+        let mut area = 0.0;
+        for index in 0..mesh.len() {
+            triangles.add(Arc::new(Triangle {
+                mesh: mesh.clone(),
+                index,
+            }));
+            area += mesh.area(index);
+            area_cdf.push(area);
+        }
+
+        Arc::new(Self {
+            bvh: FlatBVH::new_with_list(triangles, 0.0, 1.0),
+            mesh,
+            area_cdf,
+        })
+    }
+
+    /// One of the triangles, picked in proportion to its area.
+    fn pick_triangle(&self, rng: &mut dyn rand::RngCore) -> Triangle {
+        let target = rng.gen::<f32>() * self.area();
+        let index = self
+            .area_cdf
+            .partition_point(|&x| x < target)
+            .min(self.mesh.len() - 1);
+
+        Triangle {
+            mesh: self.mesh.clone(),
+            index,
+        }
+    }
+}
+
+impl Hittable for TriangleMesh {
+    fn hit(
+        &self,
+        rng: &mut dyn rand::RngCore,
+        r: &Ray,
+        t_min: f32,
+        t_max: f32,
+    ) -> Option<HitRecord> {
+        self.bvh.hit(rng, r, t_min, t_max)
+    }
+
+    fn bounding_box(&self, t0: f32, t1: f32) -> Option<AABB> {
+        self.bvh.bounding_box(t0, t1)
+    }
+
+    /// Only accounts for the closest surface along `v`, so it is exact for meshes that do not
+    /// occlude themselves as seen from `o`.
+    fn pdf_value(&self, rng: &mut dyn rand::RngCore, o: Point3, v: Vec3) -> f32 {
+        if let Some(rec) = self.hit(rng, &Ray::new(o, v, 0.0), 0.001, f32::INFINITY) {
+            let distance_squared = rec.t * rec.t * v.length_squared();
+            let cos = (v.dot(rec.geometric_normal) / v.length()).abs();
+
+            distance_squared / (cos * self.area())
+        } else {
+            0.0
+        }
+    }
+
+    fn random(&self, rng: &mut dyn rand::RngCore, o: Vec3) -> Vec3 {
+        self.pick_triangle(rng).random(rng, o)
+    }
+
+    fn area(&self) -> f32 {
+        *self.area_cdf.last().unwrap()
+    }
+
+    fn sample_surface(&self, rng: &mut dyn rand::RngCore) -> Option<HitRecord> {
+        self.pick_triangle(rng).sample_surface(rng)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    fn triangle() -> Arc<Triangle> {
+        Triangle::new(
+            point!(0.0, 0.0, 0.0),
+            point!(1.0, 0.0, 0.0),
+            point!(0.0, 1.0, 0.0),
+            Lambertian::new_rgb(0.5, 0.5, 0.5),
+        )
+    }
+
+    #[test]
+    fn hit() {
+        let rng = &mut SmallRng::seed_from_u64(0);
+        let r = Ray::new(point!(0.25, 0.25, 1.0), vec3!(0.0, 0.0, -1.0), 0.0);
+        let rec = triangle().hit(rng, &r, 0.001, f32::INFINITY).unwrap();
+
+        assert!((rec.t - 1.0).abs() < 1e-6);
+        assert!(rec.front_face);
+        assert_eq!(rec.normal.to_array(), [0.0, 0.0, 1.0]);
+        assert_eq!((rec.u, rec.v), (0.25, 0.25));
+
+        let back = Ray::new(point!(0.25, 0.25, -1.0), vec3!(0.0, 0.0, 1.0), 0.0);
+        let rec = triangle().hit(rng, &back, 0.001, f32::INFINITY).unwrap();
+        assert!(!rec.front_face);
+        assert_eq!(rec.normal.to_array(), [0.0, 0.0, -1.0]);
+    }
+
+    #[test]
+    fn miss() {
+        let rng = &mut SmallRng::seed_from_u64(0);
+        let outside = Ray::new(point!(0.75, 0.75, 1.0), vec3!(0.0, 0.0, -1.0), 0.0);
+        assert!(triangle()
+            .hit(rng, &outside, 0.001, f32::INFINITY)
+            .is_none());
+
+        let parallel = Ray::new(point!(-1.0, 0.25, 0.0), vec3!(1.0, 0.0, 0.0), 0.0);
+        assert!(triangle()
+            .hit(rng, &parallel, 0.001, f32::INFINITY)
+            .is_none());
+
+        let behind = Ray::new(point!(0.25, 0.25, 1.0), vec3!(0.0, 0.0, 1.0), 0.0);
+        assert!(triangle().hit(rng, &behind, 0.001, f32::INFINITY).is_none());
+    }
+
+    #[test]
+    fn small_triangles() {
+        // A millimeter across, still hit by a ray at a grazing angle
+        let rng = &mut SmallRng::seed_from_u64(0);
+        let triangle = Triangle::new(
+            point!(0.0, 0.0, 0.0),
+            point!(0.001, 0.0, 0.0),
+            point!(0.0, 0.001, 0.0),
+            Lambertian::new_rgb(0.5, 0.5, 0.5),
+        );
+        let r = Ray::new(
+            point!(-0.4998, 0.000_25, 0.025),
+            vec3!(1.0, 0.0, -0.05),
+            0.0,
+        );
+        let rec = triangle.hit(rng, &r, 0.001, f32::INFINITY).unwrap();
+        assert!((rec.p - point!(0.000_2, 0.000_25, 0.0)).length() < 1e-6);
+    }
+
+    #[test]
+    fn interpolated_attributes() {
+        let rng = &mut SmallRng::seed_from_u64(0);
+        let mesh = MeshData::new(
+            vec![
+                point!(0.0, 0.0, 0.0),
+                point!(1.0, 0.0, 0.0),
+                point!(0.0, 1.0, 0.0),
+            ],
+            vec![
+                vec3!(0.0, 0.0, 1.0),
+                vec3!(1.0, 0.0, 0.0),
+                vec3!(0.0, 0.0, 1.0),
+            ],
+            vec![(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)],
+            vec![[0, 1, 2]],
+            Lambertian::new_rgb(0.5, 0.5, 0.5),
+        );
+        let mesh = TriangleMesh::new(mesh);
+
+        let r = Ray::new(point!(0.5, 0.0, 1.0), vec3!(0.0, 0.0, -1.0), 0.0);
+        let rec = mesh.hit(rng, &r, 0.001, f32::INFINITY).unwrap();
+        let half = 0.5f32.sqrt();
+        assert!((rec.normal - Vec3::new(half, 0.0, half)).length() < 1e-5);
+        assert_eq!((rec.u, rec.v), (0.5, 0.0));
+        assert_eq!(mesh.area(), 0.5);
+
+        // Light sampling converts from area to solid angle with the geometric normal, as the
+        // shading one says nothing about the area seen from `o`
+        let o = point!(0.25, 0.25, 2.0);
+        let pdf = mesh.pdf_value(rng, o, vec3!(0.0, 0.0, -1.0));
+        assert!((pdf - 4.0 / 0.5).abs() < 1e-4, "{}", pdf);
+    }
+
+    #[test]
+    fn surface_samples_lie_on_mesh() {
+        let rng = &mut SmallRng::seed_from_u64(0);
+        // A tent of two triangles, one three times the area of the other
+        let mesh = TriangleMesh::new(MeshData::new(
+            vec![
+                point!(0.0, 0.0, 0.0),
+                point!(1.0, 0.0, 0.0),
+                point!(0.0, 1.0, 1.0),
+                point!(0.0, -3.0, 3.0),
+            ],
+            vec![],
+            vec![],
+            vec![[0, 1, 2], [1, 0, 3]],
+            Lambertian::new_rgb(0.5, 0.5, 0.5),
+        ));
+        let half = 0.5f32.sqrt();
+        assert!((mesh.area() - 4.0 * half).abs() < 1e-5);
+
+        const N: usize = 10_000;
+        let mut first = 0;
+        for _ in 0..N {
+            let rec = mesh.sample_surface(rng).unwrap();
+            assert!(rec.front_face);
+            assert_eq!(rec.normal, rec.geometric_normal);
+
+            // The point is where a ray along the normal meets the mesh
+            let r = Ray::new(rec.p + 0.01 * rec.normal, -rec.normal, 0.0);
+            let hit = mesh.hit(rng, &r, 0.001, f32::INFINITY).unwrap();
+            assert!((hit.p - rec.p).length() < 1e-4);
+            assert!((hit.normal - rec.normal).length() < 1e-5);
+            if rec.normal.y() < 0.0 {
+                first += 1;
+            }
+        }
+        assert!((first as f32 / N as f32 - 0.25).abs() < 0.02);
+    }
+}
@@ -1,16 +1,19 @@
+#[macro_export]
 macro_rules! point {
     ($x:expr, $y:expr, $z:expr) => {
-        Vec3::new($x, $y, $z)
+        $crate::vec3::Vec3::new($x, $y, $z)
     };
 }
+#[macro_export]
 macro_rules! rgb {
     ($x:literal, $y:literal, $z:literal) => {
-        Vec3::new($x, $y, $z)
+        $crate::vec3::Vec3::new($x, $y, $z)
     };
 }
+#[macro_export]
 macro_rules! vec3 {
     ($x:literal, $y:literal, $z:literal) => {
-        Vec3::new($x, $y, $z)
+        $crate::vec3::Vec3::new($x, $y, $z)
     };
 }
 
@@ -18,3 +21,19 @@ macro_rules! vec3 {
 pub fn clamp(x: f32, min: f32, max: f32) -> f32 {
     x.max(min).min(max)
 }
+
+/// Derives an independent seed for `stream` (e.g. a pixel index) from a base seed.
+#[inline]
+pub fn mix_seed(seed: u64, stream: u64) -> u64 {
+    // SplitMix64 finalizer
+    let mut z = seed ^ stream.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// Element `index` of the golden ratio sequence in `[0, 1)` starting at `start`.
+#[inline]
+pub fn golden_sequence(start: f64, index: u32) -> f32 {
+    (start + index as f64 * 0.618_033_988_749_894_9).fract() as f32
+}
@@ -56,7 +56,7 @@ impl Vec3 {
         unsafe { _mm_cvtss_f32(_mm_shuffle_ps(self.e, self.e, mm_shuffle(2, 2, 2, 2))) }
     }
 
-    pub fn to_array(&self) -> [f32; 3] {
+    pub fn to_array(self) -> [f32; 3] {
         let mut array = [0.0; 4];
         unsafe { _mm_storeu_ps(array.as_mut_ptr(), self.e) }
         [array[0], array[1], array[2]]
@@ -279,6 +279,19 @@ impl Vec3 {
     }
 }
 
+impl serde::Serialize for Vec3 {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        (self.x(), self.y(), self.z()).serialize(serializer)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Vec3 {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let (x, y, z) = serde::Deserialize::deserialize(deserializer)?;
+        Ok(Self::new(x, y, z))
+    }
+}
+
 impl std::fmt::Display for Vec3 {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "[{}, {}, {}]", self.x(), self.y(), self.z())
@@ -296,6 +309,16 @@ pub fn random_in_unit_sphere<R: rand::Rng + ?Sized>(rng: &mut R) -> Vec3 {
     }
 }
 
+/// A direction picked uniformly over the unit sphere.
+pub fn random_unit_vector<R: rand::Rng + ?Sized>(rng: &mut R) -> Vec3 {
+    let dist = rand::distributions::Uniform::new(0.0, 1.0);
+    let z: f32 = 1.0 - 2.0 * rng.sample(dist);
+    let phi = 2.0 * PI * rng.sample(dist);
+    let r = (1.0 - z * z).max(0.0).sqrt();
+
+    Vec3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
 pub fn random_in_unit_disk<R: rand::Rng + ?Sized>(rng: &mut R) -> Vec3 {
     let dist = rand::distributions::Uniform::new(-1.0, 1.0);
     loop {
@@ -328,7 +351,7 @@ pub fn random_to_sphere<R: rand::Rng + ?Sized>(
     let dist = rand::distributions::Uniform::new(0.0, 1.0);
     let r1: f32 = rng.sample(dist);
     let r2: f32 = rng.sample(dist);
-    let z = 1.0 + r2 * ((1.0 - radius * radius / distance_squared).sqrt() - 1.0);
+    let z = 1.0 + r2 * ((1.0 - radius * radius / distance_squared).max(0.0).sqrt() - 1.0);
 
     let phi = 2.0 * PI * r1;
     let x = phi.cos() * (1.0 - z * z).sqrt();
@@ -445,4 +468,26 @@ mod tests {
             [1.0, 1.0, 0.0]
         );
     }
+
+    #[test]
+    fn reflect_mirrors_normal_component() {
+        let v = Vec3::new(1.0, -1.0, 0.5);
+        let n = Vec3::new(0.0, 1.0, 0.0);
+        assert_eq!(reflect(v, n).to_array(), [1.0, 1.0, 0.5]);
+    }
+
+    #[test]
+    fn refract_follows_snell() {
+        let n = Vec3::new(0.0, 1.0, 0.0);
+        let uv = Vec3::new(0.6, -0.8, 0.0);
+
+        // Matched indices leave the direction unchanged
+        assert!((refract(uv, n, 1.0) - uv).length() < 1e-6);
+
+        let eta = 1.0 / 1.5;
+        let out = refract(uv, n, eta);
+        assert!((out.length() - 1.0).abs() < 1e-5);
+        assert!(out.y() < 0.0);
+        assert!((out.x() - eta * uv.x()).abs() < 1e-6);
+    }
 }
@@ -1,7 +1,8 @@
 use crate::prelude::*;
 
 use crate::{
-    aarect::*, bvh::*, constant_medium::*, cuboid::*, hittable_list::*, moving_sphere::*, sphere::*,
+    aarect::*, bvh::*, color::*, constant_medium::*, cuboid::*, hittable_list::*, moving_sphere::*,
+    onb::*, sphere::*,
 };
 
 use rand::prelude::*;
@@ -18,16 +19,91 @@ pub struct World {
 }
 
 impl World {
+    pub fn new(world: HittableList, lights: HittableList) -> Self {
+        Self { world, lights }
+    }
+
     pub fn world(&self) -> &HittableList {
         &self.world
     }
     pub fn lights(&self) -> &HittableList {
         &self.lights
     }
+
+    /// A point on a uniformly picked light, and its density per unit area.
+    pub fn sample_light_point(&self, rng: &mut dyn RngCore) -> Option<(HitRecord, f32)> {
+        let lights = &self.lights.objects;
+        if lights.is_empty() {
+            return None;
+        }
+
+        let light = &lights[rng.gen_range(0..lights.len())];
+        let area = light.area();
+        let rec = light.sample_surface(rng).filter(|_| area > 0.0)?;
+        Some((rec, 1.0 / (lights.len() as f32 * area)))
+    }
+
+    /// A cosine-distributed ray leaving a point from `sample_light_point`.
+    pub fn sample_emission(&self, rng: &mut dyn RngCore) -> Option<Emission> {
+        let (rec, pdf_pos) = self.sample_light_point(rng)?;
+        let outward = rec.geometric_normal;
+        let front = luminance(emitted_towards(&rec, outward));
+        let back = luminance(emitted_towards(&rec, -outward));
+        if front + back <= 0.0 {
+            return None;
+        }
+
+        let side = if rng.gen::<f32>() * (front + back) < front {
+            outward
+        } else {
+            -outward
+        };
+        let direction = OrthonormalBasis::from_w(side).local(random_cosine_direction(rng));
+        Some(Emission {
+            radiance: emitted_towards(&rec, direction),
+            pdf_dir: emission_pdf(&rec, direction),
+            rec,
+            pdf_pos,
+            direction,
+        })
+    }
+}
+
+/// A ray leaving a light, sampled by `World::sample_emission`.
+pub struct Emission {
+    /// Where on the light it starts
+    pub rec: HitRecord,
+    /// Density of the starting point per unit area
+    pub pdf_pos: f32,
+    pub direction: Vec3,
+    /// Density of the direction per solid angle
+    pub pdf_dir: f32,
+    /// Radiance along the ray
+    pub radiance: Color,
+}
+
+/// Radiance the light at `rec` emits towards `direction`.
+pub fn emitted_towards(rec: &HitRecord, direction: Vec3) -> Color {
+    rec.mat_ptr.emitted(&rec.facing(direction))
+}
+
+/// Density of `World::sample_emission` leaving `rec` in `direction`, per solid angle.
+pub fn emission_pdf(rec: &HitRecord, direction: Vec3) -> f32 {
+    let outward = rec.geometric_normal;
+    let front = luminance(emitted_towards(rec, outward));
+    let back = luminance(emitted_towards(rec, -outward));
+    if front + back <= 0.0 {
+        return 0.0;
+    }
+
+    let direction = direction.unit_vector();
+    let cos = direction.dot(outward);
+    let side = if cos > 0.0 { front } else { back };
+    side / (front + back) * cos.abs() / PI
 }
 
 impl World {
-    pub fn random_scene() -> Self {
+    pub fn random_scene(seed: u64) -> Self {
         let mut world = HittableList::new();
         let lights = HittableList::new();
 
@@ -39,7 +115,7 @@ impl World {
             ground_material,
         ));
 
-        let mut rng = SmallRng::from_entropy();
+        let mut rng = SmallRng::seed_from_u64(seed);
         let mut balls = HittableList::new();
         let dist = rand::distributions::Uniform::new(0.0, 1.0);
 
@@ -69,8 +145,8 @@ impl World {
                     } else if choose_mat < 0.95 {
                         // metal
                         let albedo = Color::random_with_bound(&mut rng, 0.5, 1.0);
-                        let fuzz = rng.gen_range(0.0..0.5);
-                        let sphere_material = Metal::new(albedo, fuzz);
+                        let roughness = rng.gen_range(0.0..0.5);
+                        let sphere_material = Metal::new(albedo, roughness);
                         let sphere = Sphere::new(center, 0.2, sphere_material);
 
                         balls.add(sphere);
@@ -84,7 +160,7 @@ impl World {
                 }
             }
         }
-        world.add(BVHNode::new_with_list(balls, 0.0, 1.0));
+        world.add(FlatBVH::new_with_list(balls, 0.0, 1.0));
 
         let glass = Dielectric::new(1.5);
         let sphere = Sphere::new(point!(0.0, 1.0, 0.0), 1.0, glass);
@@ -116,18 +192,19 @@ impl World {
             10.0,
             Lambertian::new(checker),
         ));
-        let bvh = BVHNode::new_with_list(world, 0.0, 1.0);
+        let bvh = FlatBVH::new_with_list(world, 0.0, 1.0);
         world = HittableList::new();
         world.add(bvh);
 
         Self { world, lights }
     }
 
-    pub fn two_perlin_spheres() -> Self {
+    pub fn two_perlin_spheres(seed: u64) -> Self {
+        let mut rng = SmallRng::seed_from_u64(seed);
         let mut world = HittableList::new();
         let lights = HittableList::new();
 
-        let pertext = NoiseTexture::new(4.0);
+        let pertext = NoiseTexture::new(&mut rng, 4.0);
 
         world.add(Sphere::new(
             point!(0.0, -1000.0, 0.0),
@@ -155,11 +232,12 @@ impl World {
         Self { world, lights }
     }
 
-    pub fn simple_light() -> Self {
+    pub fn simple_light(seed: u64) -> Self {
+        let mut rng = SmallRng::seed_from_u64(seed);
         let mut world = HittableList::new();
         let lights = HittableList::new();
 
-        let pertext = NoiseTexture::new(4.0);
+        let pertext = NoiseTexture::new(&mut rng, 4.0);
         world.add(Sphere::new(
             point!(0.0, -1000.0, 0.0),
             1000.0,
@@ -281,8 +359,8 @@ impl World {
         Self { world, lights }
     }
 
-    pub fn final_scene() -> Self {
-        let mut rng = SmallRng::from_entropy();
+    pub fn final_scene(seed: u64) -> Self {
+        let mut rng = SmallRng::seed_from_u64(seed);
         let mut world = HittableList::new();
         let mut lights = HittableList::new();
 
@@ -307,7 +385,7 @@ impl World {
                 ));
             }
         }
-        world.add(BVHNode::new_with_list(boxes1, 0.0, 1.0));
+        world.add(FlatBVH::new_with_list(boxes1, 0.0, 1.0));
 
         let light = DiffuseLight::white(7.0);
         let light = FlipFace::new(AARect::new(
@@ -336,7 +414,7 @@ impl World {
         let sphere = Sphere::new(
             point!(0.0, 150.0, 145.0),
             50.0,
-            Metal::new_rgbf(0.8, 0.8, 0.9, 10.0),
+            Metal::new_rgbf(0.8, 0.8, 0.9, 1.0),
         );
         add_lights(&mut world, &mut lights, sphere);
 
@@ -352,7 +430,7 @@ impl World {
 
         let emat = Lambertian::new(ImageTexture::new("assets/earthmap.jpg"));
         world.add(Sphere::new(point!(400.0, 200.0, 400.0), 100.0, emat));
-        let pertext = NoiseTexture::new(0.1);
+        let pertext = NoiseTexture::new(&mut rng, 0.1);
         world.add(Sphere::new(
             point!(200.0, 280.0, 300.0),
             80.0,
@@ -369,7 +447,7 @@ impl World {
             ));
         }
         world.add(Translate::new(
-            RotateY::new(BVHNode::new_with_list(boxes2, 0.0, 1.0), 15.0),
+            RotateY::new(FlatBVH::new_with_list(boxes2, 0.0, 1.0), 15.0),
             vec3!(-100.0, 270.0, 395.0),
         ));
 
@@ -0,0 +1,139 @@
+//! Splits renders between several processes and merges them back together, as on a farm.
+
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+const SCENE: [&str; 8] = [
+    "--scene",
+    "cornell_box",
+    "--height",
+    "24",
+    "--seed",
+    "5",
+    "--tile-size",
+    "8",
+];
+
+fn raytracer(args: &[&str]) {
+    let output = Command::new(env!("CARGO_BIN_EXE_raytracer"))
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "raytracer {}: {}",
+        args.join(" "),
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+fn render(args: &[&str]) {
+    let mut all = SCENE.to_vec();
+    all.extend_from_slice(args);
+    raytracer(&all);
+}
+
+/// Reads the pixels of a PFM written by the renderer.
+fn read_pfm(path: &Path) -> Vec<f32> {
+    let data = std::fs::read(path).unwrap();
+    let start = data.windows(5).position(|w| w == b"-1.0\n").unwrap() + 5;
+    data[start..]
+        .chunks(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect()
+}
+
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+
+    fn path(&self, file: &str) -> String {
+        self.0.join(file).to_str().unwrap().to_owned()
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+#[test]
+fn tile_shares() {
+    let dir = TempDir::new("tile-shares");
+    render(&["--spp", "6", "-o", &dir.path("full.pfm")]);
+
+    for k in 1..=3 {
+        let share = format!("{}/3", k);
+        render(&[
+            "--spp",
+            "6",
+            "--tiles",
+            &share,
+            "--checkpoint",
+            &dir.path(&format!("part{}.rtck", k)),
+            "-o",
+            &dir.path(&format!("part{}.png", k)),
+        ]);
+    }
+    raytracer(&[
+        "merge",
+        &dir.path("part1.rtck"),
+        &dir.path("part2.rtck"),
+        &dir.path("part3.rtck"),
+        "-o",
+        &dir.path("merged.pfm"),
+    ]);
+
+    // Each pixel comes from exactly one part
+    let full = read_pfm(dir.0.join("full.pfm").as_ref());
+    let merged = read_pfm(dir.0.join("merged.pfm").as_ref());
+    assert_eq!(full, merged);
+}
+
+#[test]
+fn sample_ranges() {
+    let dir = TempDir::new("sample-ranges");
+    render(&["--spp", "6", "-o", &dir.path("full.pfm")]);
+
+    render(&[
+        "--spp",
+        "2",
+        "--checkpoint",
+        &dir.path("first.rtck"),
+        "-o",
+        &dir.path("first.png"),
+    ]);
+    render(&[
+        "--spp",
+        "4",
+        "--spp-offset",
+        "2",
+        "--checkpoint",
+        &dir.path("second.rtck"),
+        "-o",
+        &dir.path("second.png"),
+    ]);
+    raytracer(&[
+        "merge",
+        &dir.path("first.rtck"),
+        &dir.path("second.rtck"),
+        "-o",
+        &dir.path("merged.pfm"),
+    ]);
+
+    // The same samples, up to the order they were summed in
+    let full = read_pfm(dir.0.join("full.pfm").as_ref());
+    let merged = read_pfm(dir.0.join("merged.pfm").as_ref());
+    assert_eq!(full.len(), merged.len());
+    for (a, b) in full.iter().zip(&merged) {
+        assert!((a - b).abs() <= 1e-5 * a.abs().max(1.0), "{} != {}", a, b);
+    }
+}
@@ -0,0 +1,152 @@
+//! Renders every built-in scene at low resolution with a fixed seed and compares it with the
+//! reference image in `tests/golden`.
+//!
+//! The comparison works on block averages of the 8-bit images converted back to linear color, so
+//! it tolerates the sampling noise of changes that keep the expected image, but not shifts in
+//! brightness, color or geometry. (Averaging the gamma-corrected values would darken noisier
+//! renders of the same image.) After an intended change of the expected image, regenerate the
+//! references with `UPDATE_GOLDEN=1 cargo test --release --test golden` and check them in.
+
+use raytracer::{render::*, scene::*};
+
+use image::RgbImage;
+use std::path::PathBuf;
+
+const HEIGHT: u32 = 48;
+const SEED: u64 = 1;
+
+/// Noisier scenes get more samples so that two renders agree within the tolerance.
+fn samples_per_pixel(name: &str) -> u32 {
+    match name {
+        "final_scene" => 256,
+        _ => 64,
+    }
+}
+
+/// Side of the square blocks that are averaged before comparing.
+const BLOCK: u32 = 4;
+/// Largest allowed mean difference over all blocks, in linear color.
+const MAX_MEAN_ERROR: f32 = 0.025;
+/// Largest allowed average signed difference per channel, which catches small but systematic
+/// shifts in brightness or color that are hidden in the noise of individual blocks.
+const MAX_BIAS: f32 = 0.006;
+/// A block is an outlier when any channel differs by more than this.
+const OUTLIER_ERROR: f32 = 0.12;
+/// Largest allowed fraction of outlier blocks.
+const MAX_OUTLIERS: f32 = 0.05;
+
+fn render(name: &str) -> RgbImage {
+    let mut scene = Scene::builtin(name, SEED).unwrap();
+    scene.image.image_height = HEIGHT;
+    let camera = scene.camera.build(scene.image.aspect_ratio);
+
+    let renderer = Renderer::new(RenderSettings {
+        samples_per_pixel: samples_per_pixel(name),
+        seed: SEED,
+        ..RenderSettings::for_scene(&scene)
+    });
+
+    renderer.render(&scene.world, &camera).to_rgb8()
+}
+
+/// Undoes the gamma of `color_to_rgb8`, taking the middle of the range each value stands for.
+fn linear(c: u8) -> f32 {
+    let c = (c as f32 + 0.5) / 256.0;
+    c * c
+}
+
+fn block_means(image: &RgbImage) -> Vec<[f32; 3]> {
+    let (width, height) = image.dimensions();
+    let mut means = Vec::new();
+    for by in 0..height / BLOCK {
+        for bx in 0..width / BLOCK {
+            let mut sum = [0.0; 3];
+            for y in by * BLOCK..(by + 1) * BLOCK {
+                for x in bx * BLOCK..(bx + 1) * BLOCK {
+                    for (s, &c) in sum.iter_mut().zip(image.get_pixel(x, y).0.iter()) {
+                        *s += linear(c);
+                    }
+                }
+            }
+            means.push(sum.map(|s| s / (BLOCK * BLOCK) as f32));
+        }
+    }
+    means
+}
+
+struct Comparison {
+    mean_error: f32,
+    bias: [f32; 3],
+    outliers: f32,
+}
+
+impl Comparison {
+    fn new(image: &RgbImage, reference: &RgbImage) -> Self {
+        let a = block_means(image);
+        let b = block_means(reference);
+        let n = a.len() as f32;
+
+        let mut total = 0.0;
+        let mut bias = [0.0; 3];
+        let mut outliers = 0;
+        for (a, b) in a.iter().zip(&b) {
+            let diff = [0, 1, 2].map(|c| a[c] - b[c]);
+            total += diff.iter().map(|d| d.abs()).sum::<f32>() / 3.0;
+            for (bias, d) in bias.iter_mut().zip(&diff) {
+                *bias += d;
+            }
+            if diff.iter().any(|d| d.abs() > OUTLIER_ERROR) {
+                outliers += 1;
+            }
+        }
+
+        Self {
+            mean_error: total / n,
+            bias: bias.map(|b| b / n),
+            outliers: outliers as f32 / n,
+        }
+    }
+
+    fn passes(&self) -> bool {
+        self.mean_error <= MAX_MEAN_ERROR
+            && self.bias.iter().all(|b| b.abs() <= MAX_BIAS)
+            && self.outliers <= MAX_OUTLIERS
+    }
+}
+
+#[test]
+fn builtin_scenes() {
+    let update = std::env::var_os("UPDATE_GOLDEN").is_some();
+    let mut failures = Vec::new();
+
+    for &name in BUILTIN_SCENES.iter() {
+        let image = render(name);
+        let path = PathBuf::from("tests/golden").join(format!("{}.png", name));
+
+        if update {
+            image.save(&path).unwrap();
+            continue;
+        }
+
+        let reference = image::open(&path)
+            .unwrap_or_else(|e| panic!("{}: {}", path.display(), e))
+            .to_rgb8();
+        assert_eq!(image.dimensions(), reference.dimensions(), "{}", name);
+
+        let comparison = Comparison::new(&image, &reference);
+        if !comparison.passes() {
+            let actual = path.with_extension("actual.png");
+            image.save(&actual).unwrap();
+            failures.push(format!(
+                "{}: mean error {:.4}, bias {:.4?}, {:.1}% outlier blocks (rendered to {})",
+                name,
+                comparison.mean_error,
+                comparison.bias,
+                comparison.outliers * 100.0,
+                actual.display()
+            ));
+        }
+    }
+
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}